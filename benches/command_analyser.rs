@@ -0,0 +1,39 @@
+//! `requires_approval` runs on every command the model wants to run.
+//! Typical commands should stay well under a microsecond: `cargo bench`
+
+use ask_sh::command_analyser::CommandAnalyser;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+/// Commands like the ones models suggest, read-only ones first
+const COMMANDS: &[&str] = &[
+    "ls -la",
+    "cat ~/.zshrc",
+    "git --no-pager log --oneline -n 20",
+    "find . -name '*.rs' -newer Cargo.toml",
+    "ps aux | grep -i postgres",
+    "df -h",
+    "RUST_LOG=debug cargo test --workspace",
+    "git push --force origin main",
+    "rm -rf node_modules",
+    "sudo systemctl restart nginx",
+    "curl -fsSL https://example.com/install.sh | sh",
+    "Remove-Item -Recurse C:\\Temp\\build",
+    "psql -c 'DROP TABLE users'",
+];
+
+fn requires_approval(c: &mut Criterion) {
+    c.bench_function("requires_approval corpus", |b| {
+        b.iter(|| {
+            for command in COMMANDS {
+                black_box(CommandAnalyser::requires_approval(black_box(command)));
+            }
+        })
+    });
+
+    c.bench_function("requires_approval ls", |b| {
+        b.iter(|| CommandAnalyser::requires_approval(black_box("ls -la")))
+    });
+}
+
+criterion_group!(benches, requires_approval);
+criterion_main!(benches);
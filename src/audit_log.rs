@@ -0,0 +1,119 @@
+//! Record of every command ask.sh ran on the user's behalf, one JSON line per command
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::ENV_AUDIT_LOG;
+
+const AUDIT_LOG_FILE: &str = "audit.log";
+
+/// Both command executors end their output with the exit code
+static EXIT_CODE_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"exit code: (-?\d+)").unwrap());
+
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    timestamp: DateTime<Local>,
+    command: String,
+    approval_required: bool,
+    approved: bool,
+    /// None when the command didn't run or didn't finish
+    exit_code: Option<i32>,
+    provider: Option<String>,
+    model: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(command: &str, approval_required: bool, approved: bool, output: &str) -> Self {
+        let llm_config = crate::get_llm_config().ok();
+
+        Self {
+            timestamp: Local::now(),
+            command: command.to_string(),
+            approval_required,
+            approved,
+            exit_code: approved.then(|| parse_exit_code(output)).flatten(),
+            provider: llm_config.as_ref().map(|config| config.provider.clone()),
+            model: llm_config.map(|config| config.model),
+        }
+    }
+
+    /// Append the entry to the audit log, unless ASK_SH_AUDIT_LOG is set to an empty value
+    pub fn write(&self) -> io::Result<()> {
+        let Some(file) = get_audit_log_file() else {
+            return Ok(());
+        };
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut line = serde_json::to_string(self).map_err(io::Error::other)?;
+        line.push('\n');
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file)?
+            .write_all(line.as_bytes())
+    }
+}
+
+fn get_audit_log_file() -> Option<PathBuf> {
+    match env::var(ENV_AUDIT_LOG) {
+        Ok(file) if file.is_empty() => None,
+        Ok(file) => Some(PathBuf::from(file)),
+        Err(_) => crate::get_data_dir().map(|dir| dir.join(AUDIT_LOG_FILE)),
+    }
+}
+
+fn parse_exit_code(output: &str) -> Option<i32> {
+    EXIT_CODE_PATTERN
+        .captures_iter(output)
+        .last()
+        .and_then(|captures| captures[1].parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_log_appends_json_lines() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let file = env::temp_dir().join(format!("ask-sh-audit-{}.log", uuid::Uuid::new_v4()));
+
+        env::set_var(ENV_AUDIT_LOG, &file);
+        AuditEntry::new("ls", false, true, "a\nb\nexit code: 0")
+            .write()
+            .unwrap();
+        AuditEntry::new("rm -rf x", true, false, "Command rejected by the user.")
+            .write()
+            .unwrap();
+
+        env::set_var(ENV_AUDIT_LOG, "");
+        AuditEntry::new("pwd", false, true, "exit code: 0")
+            .write()
+            .unwrap();
+        env::remove_var(ENV_AUDIT_LOG);
+
+        let log = fs::read_to_string(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        let entries: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["command"], "ls");
+        assert_eq!(entries[0]["exit_code"], 0);
+        assert_eq!(entries[1]["approval_required"], true);
+        assert_eq!(entries[1]["approved"], false);
+        assert!(entries[1]["exit_code"].is_null());
+    }
+}
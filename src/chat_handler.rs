@@ -1,44 +1,155 @@
+//! Sends the user's prompts to the model and handles its answers.
+//!
+//! When the provider passes tools to the model, the model runs commands itself through
+//! `execute_command`, once the user approves them. With tools off (`--no-tools`, `--raw`,
+//! `--explain`, or a provider without tool support) the model writes commands in code
+//! blocks instead, and they're listed after the answer for the user to pick from.
+
 use async_recursion::async_recursion;
-use futures::future::join_all;
-use std::io::Write;
-use std::process;
-use std::process::Command;
+use std::{env, error::Error, process};
 
 use crate::{
-    llm::{create_llm_provider, LLMConfig, LLMProvider, Message, Provider},
+    command_analyser::CommandAnalyser,
+    llm::{create_llm_provider, LLMConfig, LLMError, LLMProvider, Message, Provider},
     prompts,
-    tools::{execute_tool, ToolCall},
+    renderer::{OutputRenderer, RendererKind},
+    theme::icon,
+    tools::{
+        execute_command::{approve_once, confirm_command, get_batch_approval, get_env_flag},
+        execute_tool_calls,
+        final_answer::find_final_answer,
+        ToolCall,
+    },
+    transcript::Transcript,
+    user_context::get_user_context,
     user_system_info::UserSystemInfo,
+    ENV_APPROVE_ONCE, ENV_MAX_TOOL_ITERATIONS,
 };
 
-pub struct ChatHandler {
-    llm_provider: Provider,
-    display_fn: Option<fn(&str) -> Result<(), Box<dyn std::error::Error>>>,
+const DEFAULT_MAX_TOOL_ITERATIONS: usize = 10;
+
+pub struct ChatHandler<P: LLMProvider = Provider> {
+    llm_provider: P,
+    renderer: OutputRenderer,
+    transcript: Transcript,
+    /// Rounds of tool calls allowed before the model is asked to wrap up
+    max_tool_iterations: usize,
+    /// Base64 encoded images sent along with the next prompt
+    images: Vec<String>,
+    /// Earlier conversation sent along with the next prompt, when resuming it
+    previous_conversation: Option<String>,
+    /// List the commands in the answer's code blocks when the model can't run them
+    suggest_commands: bool,
+    /// With ASK_SH_APPROVE_ONCE, a command was approved this session and the next ones run
+    approved_once: bool,
+    /// With ASK_SH_STRUCTURED_OUTPUT, the arguments the model ended its turn with
+    final_answer: Option<serde_json::Value>,
 }
 
 impl ChatHandler {
-    pub fn new(llm_config: LLMConfig) -> Self {
+    /// `system_prompt` replaces the configured system prompt, for this conversation only
+    pub fn new(llm_config: LLMConfig, system_prompt: Option<String>) -> Self {
         let user_system_info = UserSystemInfo::new();
         let mut vars = std::collections::HashMap::new();
         vars.insert("user_os".to_owned(), user_system_info.os.to_owned());
         vars.insert("user_arch".to_owned(), user_system_info.arch.to_owned());
         vars.insert("user_shell".to_owned(), user_system_info.shell.to_owned());
+        vars.insert(
+            "user_distro".to_owned(),
+            user_system_info.distro.unwrap_or_default(),
+        );
+        vars.insert(
+            "user_lang".to_owned(),
+            user_system_info.language.unwrap_or_default(),
+        );
+        vars.insert(
+            "user_lang_forced".to_owned(),
+            match user_system_info.language_forced {
+                true => "true".to_owned(),
+                false => String::new(),
+            },
+        );
+        vars.insert(
+            "user_context".to_owned(),
+            get_user_context().unwrap_or_default(),
+        );
 
-        let mut display_fn: Option<fn(&str) -> Result<(), Box<dyn std::error::Error>>> = None;
-        if get_glow_installed() {
-            display_fn = Some(display_with_glow_pipe);
-        }
-
-        let templates = prompts::get_template();
-        let system_message = templates.render("SYSTEM_PROMPT", &vars).unwrap();
+        let renderer = OutputRenderer::new(RendererKind::from_env());
 
         let mut llm_provider = create_llm_provider(llm_config).unwrap();
+        vars.insert(
+            "use_tools".to_owned(),
+            match llm_provider.supports_tools() {
+                true => "true".to_owned(),
+                false => String::new(),
+            },
+        );
+
+        let system_message = system_prompt.unwrap_or_else(|| {
+            let templates = prompts::get_template();
+            templates.render("SYSTEM_PROMPT", &vars).unwrap()
+        });
         llm_provider.with_system_prompt(&system_message);
 
+        let max_tool_iterations = env::var(ENV_MAX_TOOL_ITERATIONS)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(DEFAULT_MAX_TOOL_ITERATIONS);
+
         Self {
-            llm_provider: llm_provider,
-            display_fn: display_fn,
+            llm_provider,
+            renderer,
+            transcript: Transcript::default(),
+            max_tool_iterations,
+            images: Vec::new(),
+            previous_conversation: None,
+            suggest_commands: true,
+            approved_once: false,
+            final_answer: None,
+        }
+    }
+}
+
+impl<P: LLMProvider> ChatHandler<P> {
+    /// Use a different renderer than the one configured in the environment
+    pub fn with_renderer(mut self, renderer: OutputRenderer) -> Self {
+        self.renderer = renderer;
+        self
+    }
+
+    /// Send images along with the next prompt, for vision models
+    pub fn with_images(mut self, images: Vec<String>) -> Self {
+        self.images = images;
+        self
+    }
+
+    /// Print the answer only, e.g. for --raw, where the commands in it are no suggestions
+    pub fn without_command_suggestions(mut self) -> Self {
+        self.suggest_commands = false;
+        self
+    }
+
+    /// Pick up an earlier conversation: the model is shown it with the next prompt,
+    /// and the new turn is saved after it
+    pub fn with_previous_conversation(mut self, transcript: Transcript) -> Self {
+        if !transcript.is_empty() {
+            self.previous_conversation = Some(transcript.to_markdown());
+            self.transcript = transcript;
         }
+        self
+    }
+
+    /// The structured answer to the last prompt, when the model called `final_answer`
+    pub fn final_answer(&self) -> Option<&serde_json::Value> {
+        self.final_answer.as_ref()
+    }
+
+    /// Start the conversation over, as `/clear` would in a chat session.
+    /// The system prompt stays, and the conversation is saved from the next prompt on.
+    pub fn clear_history(&mut self) {
+        self.llm_provider.clear_history();
+        self.transcript = Transcript::default();
+        self.previous_conversation = None;
     }
 
     pub async fn process_user_prompt(&mut self, user_input: String) {
@@ -46,81 +157,590 @@ impl ChatHandler {
         vars.insert("user_input".to_owned(), user_input.to_owned());
 
         let templates = prompts::get_template();
-        let user_input = templates.render("USER_PROMPT", &vars).unwrap();
-        let message = Message {
+        let prompt = templates.render("USER_PROMPT", &vars).unwrap();
+
+        self.process_prompt(user_input, prompt).await;
+    }
+
+    /// Explain a command rather than run it, pointing out the parts that need approval
+    pub async fn process_explain_prompt(&mut self, command: String) {
+        let concerns = CommandAnalyser::approval_concerns(&command)
+            .into_iter()
+            .map(|(part, reason)| format!("- `{}`: {}", part, reason))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let mut vars = std::collections::HashMap::new();
+        vars.insert("command".to_owned(), command.to_owned());
+        vars.insert("concerns".to_owned(), concerns);
+
+        let templates = prompts::get_template();
+        let prompt = templates.render("EXPLAIN_PROMPT", &vars).unwrap();
+
+        self.process_prompt(command, prompt).await;
+    }
+
+    /// The request comes from the arguments and the data it's about from stdin,
+    /// kept apart in the prompt so the model doesn't take the data for instructions
+    pub async fn process_piped_prompt(&mut self, user_input: String, piped_input: String) {
+        let mut vars = std::collections::HashMap::new();
+        vars.insert("user_input".to_owned(), user_input.to_owned());
+        vars.insert("piped_input".to_owned(), piped_input.to_owned());
+
+        let templates = prompts::get_template();
+        let prompt = templates.render("PIPE_PROMPT", &vars).unwrap();
+
+        self.process_prompt(format!("{}\n\n{}", user_input, piped_input), prompt)
+            .await;
+    }
+
+    async fn process_prompt(&mut self, user_input: String, prompt: String) {
+        // The structured answer of an earlier prompt doesn't answer this one
+        self.final_answer = None;
+
+        self.transcript.push(Message {
             content: user_input,
             role: "user".to_string(),
             ..Default::default()
+        });
+
+        let message = Message {
+            content: self.continue_prompt(prompt),
+            role: "user".to_string(),
+            images: std::mem::take(&mut self.images),
+            ..Default::default()
         };
 
-        let response = &self.llm_provider.chat(&message, self.display_fn).await;
+        let response = &self.llm_provider.chat(&message, &mut self.renderer).await;
 
         let response = match response {
             Ok(val) => val,
             Err(e) => {
-                eprintln!("Communication with LLM provider failed: {}", e);
+                report_provider_error(e.as_ref());
                 process::exit(1);
             }
         };
 
+        self.record_response(&response.content, &response.tool_calls);
+
+        // Without tools, the code blocks are the only way the model suggests commands
+        if self.suggest_commands && !self.llm_provider.supports_tools() {
+            print_suggested_commands(&suggested_commands(&response.content));
+        }
+
         if response.tool_calls.is_some() {
             let tool_calls = response.tool_calls.clone().unwrap();
-            self.process_response_tool_calls(tool_calls).await;
+            self.process_response_tool_calls(tool_calls, 0).await;
         }
+
+        // Failing to save only breaks --export-last, so don't bother the user
+        let _ = self.transcript.save_as_last();
+    }
+
+    /// Prepend the earlier conversation, if any, to the first prompt only
+    fn continue_prompt(&mut self, prompt: String) -> String {
+        let Some(conversation) = self.previous_conversation.take() else {
+            return prompt;
+        };
+
+        let mut vars = std::collections::HashMap::new();
+        vars.insert("conversation".to_owned(), conversation);
+        vars.insert("prompt".to_owned(), prompt);
+
+        let templates = prompts::get_template();
+        templates.render("CONTINUE_PROMPT", &vars).unwrap()
+    }
+
+    fn record_response(&mut self, content: &str, tool_calls: &Option<Vec<ToolCall>>) {
+        self.transcript.push(Message {
+            content: content.to_string(),
+            role: "assistant".to_string(),
+            tool_calls: tool_calls.clone(),
+            ..Default::default()
+        });
     }
 
     #[async_recursion(?Send)]
-    async fn process_response_tool_calls(&mut self, tool_calls: Vec<ToolCall>) {
-        if !tool_calls.is_empty() {
-            // Execute each tool call
-            let handles = tool_calls.into_iter().map(|tool_call| {
-                tokio::spawn(async move { execute_tool(&tool_call.function).await.unwrap() })
-            });
+    async fn process_response_tool_calls(&mut self, tool_calls: Vec<ToolCall>, iteration: usize) {
+        if !tool_calls.is_empty() && iteration >= self.max_tool_iterations {
+            self.stop_tool_calls().await;
+            return;
+        }
 
-            let results = join_all(handles)
-                .await
-                .into_iter()
-                .map(|r| r.unwrap())
-                .collect::<Vec<_>>();
+        // The final answer ends the turn, so the other tool calls along with it are dropped
+        if let Some(answer) = find_final_answer(&tool_calls) {
+            println!("{}", serde_json::to_string_pretty(answer).unwrap());
+            self.final_answer = Some(answer.clone());
+            return;
+        }
+
+        if !tool_calls.is_empty() {
+            let mut approvals = get_batch_approval(&tool_calls);
+            if get_env_flag(ENV_APPROVE_ONCE) {
+                approvals = approve_once(
+                    &tool_calls,
+                    approvals,
+                    &mut self.approved_once,
+                    confirm_command,
+                );
+            }
+            let results = execute_tool_calls(tool_calls, approvals).await;
 
             let tool_result_message = Message {
                 content: serde_json::to_string_pretty(&results).unwrap(),
                 role: "tool".to_string(),
+                tool_results: results,
                 ..Default::default()
             };
 
-            let response = &self
+            self.transcript.push(tool_result_message.clone());
+
+            let response = match self
                 .llm_provider
-                .chat(&tool_result_message, self.display_fn)
+                .chat(&tool_result_message, &mut self.renderer)
                 .await
-                .unwrap();
-            let response_tool_calls = response.tool_calls.clone().unwrap();
+            {
+                Ok(response) => response,
+                Err(e) => {
+                    report_provider_error(e.as_ref());
+                    return;
+                }
+            };
+            self.record_response(&response.content, &response.tool_calls);
+            let response_tool_calls = response.tool_calls.clone().unwrap_or_default();
             if !response_tool_calls.is_empty() {
-                self.process_response_tool_calls(response_tool_calls).await;
+                self.process_response_tool_calls(response_tool_calls, iteration + 1)
+                    .await;
             }
         }
     }
+
+    /// Ask the model to summarize instead of running more tools, so a looping model can't go on forever
+    async fn stop_tool_calls(&mut self) {
+        eprintln!(
+            "{}Stopped after {} rounds of tool calls. Set {} to allow more.",
+            icon("⚠️  "),
+            self.max_tool_iterations,
+            ENV_MAX_TOOL_ITERATIONS
+        );
+
+        let message = Message {
+            content: "You have reached the limit of tool calls for this request. \
+                Do not call any more tools. Summarize what you found so far for the user."
+                .to_string(),
+            role: "user".to_string(),
+            ..Default::default()
+        };
+        self.transcript.push(message.clone());
+
+        // Without the tools the model has to answer in text
+        self.llm_provider.set_tools_enabled(false);
+        let response = self.llm_provider.chat(&message, &mut self.renderer).await;
+        self.llm_provider.set_tools_enabled(true);
+
+        if let Ok(response) = response {
+            self.record_response(&response.content, &None);
+        }
+    }
 }
 
-fn get_glow_installed() -> bool {
-    // Use sh -c to run echo | glow
-    let glow_version = Command::new("glow").arg("-v").output();
-    glow_version.is_ok()
+/// Shell languages the model may tag its code blocks with, besides none at all
+const SHELL_LANGUAGES: &[&str] = &["sh", "bash", "zsh", "fish", "shell", "console"];
+
+/// Commands often given inline, e.g. "Run `docker ps -a`", rather than in a code block
+const KNOWN_COMMANDS: &[&str] = &[
+    "apt",
+    "awk",
+    "brew",
+    "cargo",
+    "cat",
+    "cd",
+    "chmod",
+    "chown",
+    "cp",
+    "curl",
+    "df",
+    "dnf",
+    "docker",
+    "du",
+    "echo",
+    "export",
+    "find",
+    "git",
+    "go",
+    "grep",
+    "head",
+    "journalctl",
+    "kill",
+    "kubectl",
+    "less",
+    "ln",
+    "ls",
+    "lsof",
+    "make",
+    "mkdir",
+    "mv",
+    "npm",
+    "npx",
+    "pip",
+    "ps",
+    "python",
+    "python3",
+    "rm",
+    "rsync",
+    "scp",
+    "sed",
+    "ssh",
+    "sudo",
+    "systemctl",
+    "tail",
+    "tar",
+    "top",
+    "touch",
+    "uname",
+    "unzip",
+    "wget",
+    "which",
+    "yarn",
+];
+
+/// The commands to suggest: those in shell code blocks, or else those in inline code
+fn suggested_commands(answer: &str) -> Vec<String> {
+    let commands = fenced_commands(answer);
+    match commands.is_empty() {
+        true => inline_commands(answer),
+        false => commands,
+    }
+}
+
+/// The contents of the answer's shell code blocks, one command per block
+fn fenced_commands(answer: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut block: Option<(bool, Vec<&str>)> = None;
+
+    for line in answer.lines() {
+        let trimmed = line.trim();
+        match (&mut block, trimmed.strip_prefix("```")) {
+            (None, Some(language)) => {
+                let language = language.trim();
+                block = Some((
+                    language.is_empty() || SHELL_LANGUAGES.contains(&language),
+                    Vec::new(),
+                ))
+            }
+            (Some((is_shell, lines)), Some(_)) => {
+                let command = lines.join("\n");
+                if *is_shell && !command.trim().is_empty() {
+                    commands.push(command.trim().to_string());
+                }
+                block = None;
+            }
+            (Some((_, lines)), None) => lines.push(line),
+            (None, None) => {}
+        }
+    }
+
+    commands
 }
 
-fn display_with_glow_pipe(content: &str) -> Result<(), Box<dyn std::error::Error>> {
-    // Use sh -c to run echo | glow
-    let mut child = Command::new("sh")
-        .arg("-c")
-        .arg("glow -s auto -w 100 -")
-        .stdin(std::process::Stdio::piped())
-        .spawn()?;
+/// Inline code outside code blocks that looks like a command: alone on its line, or a known
+/// command with arguments. A file or option name in a sentence isn't one.
+fn inline_commands(answer: &str) -> Vec<String> {
+    let mut commands: Vec<String> = Vec::new();
+    let mut in_block = false;
 
-    if let Some(mut stdin) = child.stdin.take() {
-        stdin.write_all(content.as_bytes())?;
+    for line in answer.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            in_block = !in_block;
+            continue;
+        }
+        if in_block {
+            continue;
+        }
+
+        // Only what's between backticks, which are every other part of the line
+        let spans: Vec<&str> = trimmed
+            .split('`')
+            .skip(1)
+            .step_by(2)
+            .map(str::trim)
+            .collect();
+        let whole_line = spans.len() == 1 && is_only_code(trimmed);
+        for span in spans {
+            let words: Vec<&str> = span.split_whitespace().collect();
+            let is_command = match words.as_slice() {
+                [] => false,
+                [_] => whole_line,
+                ["sudo", command, ..] | [command, ..] => {
+                    whole_line || KNOWN_COMMANDS.contains(command)
+                }
+            };
+            if is_command && !commands.iter().any(|command| command == span) {
+                commands.push(span.to_string());
+            }
+        }
     }
 
-    child.wait()?;
+    commands
+}
+
+/// Whether the line is a single inline code span, apart from a list marker and punctuation
+fn is_only_code(line: &str) -> bool {
+    let line = line
+        .trim_start_matches(['-', '*', '+'])
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .trim_start_matches('.')
+        .trim()
+        .trim_end_matches(['.', ',', ':', ';']);
+
+    line.len() > 1 && line.starts_with('`') && line.ends_with('`')
+}
 
-    Ok(())
+/// The commands go to stdout one per line, which is what the shell function offers to select
+fn print_suggested_commands(commands: &[String]) {
+    let lines = selectable_lines(commands);
+    if lines.is_empty() {
+        return;
+    }
+
+    eprintln!(
+        "\n{}Tools are off, so nothing was run. Suggested commands:",
+        icon("💡 ")
+    );
+    for line in lines {
+        println!("{}", line);
+    }
+}
+
+/// Each line of the commands that can be typed on its own, with continued lines joined.
+/// Blank lines, comments and repeated lines are left out.
+fn selectable_lines(commands: &[String]) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+
+    for command in commands {
+        let mut pending = String::new();
+        for line in command.lines() {
+            let line = line.trim();
+            match line.strip_suffix('\\') {
+                Some(start) => pending.push_str(start),
+                None => {
+                    pending.push_str(line);
+                    let line = std::mem::take(&mut pending);
+                    if !line.is_empty() && !line.starts_with('#') && !lines.contains(&line) {
+                        lines.push(line);
+                    }
+                }
+            }
+        }
+    }
+
+    lines
+}
+
+/// Print the error, and what to do about it when that's clear
+fn report_provider_error(error: &(dyn Error + 'static)) {
+    eprintln!("Communication with LLM provider failed: {}", error);
+
+    if let Some(guidance) = error
+        .downcast_ref::<LLMError>()
+        .and_then(LLMError::guidance)
+    {
+        eprintln!("{}{}", icon("💡 "), guidance);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{LLMError, MockProvider};
+
+    fn chat_handler(provider: MockProvider) -> ChatHandler<MockProvider> {
+        ChatHandler {
+            llm_provider: provider,
+            renderer: OutputRenderer::new(RendererKind::Plain),
+            transcript: Transcript::default(),
+            max_tool_iterations: 3,
+            images: Vec::new(),
+            previous_conversation: None,
+            suggest_commands: true,
+            approved_once: false,
+            final_answer: None,
+        }
+    }
+
+    fn noop_tool_call() -> ToolCall {
+        let response = MockProvider::tool_call("noop", serde_json::json!({})).unwrap();
+        response.tool_calls.unwrap().remove(0)
+    }
+
+    #[tokio::test]
+    async fn test_tool_results_are_sent_back_until_answered() {
+        let mut chat_handler = chat_handler(MockProvider::new(vec![
+            MockProvider::tool_call("noop", serde_json::json!({})),
+            MockProvider::text("All done"),
+        ]));
+
+        chat_handler
+            .process_response_tool_calls(vec![noop_tool_call()], 0)
+            .await;
+
+        // The results of both rounds of tool calls went back to the model
+        let requests = &chat_handler.llm_provider.requests;
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|message| message.role == "tool"));
+        assert!(requests[0].content.contains("noop"));
+        assert_eq!(
+            chat_handler
+                .transcript
+                .to_markdown()
+                .matches("## Tool")
+                .count(),
+            2
+        );
+        assert!(chat_handler.transcript.to_markdown().contains("All done"));
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_loop_terminates() {
+        // A model stuck in a loop, asking for another tool call every time
+        let responses = (0..4)
+            .map(|_| MockProvider::tool_call("noop", serde_json::json!({})))
+            .collect();
+        let mut chat_handler = chat_handler(MockProvider::new(responses));
+
+        chat_handler
+            .process_response_tool_calls(vec![noop_tool_call()], 0)
+            .await;
+
+        // One request after each of the 3 rounds, then one asking for a summary
+        let requests = &chat_handler.llm_provider.requests;
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[3].role, "user");
+        assert!(requests[3].content.contains("limit of tool calls"));
+        assert_eq!(
+            chat_handler.llm_provider.tools_sent,
+            vec![true, true, true, false]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_final_answer_ends_the_turn() {
+        let answer = serde_json::json!({"command": "ls -a", "explanation": "", "risk": "none"});
+        let mut chat_handler = chat_handler(MockProvider::new(vec![
+            MockProvider::tool_call("final_answer", answer.clone()),
+            MockProvider::text("Unused"),
+        ]));
+
+        chat_handler
+            .process_response_tool_calls(vec![noop_tool_call()], 0)
+            .await;
+
+        // Only the results of the first round went back, not the final answer
+        assert_eq!(chat_handler.llm_provider.requests.len(), 1);
+        assert_eq!(chat_handler.final_answer(), Some(&answer));
+
+        chat_handler
+            .process_user_prompt("And now?".to_string())
+            .await;
+        assert_eq!(chat_handler.final_answer(), None);
+    }
+
+    #[tokio::test]
+    async fn test_provider_error_after_tool_call() {
+        let mut chat_handler = chat_handler(MockProvider::new(vec![Err(LLMError::ApiError(
+            "overloaded".to_string(),
+        ))]));
+
+        chat_handler
+            .process_response_tool_calls(vec![noop_tool_call()], 0)
+            .await;
+
+        // The tool results are kept, without an answer
+        assert_eq!(chat_handler.llm_provider.requests.len(), 1);
+        let markdown = chat_handler.transcript.to_markdown();
+        assert!(markdown.contains("## Tool"));
+        assert!(!markdown.contains("## Assistant"));
+    }
+
+    #[test]
+    fn test_fenced_commands() {
+        let answer = "List the containers:\n\n```\ndocker ps -a\n```\n\n\
+            Then stop them:\n```bash\ndocker stop web\ndocker stop db\n```\n\
+            With this config:\n```json\n{\"restart\": \"no\"}\n```\n";
+
+        assert_eq!(
+            fenced_commands(answer),
+            vec!["docker ps -a", "docker stop web\ndocker stop db"]
+        );
+        assert!(fenced_commands("Run `docker ps` to see them.").is_empty());
+    }
+
+    #[test]
+    fn test_inline_commands() {
+        assert_eq!(
+            suggested_commands("Run `docker ps -a` to see them, or `sudo ls /root`."),
+            vec!["docker ps -a", "sudo ls /root"]
+        );
+        assert_eq!(
+            suggested_commands("You can use:\n\n- `htop`\n- `btm --basic`\n"),
+            vec!["htop", "btm --basic"]
+        );
+
+        // Code blocks are the suggestions when there are some
+        assert_eq!(
+            suggested_commands("Use `ls -la`, or better:\n```\nls -lah\n```\n"),
+            vec!["ls -lah"]
+        );
+    }
+
+    #[test]
+    fn test_selectable_lines() {
+        let commands = vec![
+            "docker ps -a".to_string(),
+            "# stop them\ndocker stop web\n\ndocker stop db".to_string(),
+            "docker run \\\n  --rm alpine".to_string(),
+            "docker ps -a".to_string(),
+        ];
+
+        assert_eq!(
+            selectable_lines(&commands),
+            vec![
+                "docker ps -a",
+                "docker stop web",
+                "docker stop db",
+                "docker run --rm alpine"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prose_inline_code_is_not_a_command() {
+        let answer = "Edit `config.json` and set `PATH`, then pass `--force` to the `docker` \
+            command.\n`retries: 3` makes it retry. The `find` is `O(n)`.\n\
+            ```json\n`ls -la`\n```\n";
+
+        assert!(suggested_commands(answer).is_empty());
+    }
+
+    #[test]
+    fn test_previous_conversation_is_sent_once() {
+        let mut transcript = Transcript::default();
+        transcript.push(Message {
+            role: "user".to_string(),
+            content: "how do I list docker containers".to_string(),
+            ..Default::default()
+        });
+
+        let mut chat_handler =
+            chat_handler(MockProvider::default()).with_previous_conversation(transcript);
+
+        let prompt = chat_handler.continue_prompt("and now stop them".to_string());
+        assert!(prompt.contains("<previous_conversation>"));
+        assert!(prompt.contains("how do I list docker containers"));
+        assert!(prompt.ends_with("and now stop them"));
+
+        let prompt = chat_handler.continue_prompt("and remove them".to_string());
+        assert_eq!(prompt, "and remove them");
+    }
 }
@@ -0,0 +1,70 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use thiserror::Error;
+
+/// External clipboard tools, tried in order when arboard is unavailable, and before it on Linux
+const FALLBACK_COMMANDS: &[(&str, &[&str])] = &[
+    ("pbcopy", &[]),
+    ("wl-copy", &[]),
+    ("xclip", &["-selection", "clipboard"]),
+];
+
+#[derive(Debug, Error)]
+pub enum ClipboardError {
+    #[error("No clipboard backend available. Please install one of: pbcopy, wl-copy, xclip")]
+    NoBackend,
+}
+
+/// Copies the given text to the system clipboard.
+/// Returns the name of the backend that was used.
+pub fn copy_to_clipboard(text: &str) -> Result<&'static str, ClipboardError> {
+    // On Linux the clipboard is served by the program that set it, so arboard's is gone once
+    // ask.sh exits, unless a clipboard manager takes it over. wl-copy and xclip stay around.
+    if cfg!(target_os = "linux") {
+        if let Some(program) = copy_with_commands(text) {
+            return Ok(program);
+        }
+    }
+
+    if let Ok(mut clipboard) = arboard::Clipboard::new() {
+        if clipboard.set_text(text).is_ok() {
+            return Ok("arboard");
+        }
+    }
+
+    match cfg!(target_os = "linux") {
+        true => Err(ClipboardError::NoBackend),
+        false => copy_with_commands(text).ok_or(ClipboardError::NoBackend),
+    }
+}
+
+/// The first of the external tools that copied the text
+fn copy_with_commands(text: &str) -> Option<&'static str> {
+    FALLBACK_COMMANDS
+        .iter()
+        .find(|(program, args)| copy_with_command(program, args, text))
+        .map(|(program, _)| *program)
+}
+
+fn copy_with_command(program: &str, args: &[&str], text: &str) -> bool {
+    let child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(_) => return false, // Command not installed
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        if stdin.write_all(text.as_bytes()).is_err() {
+            return false;
+        }
+    }
+
+    child.wait().is_ok_and(|status| status.success())
+}
@@ -1,37 +1,66 @@
 pub struct CommandAnalyser;
 
+/// Whether `ssh` is given a command to run, rather than opening a shell
+fn has_remote_command(args: &[&str]) -> bool {
+    const OPTIONS_WITH_VALUE: &[&str] = &[
+        "-b", "-c", "-D", "-E", "-e", "-F", "-I", "-i", "-J", "-L", "-l", "-m", "-O", "-o", "-p",
+        "-Q", "-R", "-S", "-W", "-w",
+    ];
+
+    let mut args = args.iter();
+    let mut destination = None;
+    while let Some(arg) = args.next() {
+        if OPTIONS_WITH_VALUE.contains(arg) {
+            args.next();
+        } else if !arg.starts_with('-') {
+            destination = Some(arg);
+            break;
+        }
+    }
+
+    destination.is_some() && args.next().is_some()
+}
+
 impl CommandAnalyser {
     /// Checks if a command requires user approval before execution.
     /// Returns (needs_approval, reason)
+    ///
+    /// It runs on every command, so the command is lowercased once and every check
+    /// works on slices of it, see `benches/command_analyser.rs`.
     pub fn requires_approval(command: &str) -> (bool, Option<&'static str>) {
         let cmd = command.trim();
-        let base_cmd = Self::extract_base_command(cmd);
+        let cmd_lower = cmd.to_lowercase();
+        let base_cmd = Self::extract_base_command(&cmd_lower);
 
         if base_cmd == "git" {
-            return Self::check_git_command(cmd);
+            return Self::check_git_command(&cmd_lower);
         }
 
-        if Self::is_file_modifying(&base_cmd) {
+        if Self::is_windows_system_config(&cmd_lower, base_cmd) {
+            return (true, Some("modifies system configuration"));
+        }
+
+        if Self::is_file_modifying(base_cmd) || Self::is_windows_file_modifying(base_cmd) {
             return (true, Some("modifies files or system state"));
         }
 
-        if Self::is_package_manager(&base_cmd) {
+        if Self::is_package_manager(base_cmd) {
             return (true, Some("installs or manages software"));
         }
 
-        if Self::is_network_operation(&base_cmd) {
+        if Self::is_network_operation(base_cmd) {
             return (true, Some("performs network operations"));
         }
 
-        if Self::is_system_config(cmd) {
+        if Self::is_system_config(cmd, base_cmd) {
             return (true, Some("modifies system configuration"));
         }
 
-        if Self::is_database_operation(&base_cmd) {
+        if Self::is_database_operation(base_cmd) {
             return (true, Some("performs database operations"));
         }
 
-        if Self::is_risky(cmd, &base_cmd) {
+        if Self::is_risky(cmd, base_cmd) {
             return (true, Some("potentially risky operation"));
         }
 
@@ -39,15 +68,134 @@ impl CommandAnalyser {
         (false, None)
     }
 
-    /// Extracts the base command name from a shell command string
-    fn extract_base_command(cmd: &str) -> String {
-        cmd.split_whitespace()
-            .skip_while(|word| word.contains('=')) // Skip env vars
-            .next()
+    /// Commands waiting for keyboard input would hang until the timeout, since nobody
+    /// can type into them. Returns what to do instead when the command is one of them.
+    pub fn interactive_alternative(command: &str) -> Option<&'static str> {
+        const EDITOR: &str = "Read files with `cat`, and write them with `tee` or a heredoc.";
+        const PAGER: &str = "Print the output directly, e.g. `cat file` or `man ls | col -b`.";
+        const MONITOR: &str =
+            "Take a single snapshot, e.g. `top -b -n 1` or `ps aux --sort=-%cpu | head`.";
+        const SSH: &str = "Pass the command to run remotely, e.g. `ssh host uptime`.";
+        const REPL: &str = "Run a script or a one-liner, e.g. `python3 -c 'print(1 + 1)'`.";
+
+        let command = command.replace("||", ";");
+        for list in command.split(['\n', ';', '&']) {
+            let stages: Vec<&str> = list.split('|').collect();
+
+            for (index, stage) in stages.iter().enumerate() {
+                let is_piped = index + 1 < stages.len();
+                let mut words = stage
+                    .split_whitespace()
+                    .skip_while(|word| word.contains('=') || ["sudo", "env"].contains(word));
+                let Some(program) = words.next() else {
+                    continue;
+                };
+                let program = program.rsplit('/').next().unwrap_or(program).to_lowercase();
+                let args: Vec<&str> = words.collect();
+
+                let alternative = match program.as_str() {
+                    "emacs" if args.contains(&"--batch") => None,
+                    "vim" | "vi" | "nvim" | "nano" | "emacs" | "pico" | "micro" => Some(EDITOR),
+                    "less" | "more" | "most" => Some(PAGER),
+                    "man" if !is_piped => Some(PAGER),
+                    "top"
+                        if !args
+                            .iter()
+                            .any(|arg| arg.starts_with("-b") || arg.starts_with("-l")) =>
+                    {
+                        Some(MONITOR)
+                    }
+                    "htop" | "btop" | "atop" | "nmon" | "watch" => Some(MONITOR),
+                    "ssh" if !has_remote_command(&args) => Some(SSH),
+                    "python" | "python3" | "ipython" | "node" | "irb"
+                        if args.iter().all(|arg| *arg == "-i") =>
+                    {
+                        Some(REPL)
+                    }
+                    _ => None,
+                };
+
+                if alternative.is_some() {
+                    return alternative;
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Each part of a pipeline or command list that would need approval, with the reason
+    pub fn approval_concerns(command: &str) -> Vec<(String, &'static str)> {
+        let command = command.replace("||", ";");
+
+        command
+            .split(['\n', ';', '&', '|'])
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .filter_map(|part| match Self::requires_approval(part) {
+                (true, reason) => Some((part.to_string(), reason.unwrap_or("needs approval"))),
+                (false, _) => None,
+            })
+            .collect()
+    }
+
+    /// Whether the command only reads, for safe mode. Unlike `requires_approval`, every part
+    /// of a pipeline or command list is checked, and whatever can't be told apart is refused.
+    pub fn is_read_only(command: &str) -> bool {
+        // Redirections write files, substitutions run commands hidden in the arguments
+        const UNSURE_PATTERNS: &[&str] = &[">", "$(", "`", "<("];
+        // Run or write through their arguments
+        const WRITING_COMMANDS: &[&str] = &[
+            "tee", "xargs", "env", "nohup", "nice", "timeout", "time", "command", "builtin",
+        ];
+        const WRITING_ARGS: &[&str] = &[
+            "-delete", "-exec", "-execdir", "-ok", "-okdir", "-fprint", "-fprint0", "-fprintf",
+            "-fls",
+        ];
+
+        if UNSURE_PATTERNS.iter().any(|p| command.contains(p))
+            || !Self::approval_concerns(command).is_empty()
+        {
+            return false;
+        }
+
+        let command = command.replace("||", ";");
+        command.split(['\n', ';', '&', '|']).all(|part| {
+            let mut words = part
+                .split_whitespace()
+                .skip_while(|word| word.contains('='));
+            let program = words
+                .next()
+                .map(|program| program.rsplit('/').next().unwrap_or(program).to_lowercase())
+                .unwrap_or_default();
+
+            !WRITING_COMMANDS.contains(&program.as_str())
+                && !words.any(|word| WRITING_ARGS.contains(&word))
+        })
+    }
+
+    /// Commands that can destroy data or take the system down, which always need
+    /// the user's own approval. Any part of a pipeline or command list makes it dangerous.
+    pub fn is_dangerous(command: &str) -> bool {
+        let command = command.replace("||", ";");
+
+        command.split(['\n', ';', '&', '|']).any(|part| {
+            let cmd = part.trim();
+            let cmd_lower = cmd.to_lowercase();
+            let base_cmd = Self::extract_base_command(&cmd_lower);
+
+            Self::is_risky(cmd, base_cmd)
+                || (base_cmd == "git" && Self::is_destructive_git(&cmd_lower))
+        })
+    }
+
+    /// Extracts the base command name from a lowercased shell command string
+    fn extract_base_command(cmd_lower: &str) -> &str {
+        cmd_lower
+            .split_whitespace()
+            .find(|word| !word.contains('=')) // Skip env vars
             .and_then(|segment| segment.split('|').next()) // Handle pipes
-            .and_then(|segment| segment.split_whitespace().next()) // Get first word
             .unwrap_or("")
-            .to_lowercase()
     }
 
     fn is_file_modifying(cmd: &str) -> bool {
@@ -59,6 +207,35 @@ impl CommandAnalyser {
         FILE_COMMANDS.contains(&cmd) || cmd.starts_with("write") || cmd.ends_with("fs")
     }
 
+    fn is_windows_file_modifying(cmd: &str) -> bool {
+        const WINDOWS_FILE_COMMANDS: &[&str] = &[
+            "remove-item",
+            "ri",
+            "del",
+            "erase",
+            "rd",
+            "move-item",
+            "move",
+            "copy-item",
+            "copy",
+            "xcopy",
+            "robocopy",
+            "new-item",
+            "ni",
+            "rename-item",
+            "ren",
+            "set-content",
+            "add-content",
+            "clear-content",
+            "out-file",
+            "icacls",
+            "takeown",
+            "attrib",
+        ];
+
+        WINDOWS_FILE_COMMANDS.contains(&cmd)
+    }
+
     fn is_package_manager(cmd: &str) -> bool {
         const PACKAGE_MANAGERS: &[&str] = &[
             "brew", "apt", "apt-get", "yum", "dnf", "pacman", "npm", "yarn", "pnpm", "pip", "pip3",
@@ -77,7 +254,7 @@ impl CommandAnalyser {
         NETWORK_COMMANDS.contains(&cmd)
     }
 
-    fn is_system_config(full_cmd: &str) -> bool {
+    fn is_system_config(full_cmd: &str, base_cmd: &str) -> bool {
         // Check for system paths
         if full_cmd.contains("/etc/") || full_cmd.contains("/sys/") {
             return true;
@@ -104,8 +281,42 @@ impl CommandAnalyser {
             "modprobe",
         ];
 
-        let base = Self::extract_base_command(full_cmd);
-        SYSTEM_COMMANDS.contains(&base.as_str())
+        SYSTEM_COMMANDS.contains(&base_cmd)
+    }
+
+    fn is_windows_system_config(cmd_lower: &str, base_cmd: &str) -> bool {
+        // Registry edits
+        const REGISTRY_PATTERNS: &[&str] = &[
+            "hklm:",
+            "hkcu:",
+            "hkey_",
+            "registry::",
+            "reg add",
+            "reg delete",
+            "reg import",
+            "regedit",
+        ];
+
+        const WINDOWS_SYSTEM_COMMANDS: &[&str] = &[
+            "stop-computer",
+            "restart-computer",
+            "set-executionpolicy",
+            "stop-service",
+            "set-service",
+            "remove-service",
+            "stop-process",
+            "taskkill",
+            "sc",
+            "bcdedit",
+            "diskpart",
+            "format-volume",
+            "clear-disk",
+            "netsh",
+            "setx",
+        ];
+
+        REGISTRY_PATTERNS.iter().any(|p| cmd_lower.contains(p))
+            || WINDOWS_SYSTEM_COMMANDS.contains(&base_cmd)
     }
 
     fn is_database_operation(cmd: &str) -> bool {
@@ -141,21 +352,19 @@ impl CommandAnalyser {
 
         const DANGEROUS_COMMANDS: &[&str] = &[
             "eval", "exec", "sh", "bash", "zsh", "python", "perl", "ruby", "kill", "killall",
-            "pkill", "reboot", "shutdown", "halt", "crontab", "at", "batch",
+            "pkill", "reboot", "shutdown", "halt", "crontab", "at", "batch", "dd",
         ];
 
         DANGEROUS_PATTERNS.iter().any(|p| full_cmd.contains(p))
             || DANGEROUS_COMMANDS.contains(&base_cmd)
     }
 
-    fn check_git_command(cmd: &str) -> (bool, Option<&'static str>) {
-        let cmd_lower = cmd.to_lowercase();
-
-        if Self::is_modifying_git(&cmd_lower) {
+    fn check_git_command(cmd_lower: &str) -> (bool, Option<&'static str>) {
+        if Self::is_modifying_git(cmd_lower) {
             return (true, Some("modifies git repository or remote"));
         }
 
-        if Self::is_destructive_git(&cmd_lower) {
+        if Self::is_destructive_git(cmd_lower) {
             return (true, Some("destructive git operation"));
         }
 
@@ -240,9 +449,8 @@ mod tests {
         ];
 
         for cmd in &safe_cmds {
-            assert_eq!(
-                CommandAnalyser::requires_approval(cmd).0,
-                false,
+            assert!(
+                !CommandAnalyser::requires_approval(cmd).0,
                 "Expected '{}' to be safe",
                 cmd
             );
@@ -259,9 +467,8 @@ mod tests {
         ];
 
         for cmd in &modify_cmds {
-            assert_eq!(
+            assert!(
                 CommandAnalyser::requires_approval(cmd).0,
-                true,
                 "Expected '{}' to need approval",
                 cmd
             );
@@ -278,9 +485,8 @@ mod tests {
         ];
 
         for cmd in &pkg_cmds {
-            assert_eq!(
+            assert!(
                 CommandAnalyser::requires_approval(cmd).0,
-                true,
                 "Expected '{}' to need approval",
                 cmd
             );
@@ -297,9 +503,8 @@ mod tests {
         ];
 
         for cmd in &net_cmds {
-            assert_eq!(
+            assert!(
                 CommandAnalyser::requires_approval(cmd).0,
-                true,
                 "Expected '{}' to need approval",
                 cmd
             );
@@ -316,9 +521,8 @@ mod tests {
         ];
 
         for cmd in &sys_cmds {
-            assert_eq!(
+            assert!(
                 CommandAnalyser::requires_approval(cmd).0,
-                true,
                 "Expected '{}' to need approval",
                 cmd
             );
@@ -336,15 +540,145 @@ mod tests {
         ];
 
         for cmd in &risky_cmds {
-            assert_eq!(
+            assert!(
+                CommandAnalyser::requires_approval(cmd).0,
+                "Expected '{}' to need approval",
+                cmd
+            );
+        }
+    }
+
+    #[test]
+    fn test_windows_commands() {
+        let safe_cmds = ["Get-ChildItem", "Get-Process", "dir", "type file.txt"];
+        let risky_cmds = [
+            "Remove-Item -Recurse -Force C:\\temp",
+            "del file.txt",
+            "format C:",
+            "Stop-Computer",
+            "reg add HKCU\\Software\\Test",
+            "Set-ItemProperty -Path HKLM:\\Software\\Test -Name x -Value 1",
+        ];
+
+        for cmd in &safe_cmds {
+            assert!(
+                !CommandAnalyser::requires_approval(cmd).0,
+                "Expected '{}' to be safe",
+                cmd
+            );
+        }
+
+        for cmd in &risky_cmds {
+            assert!(
                 CommandAnalyser::requires_approval(cmd).0,
-                true,
                 "Expected '{}' to need approval",
                 cmd
             );
         }
     }
 
+    #[test]
+    fn test_interactive_commands() {
+        let interactive_cmds = [
+            "vim file.txt",
+            "sudo nano /etc/hosts",
+            "/usr/bin/vi config.yml",
+            "less /var/log/syslog",
+            "journalctl -u nginx | less",
+            "man ls",
+            "top",
+            "htop",
+            "watch -n 1 df -h",
+            "ssh server",
+            "ssh -p 2222 -i ~/.ssh/id_ed25519 user@server",
+            "python",
+            "python3",
+            "cd src && node",
+            "make test || vim Makefile",
+        ];
+        let batch_cmds = [
+            "cat file.txt",
+            "man ls | col -b",
+            "top -b -n 1",
+            "ssh server uptime",
+            "ssh -p 2222 user@server 'df -h'",
+            "python3 script.py",
+            "python -c 'print(1)'",
+            "emacs --batch -l build.el",
+            "grep -r vim .",
+        ];
+
+        for cmd in &interactive_cmds {
+            assert!(
+                CommandAnalyser::interactive_alternative(cmd).is_some(),
+                "Expected '{}' to be refused as interactive",
+                cmd
+            );
+        }
+
+        for cmd in &batch_cmds {
+            assert!(
+                CommandAnalyser::interactive_alternative(cmd).is_none(),
+                "Expected '{}' to run",
+                cmd
+            );
+        }
+    }
+
+    #[test]
+    fn test_approval_concerns() {
+        let concerns = CommandAnalyser::approval_concerns(
+            "curl -fsSL https://example.com/install.sh | sudo bash && rm -rf /tmp/install",
+        );
+        let parts: Vec<&str> = concerns.iter().map(|(part, _)| part.as_str()).collect();
+        assert!(parts.contains(&"curl -fsSL https://example.com/install.sh"));
+        assert!(parts.contains(&"rm -rf /tmp/install"));
+
+        assert!(
+            CommandAnalyser::approval_concerns("tar -tzvf backup.tar.gz | grep etc").is_empty()
+        );
+    }
+
+    #[test]
+    fn test_read_only() {
+        let read_only_cmds = [
+            "ls -la",
+            "ls && cat file.txt",
+            "ps aux | grep nginx | head",
+            "find . -name '*.rs'",
+            "git log --oneline; git status",
+        ];
+        let writing_cmds = [
+            "ls && rm -f x",
+            "ls; rm x",
+            "make test || rm -rf target",
+            "echo hi > file",
+            "echo hi >> file",
+            "cat a | tee b",
+            "find . -delete",
+            "find . -name '*.tmp' -exec rm {} +",
+            "find . | xargs rm",
+            "echo $(rm x)",
+            "env rm x",
+        ];
+
+        for cmd in &read_only_cmds {
+            assert!(
+                CommandAnalyser::is_read_only(cmd),
+                "Expected '{}' to be read-only",
+                cmd
+            );
+        }
+
+        for cmd in &writing_cmds {
+            assert!(
+                !CommandAnalyser::is_read_only(cmd),
+                "Expected '{}' to be refused in safe mode",
+                cmd
+            );
+        }
+    }
+
     #[test]
     fn test_git_commands() {
         let safe_git = ["git status", "git log", "git diff", "git branch"];
@@ -352,18 +686,16 @@ mod tests {
         let destructive_git = ["git clean -f"];
 
         for cmd in &safe_git {
-            assert_eq!(
-                CommandAnalyser::requires_approval(cmd).0,
-                false,
+            assert!(
+                !CommandAnalyser::requires_approval(cmd).0,
                 "Expected '{}' to be safe",
                 cmd
             );
         }
 
         for cmd in &modifying_git {
-            assert_eq!(
+            assert!(
                 CommandAnalyser::requires_approval(cmd).0,
-                true,
                 "Expected '{}' to need approval",
                 cmd
             );
@@ -371,7 +703,7 @@ mod tests {
 
         for cmd in &destructive_git {
             let (needs, reason) = CommandAnalyser::requires_approval(cmd);
-            assert_eq!(needs, true, "Expected '{}' to need approval", cmd);
+            assert!(needs, "Expected '{}' to need approval", cmd);
             assert_eq!(reason, Some("destructive git operation"));
         }
     }
@@ -0,0 +1,124 @@
+//! Rough size and cost of a request, so a huge one isn't sent by accident
+
+use std::env;
+
+use inquire::Confirm;
+
+use crate::{is_local_url, llm::LLMConfig, theme::icon, ENV_CONFIRM_LARGE, ENV_COST_WARN_TOKENS};
+
+const DEFAULT_COST_WARN_TOKENS: usize = 20_000;
+
+/// Input price in US dollars per million tokens, by part of the model name.
+/// The longest matching name wins, so "gpt-4o-mini" isn't priced as "gpt-4".
+const INPUT_PRICES: &[(&str, f64)] = &[
+    ("gpt-3.5-turbo", 0.5),
+    ("gpt-4", 30.0),
+    ("gpt-4-turbo", 10.0),
+    ("gpt-4o", 2.5),
+    ("gpt-4o-mini", 0.15),
+    ("gpt-4.1", 2.0),
+    ("gpt-4.1-mini", 0.4),
+    ("gpt-4.1-nano", 0.1),
+    ("claude-3-haiku", 0.25),
+    ("claude-3-5-haiku", 0.8),
+    ("claude-3-5-sonnet", 3.0),
+    ("claude-3-7-sonnet", 3.0),
+    ("claude-sonnet-4", 3.0),
+    ("claude-3-opus", 15.0),
+    ("claude-opus-4", 15.0),
+    ("deepseek-chat", 0.27),
+    ("deepseek-reasoner", 0.55),
+];
+
+/// About 4 characters per token, which is close enough for English text and logs
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Cost in US dollars, for paid providers whose price is known
+pub fn estimate_cost(llm_config: &LLMConfig, tokens: usize) -> Option<f64> {
+    if llm_config.provider == "ollama" || llm_config.base_url.as_deref().is_some_and(is_local_url) {
+        return None;
+    }
+
+    let (_, price) = INPUT_PRICES
+        .iter()
+        .filter(|(model, _)| llm_config.model.contains(model))
+        .max_by_key(|(model, _)| model.len())?;
+
+    Some(tokens as f64 * price / 1_000_000.0)
+}
+
+/// Warning shown when the text is larger than `warn_tokens`
+fn large_request_warning(llm_config: &LLMConfig, text: &str, warn_tokens: usize) -> Option<String> {
+    let tokens = estimate_tokens(text);
+    if tokens <= warn_tokens {
+        return None;
+    }
+
+    Some(match estimate_cost(llm_config, tokens) {
+        Some(cost) => format!(
+            "{}This request is about {} tokens (~${:.2} with {}).",
+            icon("⚠️  "),
+            tokens,
+            cost,
+            llm_config.model
+        ),
+        None => format!("{}This request is about {} tokens.", icon("⚠️  "), tokens),
+    })
+}
+
+/// Warn before sending a large request, and ask first with ASK_SH_CONFIRM_LARGE.
+/// Returns false when the user doesn't want to send it.
+pub fn check_large_request(llm_config: &LLMConfig, text: &str) -> bool {
+    let warn_tokens = env::var(ENV_COST_WARN_TOKENS)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_COST_WARN_TOKENS);
+
+    let Some(warning) = large_request_warning(llm_config, text, warn_tokens) else {
+        return true;
+    };
+    eprintln!("{}", warning);
+
+    let confirm = env::var(ENV_CONFIRM_LARGE).is_ok_and(|value| value == "true" || value == "1");
+    if !confirm {
+        return true;
+    }
+
+    Confirm::new("Send it anyway?")
+        .with_default(false)
+        .prompt()
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(provider: &str, model: &str) -> LLMConfig {
+        LLMConfig {
+            provider: provider.to_string(),
+            model: model.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_large_request_warning() {
+        let log = "ERROR connection refused\n".repeat(1000);
+        assert_eq!(estimate_tokens(&log), 6250);
+
+        let warning = large_request_warning(&config("openai", "gpt-4o-mini"), &log, 1000).unwrap();
+        assert!(warning.contains("about 6250 tokens (~$0.00 with gpt-4o-mini)"));
+
+        let bedrock = config("bedrock", "anthropic.claude-3-opus-20240229-v1:0");
+        let warning = large_request_warning(&bedrock, &log, 1000).unwrap();
+        assert!(warning.contains("~$0.09"));
+
+        let warning = large_request_warning(&config("ollama", "llama3"), &log, 1000).unwrap();
+        assert!(warning.ends_with("about 6250 tokens."));
+
+        assert!(large_request_warning(&config("openai", "gpt-4o"), &log, 10_000).is_none());
+    }
+}
@@ -0,0 +1,386 @@
+//! Shell functions emitted by `ask-sh --init`
+
+use std::{env, process::Command};
+
+use ask_sh::{
+    get_user_language,
+    theme::{assistant_name, icon, no_emoji, strip_emoji},
+    ENV_SELECTOR,
+};
+
+/// The script's prompts in other languages, picked from ASK_SH_LANG or LANG when running --init
+const TRANSLATIONS: &[(&str, &[(&str, &str)])] = &[(
+    "Japanese",
+    &[
+        (
+            "Hey, __ASSISTANT_NAME__ has suggested some commands that can be typed into your terminal.",
+            "__ASSISTANT_NAME__ がターミナルで使えるコマンドを提案しました。",
+        ),
+        (
+            "Press Enter to view and select the commands, or type any other key to exit:",
+            "Enter でコマンドを表示して選択、他のキーで終了します:",
+        ),
+        (
+            "__ASSISTANT_NAME__ suggested commands (Enter to use / Ctrl+C to exit):",
+            "__ASSISTANT_NAME__ の提案コマンド (Enter で使用 / Ctrl+C で終了):",
+        ),
+        (
+            "Press Enter to run update now, or type any other key to exit:",
+            "Enter で今すぐ更新、他のキーで終了します:",
+        ),
+    ],
+)];
+
+/// Function shared by bash and zsh. Lines holding a placeholder are replaced with
+/// the shell specific snippet, keeping the placeholder's indentation.
+const POSIX_FUNCTION: &str = r#"# This function is automatically generated by ask-sh --init __SHELL_ARG__
+# ask.sh shell function v2
+ask() {
+    if ! command -v ask-sh &> /dev/null; then
+        printf "❌ Necessary rust package ask-sh is installed but cannot be accessed. Rust's bin path may not be added to your PATH."
+        printf "👉 It's usually under ~/.cargo/bin/"
+        printf "👀 Please add it to your PATH and restart your shell."
+    fi
+    system_args=()
+    if [ "$1" = "--system" ]; then # use another system prompt for this question only
+        system_args=(--system "$2")
+        shift 2
+    fi
+    case " $* " in
+        *" --pipe "*) # stdin is the data the request is about
+            ask-sh "${system_args[@]}" "$@"
+            return
+            ;;
+        *" --raw "*) # print the answer only, without suggesting commands
+            echo "$@" | ask-sh "${system_args[@]}"
+            return
+            ;;
+    esac
+    if [ "$1" = "--export-last" ]; then # save the last conversation as Markdown
+        ask-sh "$@"
+        return
+    fi
+    if [ "$1" = "--list-models" ]; then # print the models of the configured provider
+        ask-sh "$@"
+        return
+    fi
+    if [ "$1" = "--tools" ]; then # print which tools the model can use
+        ask-sh "$@"
+        return
+    fi
+    copy_to_clipboard=""
+    if [ "$1" = "--copy" ]; then # copy the selected command instead of typing it
+        copy_to_clipboard=1
+        shift
+    fi
+    suggested_commands=`echo "$@" | ask-sh "${system_args[@]}" 2> >(cat 1>&2)`
+    if [ -n "$suggested_commands" ]; then
+        printf "\n" # add one empty line to create space
+        printf "👋 Hey, __ASSISTANT_NAME__ has suggested some commands that can be typed into your terminal.\n"
+        printf "🔍 Press Enter to view and select the commands, or type any other key to exit:"
+        __READ_CHAR__
+        REPLY="${REPLY#"${REPLY%%[![:space:]]*}"}"  # trim whitespaces
+        if [ -z "$REPLY" ] ; then
+            # As Enter will move cursor to the next line, we need to go back two lines
+            printf "\033[2A"
+            # \033[2K: delete current line (👋 line), \n: go next line, \033[2K: delete next line (🔍 line)
+            printf "\033[2K\n\033[2K\n"
+            # We're at the emptified 🔍 line. So, go back two lines, including empty line to make space
+            printf "\033[2A" # go back again
+            __SELECT_COMMAND__
+            if [ -n "$selected_command" ]; then
+                if [ -n "$copy_to_clipboard" ]; then
+                    printf "%s" "$selected_command" | ask-sh --copy
+                else
+                    __INSERT_COMMAND__
+                fi
+            fi
+        else
+            # We're at the end of 🔍 line. So, go back one line (👋 line)
+            printf "\033[1A"
+            printf "\033[2K\n\033[2K\n"
+            printf "\033[2A"
+        fi
+    fi
+    if [ -z "$ASK_SH_NO_UPDATE" ]; then
+        latest_version=`ask-sh --check-update`
+        current_version=`ask-sh --version`
+        if [ -n "$latest_version" ]; then
+            # clear line
+            printf "\n"
+            printf "🎉 New version of ask-sh is available! (Current: $current_version vs New: $latest_version) Set \$ASK_SH_NO_UPDATE=1 to suppress this notice.\n"
+            printf "🆙 Press Enter to run update now, or type any other key to exit:"
+            __READ_CHAR__
+            REPLY="${REPLY#"${REPLY%%[![:space:]]*}"}"  # trim whitespaces
+            if [ -z "$REPLY" ] ; then
+                cargo install --force ask-sh
+                printf "\nDone! Please restart your shell or source __RC_FILE__ to use the new version.\n"
+            else
+                printf "\nOk, you can update ask-sh later by running 'cargo install --force ask-sh'.\n"
+            fi
+        fi
+    fi
+}
+"#;
+
+const FISH_FUNCTION: &str = r#"# This function is automatically generated by ask-sh --init fish
+# ask.sh fish function v2
+function ask
+    if not command -q ask-sh
+        printf "❌ Necessary rust package ask-sh is installed but cannot be accessed. Rust's bin path may not be added to your PATH."
+        printf "👉 It's usually under ~/.cargo/bin/"
+        printf "👀 Please add it to your PATH and restart your shell."
+        return 1
+    end
+    set -l system_args
+    if test "$argv[1]" = "--system" # use another system prompt for this question only
+        set system_args --system $argv[2]
+        set -e argv[1..2]
+    end
+    if contains -- --pipe $argv # stdin is the data the request is about
+        ask-sh $system_args $argv
+        return
+    end
+    if contains -- --raw $argv # print the answer only, without suggesting commands
+        echo "$argv" | ask-sh $system_args
+        return
+    end
+    if test "$argv[1]" = "--export-last" # save the last conversation as Markdown
+        ask-sh $argv
+        return
+    end
+    if test "$argv[1]" = "--list-models" # print the models of the configured provider
+        ask-sh $argv
+        return
+    end
+    if test "$argv[1]" = "--tools" # print which tools the model can use
+        ask-sh $argv
+        return
+    end
+    set -l copy_to_clipboard
+    if test "$argv[1]" = "--copy" # copy the selected command instead of typing it
+        set copy_to_clipboard 1
+        set -e argv[1]
+    end
+    # FISH_VERSION isn't exported by fish, pass it so ask-sh can detect the shell
+    set -l suggested_commands (echo "$argv" | env FISH_VERSION=$FISH_VERSION ask-sh $system_args)
+    if test -n "$suggested_commands"
+        printf "\n" # add one empty line to create space
+        printf "👋 Hey, __ASSISTANT_NAME__ has suggested some commands that can be typed into your terminal.\n"
+        read -l -n 1 -P "🔍 Press Enter to view and select the commands, or type any other key to exit:" reply
+        set reply (string trim -- "$reply")
+        if test -z "$reply"
+            __SELECT_COMMAND__
+            if test -n "$selected_command"
+                if test -n "$copy_to_clipboard"
+                    printf "%s" "$selected_command" | ask-sh --copy
+                else
+                    # The command line can only be edited once the next prompt is shown
+                    set -g __ask_sh_selected_command $selected_command
+                    function __ask_sh_insert_command --on-event fish_prompt
+                        commandline -r -- $__ask_sh_selected_command
+                        set -e __ask_sh_selected_command
+                        functions -e __ask_sh_insert_command
+                    end
+                end
+            end
+        end
+    end
+    if not set -q ASK_SH_NO_UPDATE
+        set -l latest_version (ask-sh --check-update)
+        if test -n "$latest_version"
+            set -l current_version (ask-sh --version)
+            printf "\n"
+            printf "🎉 New version of ask-sh is available! (Current: $current_version vs New: $latest_version) Set \$ASK_SH_NO_UPDATE=1 to suppress this notice.\n"
+            read -l -n 1 -P "🆙 Press Enter to run update now, or type any other key to exit:" reply
+            set reply (string trim -- "$reply")
+            if test -z "$reply"
+                cargo install --force ask-sh
+                printf "\nDone! Please restart your shell or source ~/.config/fish/config.fish to use the new version.\n"
+            else
+                printf "\nOk, you can update ask-sh later by running 'cargo install --force ask-sh'.\n"
+            end
+        end
+    end
+end
+"#;
+
+/// The program that lets the user pick one of the suggested commands, from ASK_SH_SELECTOR
+enum Selector {
+    Peco,
+    Fzf,
+    /// The commands are listed, for the user to copy
+    None,
+}
+
+impl Selector {
+    /// peco when ASK_SH_SELECTOR isn't set, as it's always been, unless only fzf is installed
+    fn from_env() -> Self {
+        match env::var(ENV_SELECTOR).unwrap_or_default().trim() {
+            "peco" => Selector::Peco,
+            "fzf" => Selector::Fzf,
+            "none" => Selector::None,
+            value => {
+                if !value.is_empty() {
+                    eprintln!(
+                        "{}{} must be peco, fzf or none, using what's installed",
+                        icon("⚠️  "),
+                        ENV_SELECTOR
+                    );
+                }
+                match (is_installed("peco"), is_installed("fzf")) {
+                    (false, true) => Selector::Fzf,
+                    (false, false) => Selector::None,
+                    _ => Selector::Peco,
+                }
+            }
+        }
+    }
+
+    /// Sets `selected_command` from the commands in `suggested_commands`
+    fn posix_snippet(&self) -> &'static str {
+        match self {
+            Selector::Peco => {
+                r#"selected_command=`echo "$suggested_commands" | peco --prompt "__ASSISTANT_NAME__ suggested commands (Enter to use / Ctrl+C to exit):"`"#
+            }
+            Selector::Fzf => {
+                r#"selected_command=`echo "$suggested_commands" | fzf --height=40% --reverse --header "__ASSISTANT_NAME__ suggested commands (Enter to use / Ctrl+C to exit):"`"#
+            }
+            Selector::None => {
+                r#"echo "$suggested_commands" | awk '{ printf "%2d. %s\n", NR, $0 }'
+selected_command="""#
+            }
+        }
+    }
+
+    fn fish_snippet(&self) -> &'static str {
+        match self {
+            Selector::Peco => {
+                r#"set -l selected_command (printf "%s\n" $suggested_commands | peco --prompt "__ASSISTANT_NAME__ suggested commands (Enter to use / Ctrl+C to exit):")"#
+            }
+            Selector::Fzf => {
+                r#"set -l selected_command (printf "%s\n" $suggested_commands | fzf --height=40% --reverse --header "__ASSISTANT_NAME__ suggested commands (Enter to use / Ctrl+C to exit):")"#
+            }
+            Selector::None => {
+                r#"printf "%s\n" $suggested_commands | awk '{ printf "%2d. %s\n", NR, $0 }'
+set -l selected_command"#
+            }
+        }
+    }
+}
+
+fn is_installed(program: &str) -> bool {
+    Command::new(program)
+        .arg("--version")
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+pub enum Shell {
+    /// Works in both bash and zsh by checking the shell at runtime
+    Combined,
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    pub fn from_arg(arg: Option<&str>) -> Option<Self> {
+        match arg {
+            None => Some(Shell::Combined),
+            Some("bash") => Some(Shell::Bash),
+            Some("zsh") => Some(Shell::Zsh),
+            Some("fish") => Some(Shell::Fish),
+            Some(_) => None,
+        }
+    }
+}
+
+/// The emojis are left out with ASK_SH_NO_EMOJI
+pub fn get_init_script(shell: &Shell) -> String {
+    // The name ends up in double quoted strings of the script
+    let name = assistant_name().replace(['"', '`', '$', '\\'], "");
+    let script = localize(get_shell_function(shell), get_user_language().as_deref())
+        .replace("__ASSISTANT_NAME__", &name);
+
+    match no_emoji() {
+        true => strip_emoji(&script),
+        false => script,
+    }
+}
+
+/// Messages without a translation stay in English
+fn localize(script: String, language: Option<&str>) -> String {
+    let Some((_, translations)) = TRANSLATIONS
+        .iter()
+        .find(|(name, _)| Some(*name) == language)
+    else {
+        return script;
+    };
+
+    translations
+        .iter()
+        .fold(script, |script, (english, translated)| {
+            script.replace(english, translated)
+        })
+}
+
+fn get_shell_function(shell: &Shell) -> String {
+    let selector = Selector::from_env();
+    let (shell_arg, rc_file, read_char, insert_command) = match shell {
+        Shell::Fish => {
+            return fill_placeholder(FISH_FUNCTION, "__SELECT_COMMAND__", selector.fish_snippet())
+        }
+        Shell::Combined => (
+            "",
+            "~/.zshrc or ~/.bashrc etc...",
+            r#"if [ -n "$ZSH_VERSION" ]; then # read a single char
+    read -r -k 1 REPLY # zsh
+else
+    read -r -n 1 REPLY # bash
+fi"#,
+            r#"if ! print -z $selected_command 2>/dev/null; then
+    history -s $selected_command
+fi"#,
+        ),
+        Shell::Bash => (
+            " bash",
+            "~/.bashrc",
+            "read -r -n 1 REPLY # read a single char",
+            r#"history -s "$selected_command" # press ↑ to use the command"#,
+        ),
+        Shell::Zsh => (
+            " zsh",
+            "~/.zshrc",
+            "read -r -k 1 REPLY # read a single char",
+            r#"print -z "$selected_command" # type the command into the prompt"#,
+        ),
+    };
+
+    let script = POSIX_FUNCTION
+        .replace(" __SHELL_ARG__", shell_arg)
+        .replace("__RC_FILE__", rc_file);
+    let script = fill_placeholder(&script, "__READ_CHAR__", read_char);
+    let script = fill_placeholder(&script, "__SELECT_COMMAND__", selector.posix_snippet());
+    fill_placeholder(&script, "__INSERT_COMMAND__", insert_command)
+}
+
+fn fill_placeholder(script: &str, placeholder: &str, snippet: &str) -> String {
+    let mut filled = String::new();
+
+    for line in script.lines() {
+        if line.trim() == placeholder {
+            let indent = &line[..line.len() - line.trim_start().len()];
+            for snippet_line in snippet.lines() {
+                filled.push_str(indent);
+                filled.push_str(snippet_line);
+                filled.push('\n');
+            }
+        } else {
+            filled.push_str(line);
+            filled.push('\n');
+        }
+    }
+
+    filled
+}
@@ -0,0 +1,464 @@
+//! ask.sh as a library: the LLM providers, tools and chat orchestration
+//! used by the `ask-sh` binary.
+
+pub mod audit_log;
+pub mod chat_handler;
+pub mod clipboard;
+pub mod command_analyser;
+pub mod cost;
+mod http_client;
+pub mod llm;
+mod process_command_executor;
+mod prompts;
+pub mod redact;
+pub mod renderer;
+pub mod theme;
+mod tmux_command_executor;
+pub mod tools;
+pub mod transcript;
+pub mod update_check;
+mod user_context;
+mod user_system_info;
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+pub use chat_handler::ChatHandler;
+pub use command_analyser::CommandAnalyser;
+pub use llm::{
+    create_llm_provider, ChatResponse, LLMConfig, LLMError, LLMProvider, Message, Provider,
+    SUPPORTED_PROVIDERS,
+};
+pub use tools::{
+    execute_tool, execute_tool_calls, get_available_tools, FunctionCall, FunctionDef, Tool,
+    ToolCall, ToolCallResult, ToolError,
+};
+pub use user_system_info::get_user_language;
+
+use llm::model_limits::model_limits;
+
+// LLM provider settings
+pub const ENV_LLM_PROVIDER: &str = "ASK_SH_LLM_PROVIDER";
+pub const ENV_OPENAI_API_KEY: &str = "ASK_SH_OPENAI_API_KEY";
+pub const ENV_OPENAI_MODEL: &str = "ASK_SH_OPENAI_MODEL";
+pub const ENV_OPENAI_BASE_URL: &str = "ASK_SH_OPENAI_BASE_URL";
+pub const ENV_OPENAI_ORG: &str = "ASK_SH_OPENAI_ORG";
+pub const ENV_OPENAI_PROJECT: &str = "ASK_SH_OPENAI_PROJECT";
+pub const ENV_MAX_TOKENS: &str = "ASK_SH_MAX_TOKENS";
+pub const ENV_STOP: &str = "ASK_SH_STOP";
+pub const ENV_EXTRA_BODY: &str = "ASK_SH_EXTRA_BODY";
+pub const ENV_REASONING_EFFORT: &str = "ASK_SH_REASONING_EFFORT";
+pub const ENV_VERBOSITY: &str = "ASK_SH_VERBOSITY";
+pub const ENV_ANTHROPIC_API_KEY: &str = "ASK_SH_ANTHROPIC_API_KEY";
+pub const ENV_ANTHROPIC_MODEL: &str = "ASK_SH_ANTHROPIC_MODEL";
+pub const ENV_ANTHROPIC_CACHE: &str = "ASK_SH_ANTHROPIC_CACHE";
+pub const ENV_ANTHROPIC_VERSION: &str = "ASK_SH_ANTHROPIC_VERSION";
+pub const ENV_ANTHROPIC_BETA: &str = "ASK_SH_ANTHROPIC_BETA";
+pub const ENV_OLLAMA_BASE_URL: &str = "ASK_SH_OLLAMA_BASE_URL";
+pub const ENV_OLLAMA_MODEL: &str = "ASK_SH_OLLAMA_MODEL";
+pub const ENV_OLLAMA_KEEP_ALIVE: &str = "ASK_SH_OLLAMA_KEEP_ALIVE";
+pub const ENV_OLLAMA_CONTEXT_LENGTH: &str = "ASK_SH_OLLAMA_CONTEXT_LENGTH";
+pub const ENV_BEDROCK_MODEL: &str = "ASK_SH_BEDROCK_MODEL";
+pub const ENV_DEEPSEEK_API_KEY: &str = "ASK_SH_DEEPSEEK_API_KEY";
+pub const ENV_DEEPSEEK_MODEL: &str = "ASK_SH_DEEPSEEK_MODEL";
+pub const ENV_SEARXNG_BASE_URL: &str = "ASK_SH_SEARXNG_BASE_URL";
+pub const ENV_SEARCH_CACHE_SIZE: &str = "ASK_SH_SEARCH_CACHE_SIZE";
+pub const ENV_SEARCH_MAX_CHARS: &str = "ASK_SH_SEARCH_MAX_CHARS";
+pub const ENV_HISTORY_LINES: &str = "ASK_SH_HISTORY_LINES";
+pub const ENV_HISTORY_WINDOW: &str = "ASK_SH_HISTORY_WINDOW";
+pub const ENV_STRUCTURED_OUTPUT: &str = "ASK_SH_STRUCTURED_OUTPUT";
+pub const ENV_CONTEXT_FILE: &str = "ASK_SH_CONTEXT_FILE";
+pub const ENV_LANG: &str = "ASK_SH_LANG";
+pub const ENV_PROXY: &str = "ASK_SH_PROXY";
+pub const ENV_CA_CERT: &str = "ASK_SH_CA_CERT";
+pub const ENV_USER_AGENT: &str = "ASK_SH_USER_AGENT";
+pub const ENV_STREAM_IDLE_TIMEOUT: &str = "ASK_SH_STREAM_IDLE_TIMEOUT";
+pub const ENV_DANGER_ACCEPT_INVALID_CERTS: &str = "ASK_SH_DANGER_ACCEPT_INVALID_CERTS";
+pub const ENV_COST_WARN_TOKENS: &str = "ASK_SH_COST_WARN_TOKENS";
+pub const ENV_CONFIRM_LARGE: &str = "ASK_SH_CONFIRM_LARGE";
+
+// Command execution settings
+pub const ENV_COMMAND_TIMEOUT: &str = "ASK_SH_COMMAND_TIMEOUT";
+pub const ENV_AUTO_APPROVE: &str = "ASK_SH_AUTO_APPROVE";
+pub const ENV_APPROVE_DEFAULT: &str = "ASK_SH_APPROVE_DEFAULT";
+pub const ENV_NEVER_APPROVE: &str = "ASK_SH_NEVER_APPROVE";
+pub const ENV_BATCH_APPROVE: &str = "ASK_SH_BATCH_APPROVE";
+pub const ENV_APPROVE_ONCE: &str = "ASK_SH_APPROVE_ONCE";
+pub const ENV_APPROVAL_HOOK: &str = "ASK_SH_APPROVAL_HOOK";
+pub const ENV_SAFE_MODE: &str = "ASK_SH_SAFE_MODE";
+pub const ENV_MAX_OUTPUT_CHARS: &str = "ASK_SH_MAX_OUTPUT_CHARS";
+pub const ENV_SUMMARIZE_OUTPUT: &str = "ASK_SH_SUMMARIZE_OUTPUT";
+pub const ENV_DIFF_OUTPUT: &str = "ASK_SH_DIFF_OUTPUT";
+pub const ENV_ATTACH_TMUX: &str = "ASK_SH_ATTACH_TMUX";
+pub const ENV_STREAM_COMMAND_OUTPUT: &str = "ASK_SH_STREAM_COMMAND_OUTPUT";
+pub const ENV_KEEP_STDIN: &str = "ASK_SH_KEEP_STDIN";
+pub const ENV_MAX_TOOL_ITERATIONS: &str = "ASK_SH_MAX_TOOL_ITERATIONS";
+pub const ENV_AUDIT_LOG: &str = "ASK_SH_AUDIT_LOG";
+
+// Output settings
+pub const ENV_RENDERER: &str = "ASK_SH_RENDERER";
+pub const ENV_SELECTOR: &str = "ASK_SH_SELECTOR";
+pub const ENV_FLUSH_INTERVAL_MS: &str = "ASK_SH_FLUSH_INTERVAL_MS";
+pub const ENV_NO_RERENDER: &str = "ASK_SH_NO_RERENDER";
+pub const ENV_QUIET: &str = "ASK_SH_QUIET";
+pub const ENV_DEBUG: &str = "ASK_SH_DEBUG";
+pub const ENV_NO_EMOJI: &str = "ASK_SH_NO_EMOJI";
+pub const ENV_ASSISTANT_NAME: &str = "ASK_SH_ASSISTANT_NAME";
+
+// Update check settings
+pub const ENV_NO_UPDATE: &str = "ASK_SH_NO_UPDATE";
+
+/// Build the LLM configuration from the ASK_SH_* environment variables
+pub fn get_llm_config() -> Result<LLMConfig, LLMError> {
+    // Select provider (default is OpenAI)
+    let provider = env::var(ENV_LLM_PROVIDER).unwrap_or_else(|_| "openai".to_string());
+
+    let max_tokens = get_max_tokens()?;
+
+    let stop: Option<Vec<String>> = env::var(ENV_STOP).ok().and_then(|value| {
+        let sequences: Vec<String> = value
+            .split(',')
+            .filter(|sequence| !sequence.is_empty())
+            .map(unescape_stop)
+            .collect();
+        (!sequences.is_empty()).then_some(sequences)
+    });
+
+    let extra_body = get_extra_body()?;
+
+    let mut config = match provider.as_str() {
+        "openai" => {
+            let base_url = env::var(ENV_OPENAI_BASE_URL).ok();
+
+            // Local OpenAI-compatible servers like LM Studio or vLLM usually don't need a key
+            let api_key = match base_url.as_deref().is_some_and(is_local_url) {
+                true => env::var(ENV_OPENAI_API_KEY).unwrap_or_default(),
+                false => get_required_env(ENV_OPENAI_API_KEY, &provider)?,
+            };
+
+            let model = env::var(ENV_OPENAI_MODEL).unwrap_or_else(|_| "gpt-3.5-turbo".to_string());
+
+            LLMConfig {
+                provider,
+                api_key,
+                model,
+                base_url,
+                keep_alive: None,
+                context_length: None,
+                max_tokens,
+                stop,
+                extra_body,
+                tools: None, // Some(tools::get_available_tools()),
+            }
+        }
+        "anthropic" => {
+            let api_key = get_required_env(ENV_ANTHROPIC_API_KEY, &provider)?;
+
+            let model = env::var(ENV_ANTHROPIC_MODEL)
+                .unwrap_or_else(|_| "claude-3-5-sonnet-latest".to_string());
+
+            LLMConfig {
+                provider,
+                api_key,
+                model,
+                base_url: None, // Anthropic does not support custom endpoints
+                keep_alive: None,
+                context_length: None,
+                max_tokens,
+                stop,
+                extra_body,
+                tools: Some(tools::get_available_tools()),
+            }
+        }
+        "deepseek" => {
+            // DeepSeek's API is OpenAI compatible
+            let api_key = get_required_env(ENV_DEEPSEEK_API_KEY, &provider)?;
+
+            let model =
+                env::var(ENV_DEEPSEEK_MODEL).unwrap_or_else(|_| "deepseek-chat".to_string());
+
+            LLMConfig {
+                provider,
+                api_key,
+                model,
+                base_url: Some("https://api.deepseek.com".to_string()),
+                keep_alive: None,
+                context_length: None,
+                max_tokens,
+                stop,
+                extra_body,
+                tools: None,
+            }
+        }
+        "ollama" => {
+            let api_key = "ollama dummy key".to_string();
+
+            let model = env::var(ENV_OLLAMA_MODEL).unwrap_or_else(|_| "gemma3:4b".to_string());
+
+            let base_url = env::var(ENV_OLLAMA_BASE_URL).ok();
+
+            let keep_alive: Option<i32> = env::var(ENV_OLLAMA_KEEP_ALIVE)
+                .ok()
+                .and_then(|s| s.parse().ok());
+
+            let context_length: Option<u32> = env::var(ENV_OLLAMA_CONTEXT_LENGTH)
+                .ok()
+                .and_then(|s| s.parse().ok());
+
+            LLMConfig {
+                provider,
+                api_key,
+                model,
+                base_url,
+                keep_alive,
+                context_length,
+                max_tokens,
+                stop,
+                extra_body,
+                tools: Some(tools::get_available_tools()),
+            }
+        }
+        "bedrock" => {
+            // Credentials come from the standard AWS environment variables or profile
+            let api_key = String::new();
+
+            let model = env::var(ENV_BEDROCK_MODEL)
+                .unwrap_or_else(|_| "anthropic.claude-3-5-sonnet-20240620-v1:0".to_string());
+
+            LLMConfig {
+                provider,
+                api_key,
+                model,
+                base_url: None,
+                keep_alive: None,
+                context_length: None,
+                max_tokens,
+                stop,
+                extra_body,
+                tools: None,
+            }
+        }
+        _ => {
+            return Err(LLMError::ConfigError(format!(
+                "Unknown provider '{}' in {}. Supported providers: {}",
+                provider,
+                ENV_LLM_PROVIDER,
+                SUPPORTED_PROVIDERS.join(", ")
+            )))
+        }
+    };
+
+    // What suits the model, unless ASK_SH_MAX_TOKENS etc. say otherwise
+    let limits = model_limits(&config.model);
+    config.context_length = config.context_length.or(Some(limits.context_length));
+    // OpenAI-compatible servers pick a fitting limit themselves, only an explicit one is sent
+    if !matches!(config.provider.as_str(), "openai" | "deepseek") {
+        config.max_tokens = config.max_tokens.or(Some(limits.max_tokens));
+    }
+
+    Ok(config)
+}
+
+/// Stop sequences are mostly about line breaks, e.g. "\n```", which a shell can't easily export
+fn unescape_stop(sequence: &str) -> String {
+    sequence.replace("\\n", "\n").replace("\\t", "\t")
+}
+
+/// ASK_SH_MAX_TOKENS, the longest answer in tokens
+fn get_max_tokens() -> Result<Option<u32>, LLMError> {
+    let Some(max_tokens) = env::var(ENV_MAX_TOKENS)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+    else {
+        return Ok(None);
+    };
+
+    max_tokens.trim().parse().map(Some).map_err(|_| {
+        LLMError::ConfigError(format!(
+            "{} must be a number of tokens, not \"{}\"",
+            ENV_MAX_TOKENS, max_tokens
+        ))
+    })
+}
+
+/// ASK_SH_EXTRA_BODY, a JSON object of fields to add to every request
+fn get_extra_body() -> Result<Option<serde_json::Map<String, serde_json::Value>>, LLMError> {
+    let Some(extra_body) = env::var(ENV_EXTRA_BODY)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+    else {
+        return Ok(None);
+    };
+
+    match serde_json::from_str(&extra_body) {
+        Ok(serde_json::Value::Object(fields)) => Ok(Some(fields)),
+        Ok(_) => Err(LLMError::ConfigError(format!(
+            "{} must be a JSON object, like '{{\"seed\": 42}}'",
+            ENV_EXTRA_BODY
+        ))),
+        Err(e) => Err(LLMError::ConfigError(format!(
+            "{} is not valid JSON: {}",
+            ENV_EXTRA_BODY, e
+        ))),
+    }
+}
+
+/// Read a variable the selected provider can't work without.
+/// When it is missing but another provider's key is set, suggest switching provider.
+fn get_required_env(variable: &str, provider: &str) -> Result<String, LLMError> {
+    if let Some(value) = env::var(variable)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+    {
+        return Ok(value);
+    }
+
+    let mut message = format!(
+        "{} is not set. It is required by the '{}' provider.",
+        variable, provider
+    );
+
+    let other_provider = [
+        ("openai", ENV_OPENAI_API_KEY),
+        ("anthropic", ENV_ANTHROPIC_API_KEY),
+        ("deepseek", ENV_DEEPSEEK_API_KEY),
+    ]
+    .into_iter()
+    .find(|(name, key)| *name != provider && env::var(key).is_ok());
+    if let Some((name, key)) = other_provider {
+        message.push_str(&format!(
+            " {} is set though, did you mean {}={}?",
+            key, ENV_LLM_PROVIDER, name
+        ));
+    }
+
+    Err(LLMError::ConfigError(message))
+}
+
+/// Directory of the ask-sh config file (~/.config/ask-sh, respecting XDG_CONFIG_HOME)
+pub(crate) fn get_config_dir() -> Option<PathBuf> {
+    let config_dir = env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|home| Path::new(&home).join(".config")))
+        .ok()?;
+
+    Some(config_dir.join("ask-sh"))
+}
+
+/// Load ~/.config/ask-sh/.env, so API keys are found whatever the current directory.
+/// Variables already set are kept: the shell and the current directory's .env win over it.
+pub fn load_config_env() {
+    if let Some(config_dir) = get_config_dir() {
+        dotenvy::from_path(config_dir.join(".env")).ok();
+    }
+}
+
+/// Directory for files ask-sh keeps between runs (~/.cache/ask-sh, respecting XDG_CACHE_HOME)
+pub(crate) fn get_cache_dir() -> Option<PathBuf> {
+    let cache_dir = env::var("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|home| Path::new(&home).join(".cache")))
+        .ok()?;
+
+    Some(cache_dir.join("ask-sh"))
+}
+
+/// Debug output goes to stderr, with secrets redacted
+pub fn is_debug_enabled() -> bool {
+    env::var(ENV_DEBUG).is_ok_and(|value| value == "true" || value == "1")
+}
+
+/// Whether the URL points at a server on this machine
+fn is_local_url(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|url| {
+        matches!(
+            url.host_str(),
+            Some("localhost" | "127.0.0.1" | "0.0.0.0" | "[::1]")
+        )
+    })
+}
+
+/// Directory for data ask-sh keeps for the user (~/.local/share/ask-sh, respecting XDG_DATA_HOME)
+pub(crate) fn get_data_dir() -> Option<PathBuf> {
+    let data_dir = env::var("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|home| Path::new(&home).join(".local").join("share")))
+        .ok()?;
+
+    Some(data_dir.join("ask-sh"))
+}
+
+/// Tests that set ASK_SH_* variables and read them back hold this lock, as tests run in parallel
+#[cfg(test)]
+pub(crate) static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_limits_unless_configured() {
+        let _env_lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        env::set_var(ENV_LLM_PROVIDER, "ollama");
+        env::set_var(ENV_OLLAMA_MODEL, "llama3.1:8b");
+        env::remove_var(ENV_OLLAMA_CONTEXT_LENGTH);
+        let defaults = get_llm_config().unwrap();
+
+        env::set_var(ENV_OLLAMA_CONTEXT_LENGTH, "4096");
+        env::set_var(ENV_MAX_TOKENS, "512");
+        let configured = get_llm_config().unwrap();
+        env::remove_var(ENV_LLM_PROVIDER);
+        env::remove_var(ENV_OLLAMA_MODEL);
+        env::remove_var(ENV_OLLAMA_CONTEXT_LENGTH);
+        env::remove_var(ENV_MAX_TOKENS);
+
+        env::set_var(ENV_OPENAI_API_KEY, "test-key");
+        env::set_var(ENV_OPENAI_MODEL, "gpt-5");
+        let openai = get_llm_config().unwrap();
+        env::remove_var(ENV_OPENAI_API_KEY);
+        env::remove_var(ENV_OPENAI_MODEL);
+
+        assert_eq!(defaults.context_length, Some(32_768));
+        assert_eq!(defaults.max_tokens, Some(4096));
+        assert_eq!(configured.context_length, Some(4096));
+        assert_eq!(configured.max_tokens, Some(512));
+        // Left to the server, the table is only a guess
+        assert_eq!(openai.max_tokens, None);
+    }
+
+    #[test]
+    fn test_invalid_max_tokens() {
+        let _env_lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        env::set_var(ENV_LLM_PROVIDER, "ollama");
+        env::set_var(ENV_MAX_TOKENS, "abc");
+        let result = get_llm_config();
+        env::remove_var(ENV_LLM_PROVIDER);
+        env::remove_var(ENV_MAX_TOKENS);
+
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains(ENV_MAX_TOKENS), "{}", error);
+    }
+
+    #[test]
+    fn test_load_config_env_keeps_set_variables() {
+        let _env_lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let config_home = env::temp_dir().join(format!("ask-sh-config-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(config_home.join("ask-sh")).unwrap();
+        std::fs::write(
+            config_home.join("ask-sh").join(".env"),
+            "ASK_SH_TEST_FROM_CONFIG=config\nASK_SH_TEST_ALREADY_SET=config\n",
+        )
+        .unwrap();
+
+        env::set_var("XDG_CONFIG_HOME", &config_home);
+        env::set_var("ASK_SH_TEST_ALREADY_SET", "cwd");
+        load_config_env();
+        env::remove_var("XDG_CONFIG_HOME");
+
+        assert_eq!(env::var("ASK_SH_TEST_FROM_CONFIG").unwrap(), "config");
+        assert_eq!(env::var("ASK_SH_TEST_ALREADY_SET").unwrap(), "cwd");
+        env::remove_var("ASK_SH_TEST_FROM_CONFIG");
+        env::remove_var("ASK_SH_TEST_ALREADY_SET");
+        std::fs::remove_dir_all(config_home).unwrap();
+    }
+}
@@ -2,28 +2,59 @@ use async_trait::async_trait;
 use futures::stream::StreamExt;
 use reqwest::{header, Client};
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use std::{env, fmt::Debug};
 
-use crate::llm::{ChatResponse, Message};
+use crate::{
+    http_client::{self, LLM_REQUEST_TIMEOUT},
+    is_debug_enabled,
+    llm::{ChatResponse, Message},
+    theme::icon,
+    ENV_ANTHROPIC_BETA, ENV_ANTHROPIC_CACHE, ENV_ANTHROPIC_VERSION,
+};
 
-use super::{ChatStream, LLMConfig, LLMError, LLMProvider};
+use super::{
+    history::windowed_history,
+    image, merge_extra_body,
+    retry::{retry_after, send_with_retry},
+    ChatStream, LLMConfig, LLMError, LLMProvider,
+};
 
 const ANTHROPIC_API_URL: &str = "https://api.anthropic.com/v1/messages";
+const ANTHROPIC_MODELS_URL: &str = "https://api.anthropic.com/v1/models?limit=1000";
+const PROMPT_CACHING_BETA: &str = "prompt-caching-2024-07-31";
+/// The `anthropic-version` header sent when ASK_SH_ANTHROPIC_VERSION isn't set
+const DEFAULT_ANTHROPIC_VERSION: &str = "2023-06-01";
+/// Anthropic requires a limit, this one is used when ASK_SH_MAX_TOKENS isn't set
+const DEFAULT_MAX_TOKENS: u32 = 4096;
 
 #[derive(Debug)]
 pub struct AnthropicProvider {
     client: Client,
     model: String,
     api_key: String,
+    system_prompt: Option<String>,
     conversation_history: Vec<Message>,
+    max_tokens: u32,
+    stop: Option<Vec<String>>,
+    extra_body: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Cache the system prompt with ASK_SH_ANTHROPIC_CACHE, so it isn't billed in full every turn
+    prompt_cache: bool,
+    /// The `anthropic-version` header, from ASK_SH_ANTHROPIC_VERSION
+    api_version: String,
+    /// Comma separated beta features from ASK_SH_ANTHROPIC_BETA, for the `anthropic-beta` header
+    beta: Option<String>,
 }
 
 #[derive(Serialize, Debug)]
 struct AnthropicRequest {
     model: String,
-    messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<serde_json::Value>,
+    messages: Vec<serde_json::Value>,
     stream: bool,
     max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_sequences: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -31,120 +62,174 @@ struct AnthropicStreamEvent {
     #[serde(rename = "type")]
     event_type: String,
     delta: Option<Delta>,
+    usage: Option<Usage>,
+    error: Option<StreamError>,
 }
 
 #[derive(Deserialize, Debug)]
 struct Delta {
     text: Option<String>,
+    stop_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Usage {
+    output_tokens: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct StreamError {
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ModelList {
+    data: Vec<ModelInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ModelInfo {
+    id: String,
 }
 
 impl AnthropicProvider {
     pub fn new(config: LLMConfig) -> Result<Self, LLMError> {
-        let client = Client::builder()
-            .build()
+        let client = http_client::build_client(LLM_REQUEST_TIMEOUT)
             .map_err(|e| LLMError::ConfigError(e.to_string()))?;
 
         Ok(Self {
             client,
             model: config.model,
             api_key: config.api_key,
+            system_prompt: None,
             conversation_history: Vec::new(),
+            max_tokens: config.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            stop: config.stop,
+            extra_body: config.extra_body,
+            prompt_cache: env::var(ENV_ANTHROPIC_CACHE)
+                .is_ok_and(|value| value == "true" || value == "1"),
+            api_version: env::var(ENV_ANTHROPIC_VERSION)
+                .ok()
+                .filter(|version| !version.trim().is_empty())
+                .unwrap_or_else(|| DEFAULT_ANTHROPIC_VERSION.to_string()),
+            beta: env::var(ENV_ANTHROPIC_BETA)
+                .ok()
+                .filter(|beta| !beta.trim().is_empty()),
         })
     }
 
-    fn parse_sse_line(line: &str) -> Option<String> {
-        if line.is_empty() || line.starts_with(':') {
-            return None;
+    /// Headers sent with every request to the API
+    fn api_headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![
+            ("x-api-key", self.api_key.clone()),
+            ("anthropic-version", self.api_version.clone()),
+        ];
+
+        let betas: Vec<&str> = self
+            .prompt_cache
+            .then_some(PROMPT_CACHING_BETA)
+            .into_iter()
+            .chain(self.beta.iter().flat_map(|beta| beta.split(',')))
+            .map(str::trim)
+            .filter(|beta| !beta.is_empty())
+            .collect();
+        if !betas.is_empty() {
+            headers.push(("anthropic-beta", betas.join(",")));
         }
 
-        if let Some(data) = line.strip_prefix("data: ") {
-            if data.trim() == "[DONE]" {
-                return None;
-            }
+        headers
+    }
 
-            if let Ok(event) = serde_json::from_str::<AnthropicStreamEvent>(data) {
-                if event.event_type == "content_block_delta" {
-                    if let Some(delta) = event.delta {
-                        return delta.text;
-                    }
-                }
-            }
+    fn build_request(&self) -> AnthropicRequest {
+        // A cache breakpoint can only be set on a block of text
+        let system = self
+            .system_prompt
+            .as_ref()
+            .map(|prompt| match self.prompt_cache {
+                true => serde_json::json!([{
+                    "type": "text",
+                    "text": prompt,
+                    "cache_control": { "type": "ephemeral" },
+                }]),
+                false => serde_json::Value::String(prompt.clone()),
+            });
+
+        // Anthropic only accepts user and assistant turns, tool results are sent as user turns
+        let messages = windowed_history(&self.conversation_history, |message| &message.role)
+            .iter()
+            .map(|message| {
+                let role = match message.role.as_str() {
+                    "assistant" => "assistant",
+                    _ => "user",
+                };
+                serde_json::json!({
+                    "role": role,
+                    "content": image::anthropic_content(&message.content, &message.images),
+                })
+            })
+            .collect();
+
+        AnthropicRequest {
+            model: self.model.clone(),
+            system,
+            messages,
+            stream: true,
+            max_tokens: self.max_tokens,
+            stop_sequences: self.stop.clone(),
         }
-        None
     }
 }
 
 #[async_trait]
 impl LLMProvider for AnthropicProvider {
-    /// Add a system message at the start of the conversation
+    /// Anthropic takes the system prompt separately from the conversation
     fn with_system_prompt(&mut self, prompt: &str) {
-        self.conversation_history.push(Message {
-            role: "system".to_string(),
-            content: prompt.to_string(),
-            ..Default::default()
-        });
+        self.system_prompt = Some(prompt.to_string());
     }
 
     async fn chat_stream(&mut self, user_message: &Message) -> Result<ChatStream, LLMError> {
         // Add user message to history
         self.conversation_history.push(user_message.clone());
 
-        let request = AnthropicRequest {
-            model: self.model.clone(),
-            messages: self.conversation_history.clone(),
-            stream: true,
-            max_tokens: 4096,
-        };
+        let mut request = serde_json::to_value(self.build_request())
+            .map_err(|e| LLMError::InvalidRequestError(e.to_string()))?;
+        merge_extra_body(&mut request, self.extra_body.as_ref());
 
-        let response = self
-            .client
-            .post(ANTHROPIC_API_URL)
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| LLMError::NetworkError(e.to_string()))?;
+        let response = send_with_retry(|| {
+            self.api_headers().into_iter().fold(
+                self.client
+                    .post(ANTHROPIC_API_URL)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .json(&request),
+                |request_builder, (name, value)| request_builder.header(name, value),
+            )
+        })
+        .await
+        .map_err(|e| LLMError::NetworkError(e.to_string()))?;
 
         if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let retry_after = retry_after(&response);
             let error_text = response
                 .text()
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(LLMError::ApiError(format!(
-                "Anthropic API error: {}",
-                error_text
-            )));
+            return Err(LLMError::from_status(
+                status,
+                retry_after,
+                format!("Anthropic API error: {}", error_text),
+            ));
         }
 
-        let stream = response.bytes_stream().map(move |result| match result {
-            Ok(bytes) => {
-                let text = String::from_utf8_lossy(&bytes);
-                let mut content = String::new();
-
-                for line in text.lines() {
-                    if let Some(text) = Self::parse_sse_line(line) {
-                        content.push_str(&text);
-                    }
-                }
-
-                if !content.is_empty() {
-                    let chat_response = ChatResponse {
-                        content: content,
-                        tool_calls: None,
-                    };
-                    Ok(chat_response)
-                } else {
-                    let chat_response = ChatResponse {
-                        content: String::new(),
-                        tool_calls: None,
-                    };
-                    Ok(chat_response)
-                }
-            }
-            Err(e) => Err(LLMError::NetworkError(e.to_string())),
-        });
+        let stream = response
+            .bytes_stream()
+            .scan(EventParser::default(), |parser, result| {
+                let chat_response = match result {
+                    Ok(bytes) => parser.push(&bytes),
+                    Err(e) => Err(LLMError::NetworkError(e.to_string())),
+                };
+                futures::future::ready(Some(chat_response))
+            });
 
         let filtered_stream = stream.filter(|result| {
             futures::future::ready(match result {
@@ -155,6 +240,133 @@ impl LLMProvider for AnthropicProvider {
 
         Ok(Box::pin(filtered_stream))
     }
+
+    async fn list_models(&self) -> Result<Vec<String>, LLMError> {
+        let response = send_with_retry(|| {
+            self.api_headers().into_iter().fold(
+                self.client.get(ANTHROPIC_MODELS_URL),
+                |request_builder, (name, value)| request_builder.header(name, value),
+            )
+        })
+        .await
+        .map_err(|e| LLMError::NetworkError(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let retry_after = retry_after(&response);
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(LLMError::from_status(
+                status,
+                retry_after,
+                format!("Anthropic API error: {}", error_text),
+            ));
+        }
+
+        let model_list: ModelList = response
+            .json()
+            .await
+            .map_err(|e| LLMError::ApiError(e.to_string()))?;
+
+        Ok(model_list.data.into_iter().map(|model| model.id).collect())
+    }
+
+    fn supports_tools(&self) -> bool {
+        // Tool definitions aren't sent in Anthropic requests yet
+        false
+    }
+    /// The system prompt is kept apart from the conversation
+    fn clear_history(&mut self) {
+        self.conversation_history.clear();
+    }
+
+    /// Tools are never sent
+    fn set_tools_enabled(&mut self, _enabled: bool) {}
+}
+
+/// Turns the bytes of the event stream into the answer. A chunk can end in the middle
+/// of an event, or even of a character, so only complete lines are parsed.
+#[derive(Default)]
+struct EventParser {
+    pending: Vec<u8>,
+    stop_reason: Option<String>,
+    output_tokens: Option<u32>,
+}
+
+impl EventParser {
+    fn push(&mut self, bytes: &[u8]) -> Result<ChatResponse, LLMError> {
+        self.pending.extend_from_slice(bytes);
+        let mut content = String::new();
+
+        while let Some(newline) = self.pending.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=newline).collect();
+            if let Some(text) = self.parse_line(String::from_utf8_lossy(&line).trim_end())? {
+                content.push_str(&text);
+            }
+        }
+
+        Ok(ChatResponse {
+            content,
+            tool_calls: None,
+            reasoning: None,
+        })
+    }
+
+    /// Text of a content delta. The other events only update the parser's state.
+    fn parse_line(&mut self, line: &str) -> Result<Option<String>, LLMError> {
+        let Some(data) = line.strip_prefix("data:") else {
+            return Ok(None);
+        };
+        let Ok(event) = serde_json::from_str::<AnthropicStreamEvent>(data.trim()) else {
+            return Ok(None);
+        };
+
+        match event.event_type.as_str() {
+            "content_block_delta" => return Ok(event.delta.and_then(|delta| delta.text)),
+            "message_delta" => {
+                if let Some(stop_reason) = event.delta.and_then(|delta| delta.stop_reason) {
+                    self.stop_reason = Some(stop_reason);
+                }
+                if let Some(output_tokens) = event.usage.and_then(|usage| usage.output_tokens) {
+                    self.output_tokens = Some(output_tokens);
+                }
+            }
+            "message_stop" => self.report_stop(),
+            "error" => {
+                let message = event
+                    .error
+                    .map(|error| error.message)
+                    .unwrap_or_else(|| data.to_string());
+                return Err(LLMError::ApiError(format!(
+                    "Anthropic API error: {}",
+                    message
+                )));
+            }
+            _ => {}
+        }
+
+        Ok(None)
+    }
+
+    fn report_stop(&self) {
+        if self.stop_reason.as_deref() == Some("max_tokens") {
+            eprintln!(
+                "\n{}The answer was cut off at the output token limit.",
+                icon("⚠️  ")
+            );
+        }
+        if is_debug_enabled() {
+            eprintln!(
+                "{}Anthropic stop reason: {}, output tokens: {}",
+                icon("🐛 "),
+                self.stop_reason.as_deref().unwrap_or("unknown"),
+                self.output_tokens
+                    .map_or("unknown".to_string(), |tokens| tokens.to_string())
+            );
+        }
+    }
 }
 
 #[cfg(test)]
@@ -170,10 +382,223 @@ mod tests {
             base_url: None,
             keep_alive: None,
             context_length: None,
+            max_tokens: None,
+            stop: None,
+            extra_body: None,
             tools: None,
         };
 
         let provider = AnthropicProvider::new(config).unwrap();
         assert_eq!(provider.model, "claude-3-opus-20240229");
     }
+
+    #[test]
+    fn test_system_prompt_cache_control() {
+        let config = LLMConfig {
+            provider: "anthropic".to_string(),
+            model: "claude-3-5-sonnet-latest".to_string(),
+            ..Default::default()
+        };
+        let mut provider = AnthropicProvider::new(config).unwrap();
+        provider.with_system_prompt("You are a shell assistant");
+        provider.conversation_history.push(Message {
+            role: "tool".to_string(),
+            content: "[]".to_string(),
+            ..Default::default()
+        });
+
+        provider.prompt_cache = false;
+        let request = serde_json::to_value(provider.build_request()).unwrap();
+        assert_eq!(request["system"], "You are a shell assistant");
+        assert_eq!(request["messages"][0]["role"], "user");
+
+        provider.prompt_cache = true;
+        let request = serde_json::to_value(provider.build_request()).unwrap();
+        assert_eq!(request["system"][0]["text"], "You are a shell assistant");
+        assert_eq!(request["system"][0]["cache_control"]["type"], "ephemeral");
+    }
+
+    #[test]
+    fn test_api_version_and_beta_headers() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let config = LLMConfig {
+            provider: "anthropic".to_string(),
+            model: "claude-3-5-sonnet-latest".to_string(),
+            ..Default::default()
+        };
+        let header = |provider: &AnthropicProvider, name| {
+            provider
+                .api_headers()
+                .into_iter()
+                .find(|(header, _)| *header == name)
+                .map(|(_, value)| value)
+        };
+
+        env::remove_var(ENV_ANTHROPIC_VERSION);
+        env::remove_var(ENV_ANTHROPIC_BETA);
+        let mut provider = AnthropicProvider::new(config.clone()).unwrap();
+        provider.prompt_cache = false;
+        assert_eq!(
+            header(&provider, "anthropic-version").as_deref(),
+            Some("2023-06-01")
+        );
+        assert_eq!(header(&provider, "anthropic-beta"), None);
+
+        env::set_var(ENV_ANTHROPIC_VERSION, "2025-01-01");
+        env::set_var(ENV_ANTHROPIC_BETA, "token-efficient-tools-2025-02-19");
+        let mut provider = AnthropicProvider::new(config).unwrap();
+        provider.prompt_cache = true;
+        assert_eq!(
+            header(&provider, "anthropic-version").as_deref(),
+            Some("2025-01-01")
+        );
+        assert_eq!(
+            header(&provider, "anthropic-beta").as_deref(),
+            Some("prompt-caching-2024-07-31,token-efficient-tools-2025-02-19")
+        );
+
+        env::remove_var(ENV_ANTHROPIC_VERSION);
+        env::remove_var(ENV_ANTHROPIC_BETA);
+    }
+
+    #[test]
+    fn test_clear_history_keeps_system_prompt() {
+        let config = LLMConfig {
+            provider: "anthropic".to_string(),
+            model: "claude-3-5-sonnet-latest".to_string(),
+            ..Default::default()
+        };
+        let mut provider = AnthropicProvider::new(config).unwrap();
+        provider.with_system_prompt("You are a shell assistant");
+        provider.conversation_history.push(Message {
+            role: "user".to_string(),
+            content: "which disk is full?".to_string(),
+            ..Default::default()
+        });
+
+        provider.clear_history();
+        provider.conversation_history.push(Message {
+            role: "user".to_string(),
+            content: "what's in /tmp?".to_string(),
+            ..Default::default()
+        });
+
+        let request = serde_json::to_value(provider.build_request()).unwrap();
+        assert_eq!(request["system"], "You are a shell assistant");
+        assert_eq!(request["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(request["messages"][0]["content"], "what's in /tmp?");
+    }
+
+    #[test]
+    fn test_max_tokens() {
+        let config = |max_tokens| LLMConfig {
+            provider: "anthropic".to_string(),
+            model: "claude-3-5-sonnet-latest".to_string(),
+            max_tokens,
+            ..Default::default()
+        };
+
+        let provider = AnthropicProvider::new(config(None)).unwrap();
+        assert_eq!(provider.build_request().max_tokens, 4096);
+
+        let provider = AnthropicProvider::new(config(Some(256))).unwrap();
+        assert_eq!(provider.build_request().max_tokens, 256);
+    }
+
+    #[test]
+    fn test_stop_sequences() {
+        let config = LLMConfig {
+            provider: "anthropic".to_string(),
+            model: "claude-3-5-sonnet-latest".to_string(),
+            stop: Some(vec!["\n```".to_string()]),
+            ..Default::default()
+        };
+        let provider = AnthropicProvider::new(config).unwrap();
+
+        let request = serde_json::to_value(provider.build_request()).unwrap();
+        assert_eq!(request["stop_sequences"], serde_json::json!(["\n```"]));
+    }
+
+    #[test]
+    fn test_extra_body() {
+        let config = LLMConfig {
+            provider: "anthropic".to_string(),
+            model: "claude-3-5-sonnet-latest".to_string(),
+            extra_body: serde_json::json!({ "top_k": 5, "max_tokens": 100 })
+                .as_object()
+                .cloned(),
+            ..Default::default()
+        };
+        let provider = AnthropicProvider::new(config).unwrap();
+
+        let mut request = serde_json::to_value(provider.build_request()).unwrap();
+        merge_extra_body(&mut request, provider.extra_body.as_ref());
+        assert_eq!(request["top_k"], 5);
+        assert_eq!(request["max_tokens"], 100);
+    }
+
+    #[test]
+    fn test_event_parser_handles_split_events() {
+        let events = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"usage\":{\"input_tokens\":12,\"output_tokens\":1}}}\n\n",
+            "event: content_block_start\n",
+            "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+            "event: ping\n",
+            "data: {\"type\": \"ping\"}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Use \"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"du -sh ✓\"}}\n\n",
+            "event: content_block_stop\n",
+            "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+            "event: message_delta\n",
+            "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":15}}\n\n",
+            "event: message_stop\n",
+            "data: {\"type\":\"message_stop\"}\n\n",
+        )
+        .as_bytes();
+
+        // Split in the middle of the last delta and of its multibyte character
+        let split = events
+            .windows(3)
+            .position(|bytes| bytes == "✓".as_bytes())
+            .unwrap()
+            + 1;
+        let mut parser = EventParser::default();
+        let mut answer = String::new();
+        for chunk in [&events[..split], &events[split..]] {
+            answer.push_str(&parser.push(chunk).unwrap().content);
+        }
+
+        assert_eq!(answer, "Use du -sh ✓");
+        assert_eq!(parser.stop_reason.as_deref(), Some("end_turn"));
+        assert_eq!(parser.output_tokens, Some(15));
+
+        let error = concat!(
+            "event: error\n",
+            "data: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n",
+        );
+        let error = EventParser::default().push(error.as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("Overloaded"));
+    }
+
+    #[test]
+    fn test_data_line_split_at_any_byte() {
+        let events = concat!(
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hello, \"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"wörld\"}}\n\n",
+        )
+        .as_bytes();
+
+        for split in 0..=events.len() {
+            let mut parser = EventParser::default();
+            let mut answer = parser.push(&events[..split]).unwrap().content;
+            answer.push_str(&parser.push(&events[split..]).unwrap().content);
+
+            assert_eq!(answer, "Hello, wörld", "split at byte {}", split);
+        }
+    }
 }
@@ -0,0 +1,269 @@
+use async_trait::async_trait;
+use aws_sdk_bedrockruntime::{primitives::Blob, types::ResponseStream, Client};
+use futures::stream::StreamExt;
+use serde::Deserialize;
+use std::fmt::Debug;
+
+use crate::llm::{ChatResponse, Message};
+
+use super::{
+    history::windowed_history, image, merge_extra_body, ChatStream, LLMConfig, LLMError,
+    LLMProvider,
+};
+
+const ANTHROPIC_BEDROCK_VERSION: &str = "bedrock-2023-05-31";
+/// Anthropic models require a limit, this one is used when ASK_SH_MAX_TOKENS isn't set
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// Listing models needs the Bedrock control plane API, so these are the known Anthropic ones.
+/// Some regions need a cross-region inference profile instead, like `us.` + the model id.
+const KNOWN_MODELS: &[&str] = &[
+    "anthropic.claude-3-haiku-20240307-v1:0",
+    "anthropic.claude-3-5-haiku-20241022-v1:0",
+    "anthropic.claude-3-5-sonnet-20240620-v1:0",
+    "anthropic.claude-3-5-sonnet-20241022-v2:0",
+    "anthropic.claude-3-7-sonnet-20250219-v1:0",
+    "anthropic.claude-sonnet-4-20250514-v1:0",
+    "anthropic.claude-opus-4-20250514-v1:0",
+];
+
+/// Amazon Bedrock provider, for Anthropic models served through Bedrock.
+/// Credentials and region are read the standard AWS way (env vars, profile, SSO...).
+#[derive(Debug)]
+pub struct BedrockProvider {
+    client: Option<Client>,
+    model: String,
+    system_prompt: Option<String>,
+    conversation_history: Vec<Message>,
+    max_tokens: u32,
+    extra_body: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BedrockStreamEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    delta: Option<Delta>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Delta {
+    text: Option<String>,
+}
+
+impl BedrockProvider {
+    pub fn new(config: LLMConfig) -> Result<Self, LLMError> {
+        Ok(Self {
+            client: None,
+            model: config.model,
+            system_prompt: None,
+            conversation_history: Vec::new(),
+            max_tokens: config.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            extra_body: config.extra_body,
+        })
+    }
+
+    /// Loading the AWS configuration is async, so the client is created on first use
+    async fn get_client(&mut self) -> &Client {
+        if self.client.is_none() {
+            let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+            self.client = Some(Client::new(&aws_config));
+        }
+
+        self.client.as_ref().unwrap()
+    }
+
+    fn build_request_body(&self) -> serde_json::Value {
+        // Bedrock only accepts user and assistant turns, tool results are sent as user turns
+        let messages: Vec<serde_json::Value> =
+            windowed_history(&self.conversation_history, |message| &message.role)
+                .iter()
+                .map(|message| {
+                    let role = match message.role.as_str() {
+                        "assistant" => "assistant",
+                        _ => "user",
+                    };
+                    let content = image::anthropic_content(&message.content, &message.images);
+                    serde_json::json!({ "role": role, "content": content })
+                })
+                .collect();
+
+        let mut body = serde_json::json!({
+            "anthropic_version": ANTHROPIC_BEDROCK_VERSION,
+            "max_tokens": self.max_tokens,
+            "messages": messages,
+        });
+
+        if let Some(system_prompt) = &self.system_prompt {
+            body["system"] = serde_json::Value::String(system_prompt.clone());
+        }
+        merge_extra_body(&mut body, self.extra_body.as_ref());
+
+        body
+    }
+
+    fn parse_chunk(bytes: &[u8]) -> Option<String> {
+        let event = serde_json::from_slice::<BedrockStreamEvent>(bytes).ok()?;
+
+        if event.event_type == "content_block_delta" {
+            return event.delta?.text;
+        }
+        None
+    }
+}
+
+#[async_trait]
+impl LLMProvider for BedrockProvider {
+    /// Bedrock takes the system prompt separately from the conversation
+    fn with_system_prompt(&mut self, prompt: &str) {
+        self.system_prompt = Some(prompt.to_string());
+    }
+
+    async fn chat_stream(&mut self, user_message: &Message) -> Result<ChatStream, LLMError> {
+        // Add user message to history
+        self.conversation_history.push(user_message.clone());
+
+        let body = self.build_request_body();
+        let model = self.model.clone();
+
+        let output = self
+            .get_client()
+            .await
+            .invoke_model_with_response_stream()
+            .model_id(model)
+            .content_type("application/json")
+            .accept("application/json")
+            .body(Blob::new(body.to_string()))
+            .send()
+            .await
+            .map_err(|e| {
+                let status = e.raw_response().map(|response| response.status().as_u16());
+                LLMError::from_status(
+                    status.unwrap_or_default(),
+                    None,
+                    format!("Bedrock API error: {:?}", e),
+                )
+            })?;
+
+        // Stop reading after the first error so a broken stream doesn't repeat it forever
+        let stream = futures::stream::unfold(Some(output.body), |receiver| async move {
+            let mut receiver = receiver?;
+
+            match receiver.recv().await {
+                Ok(Some(ResponseStream::Chunk(part))) => {
+                    let content = part
+                        .bytes()
+                        .and_then(|bytes| Self::parse_chunk(bytes.as_ref()))
+                        .unwrap_or_default();
+                    let chat_response = ChatResponse {
+                        content,
+                        tool_calls: None,
+                        reasoning: None,
+                    };
+                    Some((Ok(chat_response), Some(receiver)))
+                }
+                Ok(Some(_)) => Some((
+                    Ok(ChatResponse {
+                        content: String::new(),
+                        tool_calls: None,
+                        reasoning: None,
+                    }),
+                    Some(receiver),
+                )),
+                Ok(None) => None,
+                Err(e) => Some((Err(LLMError::NetworkError(e.to_string())), None)),
+            }
+        });
+
+        let filtered_stream = stream.filter(|result| {
+            futures::future::ready(match result {
+                Ok(content) => !content.content.is_empty(),
+                Err(_) => true,
+            })
+        });
+
+        Ok(Box::pin(filtered_stream))
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, LLMError> {
+        Ok(KNOWN_MODELS.iter().map(|model| model.to_string()).collect())
+    }
+
+    fn supports_tools(&self) -> bool {
+        // Tool definitions aren't sent in Bedrock requests yet
+        false
+    }
+    /// The system prompt is kept apart from the conversation
+    fn clear_history(&mut self) {
+        self.conversation_history.clear();
+    }
+
+    /// Tools are never sent
+    fn set_tools_enabled(&mut self, _enabled: bool) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bedrock_provider_creation() {
+        let config = LLMConfig {
+            provider: "bedrock".to_string(),
+            model: "anthropic.claude-3-5-sonnet-20240620-v1:0".to_string(),
+            max_tokens: Some(1024),
+            ..Default::default()
+        };
+
+        let mut provider = BedrockProvider::new(config).unwrap();
+        provider.with_system_prompt("You are a helpful assistant");
+        provider.conversation_history.push(Message {
+            role: "tool".to_string(),
+            content: "exit code: 0".to_string(),
+            ..Default::default()
+        });
+
+        let body = provider.build_request_body();
+        assert_eq!(provider.model, "anthropic.claude-3-5-sonnet-20240620-v1:0");
+        assert_eq!(body["system"], "You are a helpful assistant");
+        assert_eq!(body["messages"][0]["role"], "user");
+        assert_eq!(body["max_tokens"], 1024);
+    }
+
+    #[test]
+    fn test_clear_history_keeps_system_prompt() {
+        let config = LLMConfig {
+            provider: "bedrock".to_string(),
+            model: "anthropic.claude-3-5-sonnet-20240620-v1:0".to_string(),
+            ..Default::default()
+        };
+        let mut provider = BedrockProvider::new(config).unwrap();
+        provider.with_system_prompt("You are a helpful assistant");
+        provider.conversation_history.push(Message {
+            role: "user".to_string(),
+            content: "which disk is full?".to_string(),
+            ..Default::default()
+        });
+
+        provider.clear_history();
+        provider.conversation_history.push(Message {
+            role: "user".to_string(),
+            content: "what's in /tmp?".to_string(),
+            ..Default::default()
+        });
+
+        let body = provider.build_request_body();
+        assert_eq!(body["system"], "You are a helpful assistant");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(body["messages"][0]["content"], "what's in /tmp?");
+    }
+
+    #[test]
+    fn test_parse_chunk() {
+        let delta = br#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hi"}}"#;
+        assert_eq!(BedrockProvider::parse_chunk(delta), Some("Hi".to_string()));
+
+        let stop = br#"{"type":"message_stop"}"#;
+        assert_eq!(BedrockProvider::parse_chunk(stop), None);
+    }
+}
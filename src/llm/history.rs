@@ -0,0 +1,77 @@
+use std::env;
+
+use crate::ENV_HISTORY_WINDOW;
+
+/// The messages to send this turn. With ASK_SH_HISTORY_WINDOW, only that many of the latest
+/// messages are sent after the system prompt, for a predictable cost in long conversations.
+pub(super) fn windowed_history<T: Clone>(history: &[T], role: impl Fn(&T) -> &str) -> Vec<T> {
+    let window = env::var(ENV_HISTORY_WINDOW)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|&window| window > 0);
+
+    recent_messages(history, window, role)
+}
+
+/// The window starts at a user turn, since some providers refuse a conversation
+/// starting with the assistant, or with tool results for calls that were left out.
+/// The last message is always kept.
+fn recent_messages<T: Clone>(
+    history: &[T],
+    window: Option<usize>,
+    role: impl Fn(&T) -> &str,
+) -> Vec<T> {
+    let Some(window) = window else {
+        return history.to_vec();
+    };
+
+    let system_count = history
+        .iter()
+        .take_while(|message| role(message) == "system")
+        .count();
+    let (system, conversation) = history.split_at(system_count);
+
+    let mut recent = &conversation[conversation.len().saturating_sub(window)..];
+    while recent.len() > 1 && matches!(role(&recent[0]), "assistant" | "tool") {
+        recent = &recent[1..];
+    }
+
+    system.iter().chain(recent).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::Message;
+
+    #[test]
+    fn test_window_keeps_system_message() {
+        let message = |role: &str, content: &str| Message {
+            role: role.to_string(),
+            content: content.to_string(),
+            ..Default::default()
+        };
+        let history = vec![
+            message("system", "You are a shell assistant"),
+            message("user", "which process uses port 8080?"),
+            message("assistant", "Run `lsof -i :8080`"),
+            message("user", "and how do I stop it?"),
+        ];
+        fn role(message: &Message) -> &str {
+            &message.role
+        }
+
+        let sent = recent_messages(&history, Some(2), role);
+        let contents: Vec<&str> = sent
+            .iter()
+            .map(|message| message.content.as_str())
+            .collect();
+        assert_eq!(
+            contents,
+            vec!["You are a shell assistant", "and how do I stop it?"]
+        );
+
+        assert_eq!(recent_messages(&history, Some(3), role).len(), 4);
+        assert_eq!(recent_messages(&history, None, role).len(), 4);
+    }
+}
@@ -0,0 +1,92 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::fs;
+use std::path::Path;
+
+use super::LLMError;
+
+/// Base64 starts of the image formats vision models accept
+const MEDIA_TYPES: &[(&str, &str)] = &[
+    ("iVBORw0KGgo", "image/png"),
+    ("/9j/", "image/jpeg"),
+    ("R0lGOD", "image/gif"),
+    ("UklGR", "image/webp"),
+];
+
+/// Read an image file as base64, the way every provider takes images
+pub fn load_image(path: &Path) -> Result<String, LLMError> {
+    let bytes = fs::read(path).map_err(|e| {
+        LLMError::InvalidRequestError(format!("Could not read {}: {}", path.display(), e))
+    })?;
+    let image = STANDARD.encode(bytes);
+
+    match media_type(&image) {
+        Some(_) => Ok(image),
+        None => Err(LLMError::InvalidRequestError(format!(
+            "{} isn't a PNG, JPEG, GIF or WebP image",
+            path.display()
+        ))),
+    }
+}
+
+/// Media type of a base64 encoded image, told apart by its first bytes
+pub fn media_type(image: &str) -> Option<&'static str> {
+    MEDIA_TYPES
+        .iter()
+        .find(|(prefix, _)| image.starts_with(prefix))
+        .map(|(_, media_type)| *media_type)
+}
+
+/// Image as a data URL, for the OpenAI API
+pub fn data_url(image: &str) -> String {
+    format!(
+        "data:{};base64,{}",
+        media_type(image).unwrap_or("image/png"),
+        image
+    )
+}
+
+/// Content blocks of a message with images, for the Anthropic API and Anthropic models on Bedrock
+pub fn anthropic_content(text: &str, images: &[String]) -> serde_json::Value {
+    if images.is_empty() {
+        return serde_json::Value::String(text.to_string());
+    }
+
+    let mut blocks: Vec<serde_json::Value> = images
+        .iter()
+        .map(|image| {
+            serde_json::json!({
+                "type": "image",
+                "source": {
+                    "type": "base64",
+                    "media_type": media_type(image).unwrap_or("image/png"),
+                    "data": image,
+                }
+            })
+        })
+        .collect();
+    blocks.push(serde_json::json!({ "type": "text", "text": text }));
+
+    serde_json::Value::Array(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_formats() {
+        let png = STANDARD.encode(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR");
+        let jpeg = STANDARD.encode(b"\xff\xd8\xff\xe0\0\x10JFIF");
+        assert_eq!(media_type(&png), Some("image/png"));
+        assert_eq!(media_type(&jpeg), Some("image/jpeg"));
+        assert_eq!(media_type(&STANDARD.encode(b"plain text")), None);
+
+        assert!(data_url(&jpeg).starts_with("data:image/jpeg;base64,/9j/"));
+
+        let content = anthropic_content("what's wrong here", std::slice::from_ref(&png));
+        assert_eq!(content[0]["source"]["media_type"], "image/png");
+        assert_eq!(content[0]["source"]["data"], png.as_str());
+        assert_eq!(content[1]["text"], "what's wrong here");
+        assert_eq!(anthropic_content("hi", &[]), "hi");
+    }
+}
@@ -2,18 +2,21 @@ use async_trait::async_trait;
 use futures::stream::StreamExt;
 use futures::Stream;
 use serde::{Deserialize, Serialize};
-use std::{
-    error::Error,
-    fmt::Debug,
-    io::{stdout, Write},
-    pin::Pin,
-};
-use termimad::crossterm::{cursor, terminal, ExecutableCommand};
+use std::{env, error::Error, fmt::Debug, pin::Pin, time::Duration};
 use thiserror::Error;
 
-use crate::tools::{Tool, ToolCall};
+use crate::{
+    redact::redact,
+    renderer::OutputRenderer,
+    tools::{Tool, ToolCall, ToolCallResult},
+    ENV_STREAM_IDLE_TIMEOUT,
+};
+
+/// Longest wait for the next chunk of an answer, unless ASK_SH_STREAM_IDLE_TIMEOUT says otherwise
+const DEFAULT_STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 
 /// Error from LLM provider
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum LLMError {
     #[error("API error: {0}")]
@@ -27,10 +30,44 @@ pub enum LLMError {
 
     #[error("Invalid request: {0}")]
     InvalidRequestError(String),
+
+    #[error("Authentication failed: {0}")]
+    AuthError(String),
+
+    #[error(
+        "Rate limited by the provider{}",
+        .retry_after.map(|delay| format!(", try again in {}s", delay.as_secs())).unwrap_or_default()
+    )]
+    RateLimited { retry_after: Option<Duration> },
+}
+
+impl LLMError {
+    /// The error for an unsuccessful HTTP status, telling a bad key and rate limiting apart
+    pub(crate) fn from_status(status: u16, retry_after: Option<Duration>, message: String) -> Self {
+        match status {
+            401 | 403 => LLMError::AuthError(message),
+            429 => LLMError::RateLimited { retry_after },
+            _ => LLMError::ApiError(message),
+        }
+    }
+
+    /// What the user can do about the error, when there's something obvious
+    pub fn guidance(&self) -> Option<&'static str> {
+        match self {
+            LLMError::AuthError(_) => Some(
+                "Check your API key: it may be mistyped, revoked, or not allowed to use this model. \
+                With Bedrock, check your AWS credentials.",
+            ),
+            LLMError::RateLimited { .. } => Some(
+                "Wait a bit before asking again, or check the rate limits and credits of your account.",
+            ),
+            _ => None,
+        }
+    }
 }
 
 /// LLM configuration
-#[derive(Debug, Clone)]
+#[derive(Clone, Default)]
 pub struct LLMConfig {
     pub provider: String,
     pub model: String,
@@ -38,24 +75,33 @@ pub struct LLMConfig {
     pub base_url: Option<String>, // Custom endpoint URL (for OpenAI and Ollama)
     pub keep_alive: Option<i32>,  // Amount of minutes to keep the model loaded (Ollama only)
     pub context_length: Option<u32>, // Context length to pass to Ollama (Ollama only)
+    pub max_tokens: Option<u32>,  // Longest answer, in tokens (defaults depend on the model)
+    /// The answer ends before any of these, which are left out of it
+    pub stop: Option<Vec<String>>,
+    /// Fields added to every request as is, overriding the ones ask.sh sets
+    pub extra_body: Option<serde_json::Map<String, serde_json::Value>>,
     pub tools: Option<Vec<Tool>>,
 }
 
-impl Default for LLMConfig {
-    fn default() -> Self {
-        Self {
-            provider: String::new(),
-            model: String::new(),
-            api_key: String::new(),
-            base_url: None,
-            keep_alive: None,
-            context_length: None,
-            tools: None,
-        }
+/// The API key is redacted, so the config can be printed when debugging
+impl Debug for LLMConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LLMConfig")
+            .field("provider", &self.provider)
+            .field("model", &self.model)
+            .field("api_key", &redact(&self.api_key))
+            .field("base_url", &self.base_url)
+            .field("keep_alive", &self.keep_alive)
+            .field("context_length", &self.context_length)
+            .field("max_tokens", &self.max_tokens)
+            .field("stop", &self.stop)
+            .field("extra_body", &self.extra_body)
+            .field("tools", &self.tools)
+            .finish()
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Message {
     pub role: String,
     pub content: String,
@@ -63,23 +109,22 @@ pub struct Message {
     pub tool_calls: Option<Vec<ToolCall>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
-}
-
-impl Default for Message {
-    fn default() -> Self {
-        Self {
-            role: String::new(),
-            content: String::new(),
-            tool_calls: None,
-            name: None,
-        }
-    }
+    /// Base64 encoded images, for vision models
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+    /// The result of each tool call, for providers that send them back one by one.
+    /// A tool message's content already holds them all as JSON.
+    #[serde(skip)]
+    pub tool_results: Vec<ToolCallResult>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatResponse {
     pub content: String,
     pub tool_calls: Option<Vec<ToolCall>>,
+    /// What reasoning models think before answering, kept out of the answer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
 }
 
 /// Type alias for chat stream
@@ -93,61 +138,167 @@ pub trait LLMProvider: Send + Sync + Debug {
     /// Get chat completion as a stream
     async fn chat_stream(&mut self, user_message: &Message) -> Result<ChatStream, LLMError>;
 
-    async fn chat<F>(
+    /// Names of the models that can be set as the provider's model
+    async fn list_models(&self) -> Result<Vec<String>, LLMError>;
+
+    /// Whether tools are sent to the model, so it can call them
+    fn supports_tools(&self) -> bool;
+
+    /// Forget the conversation so far, keeping the system prompt
+    fn clear_history(&mut self);
+
+    /// Whether the next requests send the tools, the model answers in text without them
+    fn set_tools_enabled(&mut self, enabled: bool);
+
+    async fn chat(
         &mut self,
         user_message: &Message,
-        display_fn: Option<F>,
-    ) -> Result<ChatResponse, Box<dyn Error>>
-    where
-        F: Fn(&str) -> Result<(), Box<dyn std::error::Error>> + Send,
-    {
-        let mut stream = self
-            .chat_stream(user_message)
-            .await
-            .map_err(|e| Box::new(e) as Box<dyn Error>)?;
+        renderer: &mut OutputRenderer,
+    ) -> Result<ChatResponse, Box<dyn Error>> {
+        let spinner = renderer.waiting_spinner();
+        let stream = self.chat_stream(user_message).await;
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                spinner.finish_and_clear();
+                return Err(Box::new(e));
+            }
+        };
+
+        // The spinner makes way for the answer once its first chunk arrives
+        let idle_timeout = stream_idle_timeout();
+        let first = next_chunk(&mut stream, idle_timeout).await;
+        spinner.finish_and_clear();
+        let mut stream = futures::stream::iter(first?).chain(stream);
 
         let mut response = ChatResponse {
             content: "".to_string(),
             tool_calls: None,
+            reasoning: None,
         };
 
-        let mut stdout = stdout();
+        // Some models inline their reasoning in <think> tags instead of a separate field
+        let mut think_tags = ThinkTagSplitter::default();
 
-        // Save cursor position
-        let start_line = cursor::position()?.1;
+        renderer.start()?;
+
+        loop {
+            let result = match next_chunk(&mut stream, idle_timeout).await {
+                Ok(Some(result)) => result,
+                Ok(None) => break,
+                // Keep what was answered so far on screen
+                Err(e) => {
+                    renderer.finish(&response.content)?;
+                    return Err(Box::new(e));
+                }
+            };
 
-        while let Some(result) = stream.next().await {
             match result {
                 Ok(content) => {
-                    response.content.push_str(&content.content);
-                    response.tool_calls = content.tool_calls;
+                    let (tagged_reasoning, text) = think_tags.push(&content.content);
+                    let reasoning = content.reasoning.unwrap_or_default() + &tagged_reasoning;
+                    if let Some(tool_calls) = content.tool_calls {
+                        response
+                            .tool_calls
+                            .get_or_insert_with(Vec::new)
+                            .extend(tool_calls);
+                    }
 
-                    // Print plain text immediately
-                    print!("{}", content.content);
-                    std::io::stdout().flush()?;
+                    add_to_response(&mut response, &reasoning, &text, renderer)?;
                 }
                 Err(err) => {
                     eprintln!("{}", err);
                 }
             }
         }
-        println!();
 
-        if display_fn.is_some() {
-            // Clear from start position and re-render
-            stdout.execute(cursor::MoveTo(0, start_line))?;
-            stdout.execute(terminal::Clear(terminal::ClearType::FromCursorDown))?;
+        let (reasoning, text) = think_tags.finish();
+        add_to_response(&mut response, &reasoning, &text, renderer)?;
 
-            (display_fn.unwrap())(&response.content)?;
-        }
+        renderer.finish(&response.content)?;
 
         Ok(response)
     }
 }
 
+fn stream_idle_timeout() -> Duration {
+    env::var(ENV_STREAM_IDLE_TIMEOUT)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|&seconds| seconds > 0)
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_STREAM_IDLE_TIMEOUT)
+}
+
+/// The next chunk of the answer, or an error when the provider stalls, having sent nothing
+/// for `idle_timeout`. Its stream would otherwise never end.
+async fn next_chunk<S>(stream: &mut S, idle_timeout: Duration) -> Result<Option<S::Item>, LLMError>
+where
+    S: Stream + Unpin,
+{
+    tokio::time::timeout(idle_timeout, stream.next())
+        .await
+        .map_err(|_| {
+            LLMError::NetworkError(format!(
+                "the provider sent nothing for {}s, giving up. Set {} to wait longer.",
+                idle_timeout.as_secs_f32(),
+                ENV_STREAM_IDLE_TIMEOUT
+            ))
+        })
+}
+
+/// Add ASK_SH_EXTRA_BODY to a request, its fields winning over the ones already there
+pub(super) fn merge_extra_body(
+    body: &mut serde_json::Value,
+    extra_body: Option<&serde_json::Map<String, serde_json::Value>>,
+) {
+    if let (Some(body), Some(extra_body)) = (body.as_object_mut(), extra_body) {
+        body.extend(extra_body.clone());
+    }
+}
+
+/// Display a streamed chunk and add it to the response, keeping reasoning apart from the answer
+fn add_to_response(
+    response: &mut ChatResponse,
+    reasoning: &str,
+    text: &str,
+    renderer: &mut OutputRenderer,
+) -> Result<(), Box<dyn Error>> {
+    if !reasoning.is_empty() {
+        renderer.push_reasoning(reasoning)?;
+        response
+            .reasoning
+            .get_or_insert_with(String::new)
+            .push_str(reasoning);
+    }
+
+    // The answer usually starts with blank lines after the reasoning
+    let text = match response.content.is_empty() {
+        true => text.trim_start(),
+        false => text,
+    };
+    if !text.is_empty() {
+        response.content.push_str(text);
+        renderer.push(text)?;
+    }
+
+    Ok(())
+}
+
 pub mod anthropic;
+pub mod bedrock;
+mod history;
+pub mod image;
+pub mod model_limits;
 pub mod ollama;
 pub mod openai;
+mod reasoning;
+mod retry;
+
+use reasoning::ThinkTagSplitter;
+
+/// Names accepted in ASK_SH_LLM_PROVIDER
+pub const SUPPORTED_PROVIDERS: &[&str] = &["openai", "anthropic", "ollama", "bedrock", "deepseek"];
 
 /// Available LLM providers
 #[derive(Debug)]
@@ -155,6 +306,7 @@ pub enum Provider {
     OpenAI(openai::OpenAIProvider),
     Anthropic(anthropic::AnthropicProvider),
     Ollama(ollama::OllamaProvider),
+    Bedrock(bedrock::BedrockProvider),
 }
 
 #[async_trait]
@@ -164,6 +316,7 @@ impl LLMProvider for Provider {
             Provider::OpenAI(p) => p.with_system_prompt(prompt),
             Provider::Anthropic(p) => p.with_system_prompt(prompt),
             Provider::Ollama(p) => p.with_system_prompt(prompt),
+            Provider::Bedrock(p) => p.with_system_prompt(prompt),
         }
     }
 
@@ -172,21 +325,171 @@ impl LLMProvider for Provider {
             Provider::OpenAI(p) => p.chat_stream(user_message).await,
             Provider::Anthropic(p) => p.chat_stream(user_message).await,
             Provider::Ollama(p) => p.chat_stream(user_message).await,
+            Provider::Bedrock(p) => p.chat_stream(user_message).await,
         }
     }
+
+    async fn list_models(&self) -> Result<Vec<String>, LLMError> {
+        match self {
+            Provider::OpenAI(p) => p.list_models().await,
+            Provider::Anthropic(p) => p.list_models().await,
+            Provider::Ollama(p) => p.list_models().await,
+            Provider::Bedrock(p) => p.list_models().await,
+        }
+    }
+
+    fn supports_tools(&self) -> bool {
+        match self {
+            Provider::OpenAI(p) => p.supports_tools(),
+            Provider::Anthropic(p) => p.supports_tools(),
+            Provider::Ollama(p) => p.supports_tools(),
+            Provider::Bedrock(p) => p.supports_tools(),
+        }
+    }
+
+    fn clear_history(&mut self) {
+        match self {
+            Provider::OpenAI(p) => p.clear_history(),
+            Provider::Anthropic(p) => p.clear_history(),
+            Provider::Ollama(p) => p.clear_history(),
+            Provider::Bedrock(p) => p.clear_history(),
+        }
+    }
+
+    fn set_tools_enabled(&mut self, enabled: bool) {
+        match self {
+            Provider::OpenAI(p) => p.set_tools_enabled(enabled),
+            Provider::Anthropic(p) => p.set_tools_enabled(enabled),
+            Provider::Ollama(p) => p.set_tools_enabled(enabled),
+            Provider::Bedrock(p) => p.set_tools_enabled(enabled),
+        }
+    }
+}
+
+/// Provider answering with scripted responses, to test the conversation logic without an API
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct MockProvider {
+    responses: std::collections::VecDeque<Result<ChatResponse, LLMError>>,
+    /// Every message the provider was asked to answer
+    pub(crate) requests: Vec<Message>,
+    /// Whether the tools were sent with each of the requests
+    pub(crate) tools_sent: Vec<bool>,
+    tools_disabled: bool,
+}
+
+#[cfg(test)]
+impl MockProvider {
+    /// The responses are given in order, and an error once they are used up
+    pub(crate) fn new(responses: Vec<Result<ChatResponse, LLMError>>) -> Self {
+        Self {
+            responses: responses.into(),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn text(content: &str) -> Result<ChatResponse, LLMError> {
+        Ok(ChatResponse {
+            content: content.to_string(),
+            tool_calls: None,
+            reasoning: None,
+        })
+    }
+
+    pub(crate) fn tool_call(
+        name: &str,
+        arguments: serde_json::Value,
+    ) -> Result<ChatResponse, LLMError> {
+        Ok(ChatResponse {
+            content: String::new(),
+            tool_calls: Some(vec![ToolCall {
+                id: None,
+                function: crate::tools::FunctionCall {
+                    name: name.to_string(),
+                    arguments,
+                },
+            }]),
+            reasoning: None,
+        })
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl LLMProvider for MockProvider {
+    fn with_system_prompt(&mut self, _prompt: &str) {}
+
+    async fn chat_stream(&mut self, user_message: &Message) -> Result<ChatStream, LLMError> {
+        self.requests.push(user_message.clone());
+        self.tools_sent.push(!self.tools_disabled);
+
+        let response = self
+            .responses
+            .pop_front()
+            .unwrap_or_else(|| Err(LLMError::ApiError("No scripted response left".to_string())))?;
+        Ok(Box::pin(futures::stream::iter(vec![Ok(response)])))
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, LLMError> {
+        Ok(vec!["mock".to_string()])
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn clear_history(&mut self) {}
+
+    fn set_tools_enabled(&mut self, enabled: bool) {
+        self.tools_disabled = !enabled;
+    }
 }
 
 /// Provider factory
 pub fn create_llm_provider(config: LLMConfig) -> Result<Provider, LLMError> {
     match config.provider.as_str() {
-        "openai" => Ok(Provider::OpenAI(openai::OpenAIProvider::new(config)?)),
+        "openai" | "deepseek" => Ok(Provider::OpenAI(openai::OpenAIProvider::new(config)?)),
         "anthropic" => Ok(Provider::Anthropic(anthropic::AnthropicProvider::new(
             config,
         )?)),
         "ollama" => Ok(Provider::Ollama(ollama::OllamaProvider::new(config)?)),
+        "bedrock" => Ok(Provider::Bedrock(bedrock::BedrockProvider::new(config)?)),
         _ => Err(LLMError::ConfigError(format!(
-            "Unknown provider: {}",
-            config.provider
+            "Unknown provider '{}'. Supported providers: {}",
+            config.provider,
+            SUPPORTED_PROVIDERS.join(", ")
         ))),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_provider_lists_supported_ones() {
+        let config = LLMConfig {
+            provider: "openia".to_string(),
+            ..Default::default()
+        };
+
+        let error = create_llm_provider(config).unwrap_err().to_string();
+        assert!(error.contains("Unknown provider 'openia'"));
+        assert!(error.contains("openai, anthropic, ollama, bedrock"));
+    }
+
+    #[tokio::test]
+    async fn test_stalled_stream_times_out() {
+        // A provider that answers once, then hangs without closing the stream
+        let mut stream = futures::stream::iter([MockProvider::text("Run `ls`")])
+            .chain(futures::stream::pending());
+        let idle_timeout = Duration::from_millis(50);
+
+        let first = next_chunk(&mut stream, idle_timeout).await.unwrap();
+        assert_eq!(first.unwrap().unwrap().content, "Run `ls`");
+
+        let error = next_chunk(&mut stream, idle_timeout).await.unwrap_err();
+        assert!(matches!(error, LLMError::NetworkError(_)));
+        assert!(error.to_string().contains(ENV_STREAM_IDLE_TIMEOUT));
+    }
+}
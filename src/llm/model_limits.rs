@@ -0,0 +1,88 @@
+//! Context length and answer length that suit known models, used unless they're set explicitly
+
+/// Used for models that aren't in the table, small enough for about any model
+const DEFAULT_LIMITS: ModelLimits = ModelLimits {
+    context_length: 8192,
+    max_tokens: 4096,
+};
+
+/// Context length and longest answer in tokens, by part of the model name.
+/// The longest matching name wins, so "gpt-4o-mini" isn't limited like "gpt-4",
+/// and dated versions with other limits than the rest of their family are listed as such.
+/// Ollama reserves memory for the whole context, so local models get less than they support.
+const MODEL_LIMITS: &[(&str, u32, u32)] = &[
+    ("gpt-3.5-turbo", 16_385, 4096),
+    ("gpt-4", 8192, 4096),
+    ("gpt-4-turbo", 128_000, 4096),
+    ("gpt-4o", 128_000, 16_384),
+    ("gpt-4o-2024-05-13", 128_000, 4096),
+    ("gpt-4o-mini", 128_000, 16_384),
+    ("gpt-4.1", 1_047_576, 32_768),
+    ("gpt-5", 400_000, 128_000),
+    ("gpt-5-chat", 128_000, 16_384),
+    ("o1", 200_000, 100_000),
+    ("o1-mini", 128_000, 65_536),
+    ("o1-preview", 128_000, 32_768),
+    ("o3", 200_000, 100_000),
+    ("o4-mini", 200_000, 100_000),
+    ("claude-3-haiku", 200_000, 4096),
+    ("claude-3-opus", 200_000, 4096),
+    ("claude-3-5-haiku", 200_000, 8192),
+    ("claude-3-5-sonnet", 200_000, 8192),
+    ("claude-3-5-sonnet-20240620", 200_000, 4096),
+    ("claude-3-7-sonnet", 200_000, 64_000),
+    ("claude-sonnet-4", 200_000, 64_000),
+    ("claude-opus-4", 200_000, 32_000),
+    ("deepseek-chat", 128_000, 8192),
+    ("deepseek-reasoner", 128_000, 65_536),
+    ("gemma3", 32_768, 8192),
+    ("llama3", 8192, 4096),
+    ("llama3.1", 32_768, 4096),
+    ("llama3.2", 32_768, 4096),
+    ("qwen2.5", 32_768, 8192),
+    ("mistral", 32_768, 4096),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelLimits {
+    pub context_length: u32,
+    pub max_tokens: u32,
+}
+
+pub fn model_limits(model: &str) -> ModelLimits {
+    MODEL_LIMITS
+        .iter()
+        .filter(|(name, _, _)| model.contains(name))
+        .max_by_key(|(name, _, _)| name.len())
+        .map(|&(_, context_length, max_tokens)| ModelLimits {
+            context_length,
+            max_tokens,
+        })
+        .unwrap_or(DEFAULT_LIMITS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_limits() {
+        assert_eq!(model_limits("gpt-4o-mini").max_tokens, 16_384);
+        assert_eq!(model_limits("gpt-4-0613").context_length, 8192);
+        assert_eq!(model_limits("gpt-4o-2024-05-13").max_tokens, 4096);
+        assert_eq!(model_limits("gpt-5").max_tokens, 128_000);
+        assert_eq!(model_limits("gpt-5-mini").max_tokens, 128_000);
+        assert_eq!(model_limits("o1").max_tokens, 100_000);
+        assert_eq!(model_limits("o1-mini").max_tokens, 65_536);
+        assert_eq!(model_limits("o1-preview-2024-09-12").max_tokens, 32_768);
+        assert_eq!(model_limits("claude-3-5-sonnet-latest").max_tokens, 8192);
+        // The default Bedrock model
+        assert_eq!(
+            model_limits("anthropic.claude-3-5-sonnet-20240620-v1:0").max_tokens,
+            4096
+        );
+        assert_eq!(model_limits("deepseek-reasoner").max_tokens, 65_536);
+        assert_eq!(model_limits("llama3.1:8b").context_length, 32_768);
+        assert_eq!(model_limits("my-finetune"), DEFAULT_LIMITS);
+    }
+}
@@ -1,15 +1,21 @@
 use async_trait::async_trait;
 use futures::stream::StreamExt;
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use std::{fmt::Debug, time::Duration};
 
 use crate::{
+    http_client::{self, LLM_REQUEST_TIMEOUT},
     llm::{ChatResponse, Message},
-    tools::Tool,
+    tools::{Tool, ToolCall},
 };
 
-use super::{ChatStream, LLMConfig, LLMError, LLMProvider};
+use super::{
+    history::windowed_history,
+    merge_extra_body,
+    retry::{retry_after, send_with_retry},
+    ChatStream, LLMConfig, LLMError, LLMProvider,
+};
 
 #[derive(Debug, Serialize)]
 struct OllamaRequest {
@@ -27,13 +33,39 @@ struct OllamaRequest {
 struct ModelOptions {
     #[serde(skip_serializing_if = "Option::is_none")]
     num_ctx: Option<u32>,
+    /// Longest answer, in tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
 }
 
 // For Ollama native format
 #[derive(Debug, Deserialize)]
 struct OllamaNativeResponse {
     #[serde(default)]
-    message: Option<Message>,
+    message: Option<OllamaResponseMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaResponseMessage {
+    #[serde(default)]
+    content: String,
+    /// Reasoning of thinking models, streamed before the answer
+    #[serde(default)]
+    thinking: Option<String>,
+    tool_calls: Option<Vec<ToolCall>>,
+}
+
+/// Models pulled on the Ollama server, from /api/tags
+#[derive(Debug, Deserialize)]
+struct OllamaTags {
+    models: Vec<OllamaModel>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaModel {
+    name: String,
 }
 
 #[derive(Debug)]
@@ -43,8 +75,12 @@ pub struct OllamaProvider {
     model: String,
     keep_alive: Option<i32>,
     context_length: Option<u32>,
+    max_tokens: Option<u32>,
+    stop: Option<Vec<String>>,
+    extra_body: Option<serde_json::Map<String, serde_json::Value>>,
     conversation_history: Vec<Message>,
     tools: Option<Vec<Tool>>,
+    tools_disabled: bool,
 }
 
 impl OllamaProvider {
@@ -53,16 +89,71 @@ impl OllamaProvider {
             .base_url
             .unwrap_or_else(|| "http://localhost:11434/api".to_string());
 
+        let client = http_client::build_client(LLM_REQUEST_TIMEOUT)
+            .map_err(|e| LLMError::ConfigError(e.to_string()))?;
+
         Ok(Self {
-            client: Client::new(),
+            client,
             base_url,
             model: config.model,
             keep_alive: config.keep_alive,
             context_length: config.context_length,
+            max_tokens: config.max_tokens,
+            stop: config.stop,
+            extra_body: config.extra_body,
             conversation_history: Vec::new(),
             tools: config.tools,
+            tools_disabled: false,
         })
     }
+
+    /// The Ollama daemon not running is the usual reason requests can't be sent
+    fn request_error(&self, err: reqwest::Error) -> LLMError {
+        if err.is_connect() {
+            return LLMError::ConfigError(format!(
+                "Could not connect to Ollama at {}. Is ollama running? Try `ollama serve`",
+                self.base_url
+            ));
+        }
+
+        LLMError::ApiError(err.to_string())
+    }
+
+    fn build_request(&self) -> OllamaRequest {
+        OllamaRequest {
+            model: self.model.clone(),
+            keep_alive: self.keep_alive,
+            messages: windowed_history(&self.conversation_history, |message| &message.role),
+            stream: true,
+            tools: self.tools.clone().filter(|_| !self.tools_disabled),
+            options: Some(ModelOptions {
+                num_ctx: self.context_length,
+                num_predict: self.max_tokens,
+                stop: self.stop.clone(),
+            }),
+        }
+    }
+
+    fn status_error(
+        &self,
+        status: StatusCode,
+        retry_after: Option<Duration>,
+        body: &str,
+    ) -> LLMError {
+        if status == StatusCode::NOT_FOUND && body.contains("not found") {
+            return LLMError::ConfigError(format!(
+                "The model '{}' isn't available in Ollama. Download it with `ollama pull {}`",
+                self.model, self.model
+            ));
+        }
+
+        // Ollama itself has no keys, but a proxy in front of it might
+        LLMError::from_status(
+            status.as_u16(),
+            retry_after,
+            format!("HTTP {}: {}", status, body),
+        )
+    }
 }
 
 #[async_trait]
@@ -83,76 +174,126 @@ impl LLMProvider for OllamaProvider {
         // Add user message to history
         self.conversation_history.push(user_message.clone());
 
-        let request = OllamaRequest {
-            model: self.model.clone(),
-            keep_alive: self.keep_alive.clone(),
-            messages: self.conversation_history.clone(),
-            stream: true,
-            tools: self.tools.clone(),
-            options: Some(ModelOptions {
-                num_ctx: self.context_length.clone(),
-                ..Default::default()
-            }),
-        };
+        let mut request = serde_json::to_value(self.build_request())
+            .map_err(|e| LLMError::InvalidRequestError(e.to_string()))?;
+        merge_extra_body(&mut request, self.extra_body.as_ref());
 
-        let response = self
-            .client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| LLMError::ApiError(e.to_string()))?;
+        let response = send_with_retry(|| {
+            self.client
+                .post(&url)
+                .header("Content-Type", "application/json")
+                .json(&request)
+        })
+        .await
+        .map_err(|e| self.request_error(e))?;
 
         if !response.status().is_success() {
             let status = response.status();
+            let retry_after = retry_after(&response);
             let error_text = response
                 .text()
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(LLMError::ApiError(format!(
-                "HTTP {}: {}",
-                status, error_text
-            )));
+            return Err(self.status_error(status, retry_after, &error_text));
         }
 
         // Parse Ollama's native streaming format
         let stream = response.bytes_stream();
-        let mapped_stream = stream.filter_map(|result| async move {
-            match result {
-                Ok(bytes) => {
-                    let text = String::from_utf8_lossy(&bytes);
-
-                    // Ollama native API returns newline-delimited JSON (not SSE format)
-                    for line in text.lines() {
-                        if line.trim().is_empty() {
-                            continue;
-                        }
-
-                        // Try parsing as Ollama native format
-                        if let Ok(response) = serde_json::from_str::<OllamaNativeResponse>(line) {
-                            if let Some(message) = response.message {
-                                let content = message.content;
-                                let tool_calls = message.tool_calls.unwrap_or_default();
-
-                                if !content.is_empty() || !tool_calls.is_empty() {
-                                    let chat_response = ChatResponse {
-                                        content: content,
-                                        tool_calls: Some(tool_calls),
-                                    };
-                                    return Some(Ok(chat_response));
-                                }
-                            }
-                        }
-                    }
-                    None
-                }
-                Err(e) => Some(Err(LLMError::ApiError(e.to_string()))),
-            }
-        });
+        let mapped_stream = stream
+            .scan(LineBuffer::default(), |buffer, result| {
+                let chat_response = match result {
+                    Ok(bytes) => buffer.push(&bytes).map(Ok),
+                    Err(e) => Some(Err(LLMError::ApiError(e.to_string()))),
+                };
+                futures::future::ready(Some(chat_response))
+            })
+            .filter_map(futures::future::ready);
 
         Ok(Box::pin(mapped_stream))
     }
+
+    async fn list_models(&self) -> Result<Vec<String>, LLMError> {
+        let url = format!("{}/tags", self.base_url);
+
+        let response = send_with_retry(|| self.client.get(&url))
+            .await
+            .map_err(|e| self.request_error(e))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let retry_after = retry_after(&response);
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(LLMError::from_status(
+                status.as_u16(),
+                retry_after,
+                format!("HTTP {}: {}", status, error_text),
+            ));
+        }
+
+        let tags: OllamaTags = response
+            .json()
+            .await
+            .map_err(|e| LLMError::ApiError(e.to_string()))?;
+
+        Ok(tags.models.into_iter().map(|model| model.name).collect())
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.tools.is_some()
+    }
+
+    fn clear_history(&mut self) {
+        self.conversation_history
+            .retain(|message| message.role == "system");
+    }
+
+    fn set_tools_enabled(&mut self, enabled: bool) {
+        self.tools_disabled = !enabled;
+    }
+}
+
+/// Ollama streams newline-delimited JSON, and a network chunk can end in the middle
+/// of an object, so only complete lines are parsed
+#[derive(Default)]
+struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    /// Everything in the complete lines received so far, if there is anything
+    fn push(&mut self, bytes: &[u8]) -> Option<ChatResponse> {
+        self.pending.extend_from_slice(bytes);
+
+        let mut content = String::new();
+        let mut reasoning = String::new();
+        let mut tool_calls = Vec::new();
+
+        while let Some(newline) = self.pending.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=newline).collect();
+
+            let Ok(response) = serde_json::from_slice::<OllamaNativeResponse>(&line) else {
+                continue;
+            };
+            if let Some(message) = response.message {
+                content.push_str(&message.content);
+                reasoning.push_str(&message.thinking.unwrap_or_default());
+                tool_calls.extend(message.tool_calls.unwrap_or_default());
+            }
+        }
+
+        if content.is_empty() && reasoning.is_empty() && tool_calls.is_empty() {
+            return None;
+        }
+
+        Some(ChatResponse {
+            content,
+            tool_calls: Some(tool_calls),
+            reasoning: Some(reasoning).filter(|reasoning| !reasoning.is_empty()),
+        })
+    }
 }
 
 #[cfg(test)]
@@ -168,10 +309,144 @@ mod tests {
             base_url: Some("http://localhost:11434".to_string()),
             keep_alive: Some(-1),
             context_length: Some(8192),
+            max_tokens: None,
+            stop: None,
+            extra_body: None,
             tools: None,
         };
 
         let provider = OllamaProvider::new(config).unwrap();
         assert_eq!(provider.model, "gemma3");
     }
+
+    #[tokio::test]
+    async fn test_ollama_context_length_from_env() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        std::env::set_var(crate::ENV_LLM_PROVIDER, "ollama");
+        std::env::set_var(crate::ENV_OLLAMA_KEEP_ALIVE, "-1");
+        std::env::set_var(crate::ENV_OLLAMA_CONTEXT_LENGTH, "16384");
+        std::env::set_var(crate::ENV_MAX_TOKENS, "512");
+        std::env::set_var(crate::ENV_STOP, "\\n```,END");
+
+        let config = crate::get_llm_config().unwrap();
+        std::env::remove_var(crate::ENV_LLM_PROVIDER);
+        std::env::remove_var(crate::ENV_OLLAMA_KEEP_ALIVE);
+        std::env::remove_var(crate::ENV_OLLAMA_CONTEXT_LENGTH);
+        std::env::remove_var(crate::ENV_MAX_TOKENS);
+        std::env::remove_var(crate::ENV_STOP);
+        let provider = OllamaProvider::new(config).unwrap();
+        assert_eq!(provider.keep_alive, Some(-1));
+        assert_eq!(provider.context_length, Some(16384));
+
+        let request = serde_json::to_value(provider.build_request()).unwrap();
+        assert_eq!(request["options"]["num_ctx"], 16384);
+        assert_eq!(request["options"]["num_predict"], 512);
+        assert_eq!(
+            request["options"]["stop"],
+            serde_json::json!(["\n```", "END"])
+        );
+    }
+
+    #[test]
+    fn test_clear_history_keeps_system_prompt() {
+        let config = LLMConfig {
+            provider: "ollama".to_string(),
+            model: "gemma3".to_string(),
+            ..Default::default()
+        };
+        let mut provider = OllamaProvider::new(config).unwrap();
+        provider.with_system_prompt("You are a shell assistant");
+        provider.conversation_history.push(Message {
+            role: "user".to_string(),
+            content: "which disk is full?".to_string(),
+            ..Default::default()
+        });
+
+        provider.clear_history();
+        provider.conversation_history.push(Message {
+            role: "user".to_string(),
+            content: "what's in /tmp?".to_string(),
+            ..Default::default()
+        });
+
+        let messages = provider.build_request().messages;
+        let sent: Vec<(&str, &str)> = messages
+            .iter()
+            .map(|message| (message.role.as_str(), message.content.as_str()))
+            .collect();
+        assert_eq!(
+            sent,
+            vec![
+                ("system", "You are a shell assistant"),
+                ("user", "what's in /tmp?")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_tags() {
+        let tags = r#"{"models":[{"name":"gemma3:4b","size":3338801804},{"name":"qwen3:8b"}]}"#;
+
+        let tags: OllamaTags = serde_json::from_str(tags).unwrap();
+        let names: Vec<String> = tags.models.into_iter().map(|model| model.name).collect();
+        assert_eq!(names, vec!["gemma3:4b", "qwen3:8b"]);
+    }
+
+    #[test]
+    fn test_line_split_across_chunks() {
+        let lines = concat!(
+            r#"{"model":"gemma3","message":{"role":"assistant","content":"Use "},"done":false}"#,
+            "\n",
+            r#"{"model":"gemma3","message":{"role":"assistant","content":"df -h"},"done":false}"#,
+            "\n",
+            r#"{"model":"gemma3","message":{"role":"assistant","content":""},"done":true}"#,
+            "\n",
+        );
+        let split = lines.find("df -h").unwrap();
+
+        let mut buffer = LineBuffer::default();
+        let first = buffer.push(&lines.as_bytes()[..split]).unwrap();
+        assert_eq!(first.content, "Use ");
+        let second = buffer.push(&lines.as_bytes()[split..]).unwrap();
+        assert_eq!(second.content, "df -h");
+        assert!(buffer.pending.is_empty());
+    }
+
+    #[test]
+    fn test_model_not_found_suggests_pull() {
+        let config = LLMConfig {
+            provider: "ollama".to_string(),
+            model: "qwen3:8b".to_string(),
+            ..Default::default()
+        };
+        let provider = OllamaProvider::new(config).unwrap();
+
+        let error = provider.status_error(
+            StatusCode::NOT_FOUND,
+            None,
+            r#"{"error":"model \"qwen3:8b\" not found, try pulling it first"}"#,
+        );
+        assert!(matches!(error, LLMError::ConfigError(_)));
+        assert!(error.to_string().contains("ollama pull qwen3:8b"));
+
+        let error = provider.status_error(StatusCode::INTERNAL_SERVER_ERROR, None, "out of memory");
+        assert_eq!(
+            error.to_string(),
+            "API error: HTTP 500 Internal Server Error: out of memory"
+        );
+
+        let error = provider.status_error(StatusCode::UNAUTHORIZED, None, "bad token");
+        assert!(matches!(error, LLMError::AuthError(_)));
+        assert!(error.guidance().is_some());
+
+        let error = provider.status_error(
+            StatusCode::TOO_MANY_REQUESTS,
+            Some(Duration::from_secs(30)),
+            "slow down",
+        );
+        assert_eq!(
+            error.to_string(),
+            "Rate limited by the provider, try again in 30s"
+        );
+    }
 }
@@ -1,53 +1,245 @@
 use async_openai::{
-    config::OpenAIConfig,
+    config::{Config, OpenAIConfig, OPENAI_API_BASE},
+    error::OpenAIError,
     types::{
-        ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageArgs,
-        ChatCompletionRequestUserMessageArgs, ChatCompletionTool, ChatCompletionToolType,
-        CreateChatCompletionRequestArgs, FunctionObject,
+        ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessageArgs,
+        ChatCompletionRequestMessage, ChatCompletionRequestMessageContentPart,
+        ChatCompletionRequestMessageContentPartImage, ChatCompletionRequestMessageContentPartText,
+        ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestToolMessageArgs,
+        ChatCompletionRequestUserMessageArgs, ChatCompletionRequestUserMessageContent,
+        ChatCompletionTool, ChatCompletionToolType, CreateChatCompletionRequest,
+        CreateChatCompletionRequestArgs, CreateChatCompletionStreamResponse, FunctionObject,
+        ImageUrl, ImageUrlDetail, Stop,
     },
     Client,
 };
 use async_trait::async_trait;
-use futures::stream::StreamExt;
-use std::fmt::Debug;
+use futures::stream::{Stream, StreamExt};
+use reqwest::header::{HeaderMap, HeaderValue};
+use std::{collections::BTreeMap, env, fmt::Debug};
 
 use crate::{
+    http_client::{self, LLM_REQUEST_TIMEOUT},
     llm::{ChatResponse, Message},
-    tools::Tool,
+    tools::{FunctionCall, Tool, ToolCall},
+    ENV_OPENAI_ORG, ENV_OPENAI_PROJECT, ENV_REASONING_EFFORT, ENV_VERBOSITY,
 };
 
-use super::{ChatStream, LLMConfig, LLMError, LLMProvider};
+use super::{
+    history::windowed_history,
+    image,
+    retry::{retry_after, send_with_retry, RateLimitRetry},
+    ChatStream, LLMConfig, LLMError, LLMProvider,
+};
+
+/// Sent to local servers that don't need a key, so the Authorization header stays well-formed
+const LOCAL_SERVER_API_KEY: &str = "local server dummy key";
+
+const REASONING_EFFORTS: &[&str] = &["low", "medium", "high"];
+const VERBOSITIES: &[&str] = &["low", "medium", "high"];
 
 #[derive(Debug)]
 pub struct OpenAIProvider {
     client: Client<OpenAIConfig>,
+    /// Same client async-openai uses, for requests with fields it doesn't know about
+    http_client: reqwest::Client,
     model: String,
     conversation_history: Vec<ChatCompletionRequestMessage>,
     tools: Option<Vec<ChatCompletionTool>>,
+    tools_disabled: bool,
+    stop: Option<Vec<String>>,
+    /// reasoning_effort and verbosity for the models that take them, the token limit,
+    /// and ASK_SH_EXTRA_BODY
+    extra_params: serde_json::Map<String, serde_json::Value>,
 }
 
 impl OpenAIProvider {
     pub fn new(config: LLMConfig) -> Result<Self, LLMError> {
-        let mut openai_config = OpenAIConfig::new().with_api_key(config.api_key);
+        let api_key = match config.api_key.is_empty() {
+            true => LOCAL_SERVER_API_KEY.to_string(),
+            false => config.api_key,
+        };
+        let mut openai_config = OpenAIConfig::new().with_api_key(api_key);
 
         // Set custom base_url if specified
         if let Some(base_url) = config.base_url {
             openai_config = openai_config.with_api_base(&base_url);
         }
 
-        let client = Client::with_config(openai_config);
+        // Usage is billed to the organization and project of org-scoped keys
+        if let Some(org_id) = non_empty_env(ENV_OPENAI_ORG) {
+            openai_config = openai_config.with_org_id(org_id);
+        }
+
+        let http_client =
+            http_client::build_client_with_headers(LLM_REQUEST_TIMEOUT, project_headers()?)
+                .map_err(|e| LLMError::ConfigError(e.to_string()))?;
+        let client = Client::with_config(openai_config).with_http_client(http_client.clone());
 
         let chat_tools: Option<Vec<ChatCompletionTool>> = config
             .tools
             .map(|vec| vec.into_iter().map(|tool| tool.into()).collect());
 
+        let mut extra_params = extra_params(&config.model)?;
+        if let Some(max_tokens) = config.max_tokens {
+            // OpenAI's reasoning models only take the newer name, other servers mostly the older one
+            let name = match client.config().api_base() == OPENAI_API_BASE {
+                true => "max_completion_tokens",
+                false => "max_tokens",
+            };
+            extra_params.insert(name.to_string(), max_tokens.into());
+        }
+        extra_params.extend(config.extra_body.unwrap_or_default());
+
         Ok(Self {
             client,
+            http_client,
             model: config.model,
             conversation_history: Vec::new(),
             tools: chat_tools,
+            tools_disabled: false,
+            stop: config.stop,
+            extra_params,
         })
     }
+
+    fn build_request(
+        &self,
+        messages: Vec<ChatCompletionRequestMessage>,
+    ) -> Result<CreateChatCompletionRequest, LLMError> {
+        let mut request = CreateChatCompletionRequestArgs::default();
+        request.model(&self.model).messages(messages);
+        if let Some(tools) = self.tools.as_ref().filter(|_| !self.tools_disabled) {
+            request.tools(tools.clone());
+        }
+        if let Some(stop) = &self.stop {
+            request.stop(Stop::StringArray(stop.clone()));
+        }
+
+        request
+            .build()
+            .map_err(|e| LLMError::InvalidRequestError(e.to_string()))
+    }
+
+    /// async-openai can't send fields its request type doesn't have,
+    /// so requests with extra parameters are sent as JSON directly
+    async fn create_stream_with_extra_params(
+        &self,
+        request: &CreateChatCompletionRequest,
+    ) -> Result<ChatStream, LLMError> {
+        let mut body = serde_json::to_value(request)
+            .map_err(|e| LLMError::InvalidRequestError(e.to_string()))?;
+        if let Some(object) = body.as_object_mut() {
+            object.extend(self.extra_params.clone());
+            object.insert("stream".to_string(), true.into());
+        }
+
+        let config = self.client.config();
+        let response = send_with_retry(|| {
+            self.http_client
+                .post(config.url("/chat/completions"))
+                .headers(config.headers())
+                .json(&body)
+        })
+        .await
+        .map_err(|e| LLMError::NetworkError(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let retry_after = retry_after(&response);
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(LLMError::from_status(
+                status,
+                retry_after,
+                format!("OpenAI API error: {}", error_text),
+            ));
+        }
+
+        let stream = response
+            .bytes_stream()
+            .scan(EventBuffer::default(), |events, result| {
+                let responses = match result {
+                    Ok(bytes) => events.push(&bytes),
+                    Err(e) => Err(LLMError::NetworkError(e.to_string())),
+                };
+                futures::future::ready(Some(responses))
+            })
+            .flat_map(|responses| {
+                futures::stream::iter(match responses {
+                    Ok(responses) => responses.into_iter().map(Ok).collect(),
+                    Err(e) => vec![Err(e)],
+                })
+            });
+
+        Ok(chat_responses(stream))
+    }
+}
+
+fn non_empty_env(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.trim().is_empty())
+}
+
+/// async-openai only knows about the organization, so the project is sent by the HTTP client
+fn project_headers() -> Result<HeaderMap, LLMError> {
+    let mut headers = HeaderMap::new();
+    if let Some(project) = non_empty_env(ENV_OPENAI_PROJECT) {
+        let value = HeaderValue::from_str(project.trim()).map_err(|_| {
+            LLMError::ConfigError(format!("{} is not a valid project id", ENV_OPENAI_PROJECT))
+        })?;
+        headers.insert("OpenAI-Project", value);
+    }
+
+    Ok(headers)
+}
+
+/// ASK_SH_REASONING_EFFORT and ASK_SH_VERBOSITY, for the models that take them.
+/// They're checked whatever the model is, so a typo doesn't go unnoticed.
+fn extra_params(model: &str) -> Result<serde_json::Map<String, serde_json::Value>, LLMError> {
+    let mut params = serde_json::Map::new();
+
+    let reasoning_effort = env_choice(ENV_REASONING_EFFORT, REASONING_EFFORTS)?;
+    if let Some(effort) = reasoning_effort.filter(|_| supports_reasoning_effort(model)) {
+        params.insert("reasoning_effort".to_string(), effort.into());
+    }
+
+    let verbosity = env_choice(ENV_VERBOSITY, VERBOSITIES)?;
+    if let Some(verbosity) = verbosity.filter(|_| supports_verbosity(model)) {
+        params.insert("verbosity".to_string(), verbosity.into());
+    }
+
+    Ok(params)
+}
+
+fn env_choice(name: &str, choices: &[&str]) -> Result<Option<String>, LLMError> {
+    let Some(value) = env::var(name).ok().filter(|value| !value.trim().is_empty()) else {
+        return Ok(None);
+    };
+
+    let value = value.trim().to_lowercase();
+    match choices.contains(&value.as_str()) {
+        true => Ok(Some(value)),
+        false => Err(LLMError::ConfigError(format!(
+            "{} must be one of {}, not \"{}\"",
+            name,
+            choices.join(", "),
+            value
+        ))),
+    }
+}
+
+/// o-series and GPT-5 models, except the first o1 previews which predate the parameter
+fn supports_reasoning_effort(model: &str) -> bool {
+    let o_series = model.starts_with('o') && model[1..].starts_with(|c: char| c.is_ascii_digit());
+    let early_preview = model.starts_with("o1-mini") || model.starts_with("o1-preview");
+
+    (o_series && !early_preview) || supports_verbosity(model)
+}
+
+fn supports_verbosity(model: &str) -> bool {
+    model.starts_with("gpt-5")
 }
 
 #[async_trait]
@@ -65,57 +257,317 @@ impl LLMProvider for OpenAIProvider {
 
     async fn chat_stream(&mut self, user_message: &Message) -> Result<ChatStream, LLMError> {
         // Add user message to history
-        self.conversation_history.push(
-            ChatCompletionRequestUserMessageArgs::default()
-                .content(user_message.content.as_str())
+        match tool_messages(user_message)? {
+            Some(messages) => self.conversation_history.extend(messages),
+            None => self.conversation_history.push(
+                ChatCompletionRequestUserMessageArgs::default()
+                    .content(user_content(user_message))
+                    .build()
+                    .map_err(|e| LLMError::InvalidRequestError(e.to_string()))?
+                    .into(),
+            ),
+        }
+
+        let messages = windowed_history(&self.conversation_history, |message| match message {
+            ChatCompletionRequestMessage::System(_) => "system",
+            ChatCompletionRequestMessage::Assistant(_) => "assistant",
+            ChatCompletionRequestMessage::Tool(_) => "tool",
+            _ => "user",
+        });
+        let request = self.build_request(messages)?;
+
+        if !self.extra_params.is_empty() {
+            return self.create_stream_with_extra_params(&request).await;
+        }
+
+        // A rate limited request only fails once the stream is read, and without the headers
+        let mut retry = RateLimitRetry::new();
+        let stream = loop {
+            let mut stream = self
+                .client
+                .chat()
+                .create_stream(request.clone())
+                .await
+                .map_err(api_error)?;
+
+            let first = stream.next().await;
+            let rate_limited = matches!(&first, Some(Err(OpenAIError::StreamError(e))) if stream_status(e) == Some(429));
+
+            if !rate_limited || !retry.wait(None).await {
+                break futures::stream::iter(first).chain(stream);
+            }
+        };
+
+        // Convert OpenAI stream to a stream using LLMError
+        let api_base = self.client.config().api_base().to_string();
+        Ok(chat_responses(stream.map(move |result| {
+            result.map_err(|err| stream_error(err, &api_base))
+        })))
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, LLMError> {
+        let response = self.client.models().list().await.map_err(api_error)?;
+
+        Ok(response.data.into_iter().map(|model| model.id).collect())
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.tools.is_some()
+    }
+
+    fn clear_history(&mut self) {
+        self.conversation_history
+            .retain(|message| matches!(message, ChatCompletionRequestMessage::System(_)));
+    }
+
+    fn set_tools_enabled(&mut self, enabled: bool) {
+        self.tools_disabled = !enabled;
+    }
+}
+
+/// Tool results answer the assistant turn that asked for them, one tool message per call
+/// linked by its id. Without ids, as from some OpenAI compatible servers, they're sent as a
+/// user message instead.
+fn tool_messages(message: &Message) -> Result<Option<Vec<ChatCompletionRequestMessage>>, LLMError> {
+    let linked = message.role == "tool"
+        && !message.tool_results.is_empty()
+        && message
+            .tool_results
+            .iter()
+            .all(|result| result.tool_call_id.is_some());
+    if !linked {
+        return Ok(None);
+    }
+
+    let invalid_request = |e: OpenAIError| LLMError::InvalidRequestError(e.to_string());
+    let tool_calls = message
+        .tool_results
+        .iter()
+        .map(|result| ChatCompletionMessageToolCall {
+            id: result.tool_call_id.clone().unwrap_or_default(),
+            r#type: ChatCompletionToolType::Function,
+            function: async_openai::types::FunctionCall {
+                name: result.function_call.name.clone(),
+                arguments: result.function_call.arguments.to_string(),
+            },
+        })
+        .collect::<Vec<_>>();
+
+    let mut messages = vec![ChatCompletionRequestAssistantMessageArgs::default()
+        .tool_calls(tool_calls)
+        .build()
+        .map_err(invalid_request)?
+        .into()];
+    for result in &message.tool_results {
+        let content = match &result.content {
+            serde_json::Value::String(text) => text.clone(),
+            value => value.to_string(),
+        };
+        messages.push(
+            ChatCompletionRequestToolMessageArgs::default()
+                .tool_call_id(result.tool_call_id.clone().unwrap_or_default())
+                .content(content)
                 .build()
-                .map_err(|e| LLMError::InvalidRequestError(e.to_string()))?
+                .map_err(invalid_request)?
                 .into(),
         );
+    }
 
-        let request = match &self.tools {
-            Some(tools) => CreateChatCompletionRequestArgs::default()
-                .model(&self.model)
-                .messages(self.conversation_history.clone())
-                .tools(tools.clone())
-                .build(),
-            None => CreateChatCompletionRequestArgs::default()
-                .model(&self.model)
-                .messages(self.conversation_history.clone())
-                .build(),
-        }
-        .map_err(|e| LLMError::InvalidRequestError(e.to_string()))?;
-
-        let stream = self
-            .client
-            .chat()
-            .create_stream(request)
-            .await
-            .map_err(|e| LLMError::ApiError(e.to_string()))?;
+    Ok(Some(messages))
+}
 
-        // Convert OpenAI stream to a stream using LLMError
-        let mapped_stream = stream.map(|result| match result {
-            Ok(response) => {
-                let content = response
-                    .choices
-                    .iter()
-                    .filter_map(|choice| choice.delta.content.as_ref())
-                    .fold(String::new(), |mut acc, s| {
-                        acc.push_str(s);
-                        acc
-                    });
-
-                let chat_response = ChatResponse {
-                    content: content,
-                    tool_calls: None,
-                };
+/// Text only, or the text followed by the images
+fn user_content(message: &Message) -> ChatCompletionRequestUserMessageContent {
+    if message.images.is_empty() {
+        return ChatCompletionRequestUserMessageContent::Text(message.content.clone());
+    }
 
-                Ok(chat_response)
-            }
-            Err(err) => Err(LLMError::ApiError(err.to_string())),
+    let text = ChatCompletionRequestMessageContentPart::Text(
+        ChatCompletionRequestMessageContentPartText {
+            r#type: "text".to_string(),
+            text: message.content.clone(),
+        },
+    );
+    let images = message.images.iter().map(|image| {
+        ChatCompletionRequestMessageContentPart::Image(
+            ChatCompletionRequestMessageContentPartImage {
+                r#type: "image_url".to_string(),
+                image_url: ImageUrl {
+                    url: image::data_url(image),
+                    detail: ImageUrlDetail::Auto,
+                },
+            },
+        )
+    });
+
+    ChatCompletionRequestUserMessageContent::Array(std::iter::once(text).chain(images).collect())
+}
+
+/// OpenAI-compatible servers don't always answer in the shape the OpenAI API does.
+/// Point at the base URL then, as it's most likely missing a path like /v1.
+fn stream_error(err: OpenAIError, api_base: &str) -> LLMError {
+    if let OpenAIError::StreamError(message) = &err {
+        if let Some(status @ (401 | 403 | 429)) = stream_status(message) {
+            return LLMError::from_status(status, None, message.clone());
+        }
+    }
+
+    match err {
+        OpenAIError::JSONDeserialize(_) | OpenAIError::StreamError(_)
+            if api_base != OPENAI_API_BASE =>
+        {
+            LLMError::ApiError(format!(
+                "{} (is {} an OpenAI-compatible endpoint? It usually ends with /v1)",
+                err, api_base
+            ))
+        }
+        err => api_error(err),
+    }
+}
+
+/// async-openai only hands over the HTTP status of a failed stream as text
+fn stream_status(message: &str) -> Option<u16> {
+    message
+        .strip_prefix("Invalid status code: ")?
+        .get(..3)?
+        .parse()
+        .ok()
+}
+
+/// OpenAI reports a wrong key with an error code, the HTTP status isn't kept
+fn api_error(err: OpenAIError) -> LLMError {
+    match err {
+        OpenAIError::ApiError(error)
+            if error.code.as_ref().and_then(|code| code.as_str()) == Some("invalid_api_key") =>
+        {
+            LLMError::AuthError(error.message)
+        }
+        err => LLMError::ApiError(err.to_string()),
+    }
+}
+
+/// Server-sent events of a streamed chat completion, which can be split across chunks
+#[derive(Debug, Default)]
+struct EventBuffer {
+    pending: Vec<u8>,
+}
+
+impl EventBuffer {
+    /// The chat completion chunks of every complete line received so far
+    fn push(&mut self, bytes: &[u8]) -> Result<Vec<CreateChatCompletionStreamResponse>, LLMError> {
+        self.pending.extend_from_slice(bytes);
+
+        let Some(end) = self.pending.iter().rposition(|&b| b == b'\n') else {
+            return Ok(Vec::new());
+        };
+        let lines: Vec<u8> = self.pending.drain(..=end).collect();
+
+        String::from_utf8_lossy(&lines)
+            .lines()
+            .filter_map(|line| line.strip_prefix("data:"))
+            .map(str::trim)
+            .filter(|data| *data != "[DONE]")
+            .map(|data| {
+                serde_json::from_str(data).map_err(|e| {
+                    LLMError::ApiError(format!("Failed to parse stream event: {}: {}", e, data))
+                })
+            })
+            .collect()
+    }
+}
+
+/// The chunks as chat responses, leaving out the empty ones. A model calling tools often sends
+/// no text at all, so the tool calls left when the stream ends are handed out last, in case
+/// the server never sent a finish reason.
+fn chat_responses(
+    stream: impl Stream<Item = Result<CreateChatCompletionStreamResponse, LLMError>> + Send + 'static,
+) -> ChatStream {
+    let stream = stream
+        .map(Some)
+        .chain(futures::stream::iter([None]))
+        .scan(ToolCallBuffer::default(), |buffer, chunk| {
+            let chat_response = match chunk {
+                Some(Ok(response)) => Ok(buffer.push(response)),
+                Some(Err(e)) => Err(e),
+                None => Ok(ChatResponse {
+                    content: String::new(),
+                    tool_calls: buffer.flush(),
+                    reasoning: None,
+                }),
+            };
+            futures::future::ready(Some(chat_response))
+        })
+        .filter(|result| {
+            futures::future::ready(match result {
+                Ok(response) => !response.content.is_empty() || response.tool_calls.is_some(),
+                Err(_) => true,
+            })
         });
 
-        Ok(Box::pin(mapped_stream))
+    Box::pin(stream)
+}
+
+/// Tool calls stream in pieces, their arguments being partial JSON until the model is done.
+/// They're only handed out once there's a finish reason, which some OpenAI compatible servers
+/// set to `stop` rather than `tool_calls`.
+#[derive(Debug, Default)]
+struct ToolCallBuffer {
+    /// Id, name and arguments of each tool call, by index
+    tool_calls: BTreeMap<i32, (Option<String>, String, String)>,
+}
+
+impl ToolCallBuffer {
+    fn push(&mut self, response: CreateChatCompletionStreamResponse) -> ChatResponse {
+        let mut content = String::new();
+        let mut tool_calls = None;
+
+        for choice in response.choices {
+            if let Some(text) = choice.delta.content {
+                content.push_str(&text);
+            }
+
+            for chunk in choice.delta.tool_calls.unwrap_or_default() {
+                let (id, name, arguments) = self.tool_calls.entry(chunk.index).or_default();
+                // The id comes with the first piece only
+                if chunk.id.is_some() {
+                    *id = chunk.id;
+                }
+                if let Some(function) = chunk.function {
+                    name.push_str(&function.name.unwrap_or_default());
+                    arguments.push_str(&function.arguments.unwrap_or_default());
+                }
+            }
+
+            if choice.finish_reason.is_some() {
+                tool_calls = self.flush();
+            }
+        }
+
+        ChatResponse {
+            content,
+            tool_calls,
+            reasoning: None,
+        }
+    }
+
+    /// The complete tool calls, None when there aren't any
+    fn flush(&mut self) -> Option<Vec<ToolCall>> {
+        if self.tool_calls.is_empty() {
+            return None;
+        }
+
+        let tool_calls = std::mem::take(&mut self.tool_calls)
+            .into_values()
+            .map(|(id, name, arguments)| ToolCall {
+                id,
+                function: FunctionCall {
+                    name,
+                    arguments: serde_json::from_str(&arguments)
+                        .unwrap_or_else(|_| serde_json::json!({})),
+                },
+            })
+            .collect();
+        Some(tool_calls)
     }
 }
 
@@ -136,9 +588,14 @@ impl From<Tool> for ChatCompletionTool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        renderer::{OutputRenderer, RendererKind},
+        tools::ToolCallResult,
+    };
 
     #[tokio::test]
     async fn test_openai_provider_creation() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
         let config = LLMConfig {
             provider: "openai".to_string(),
             model: "gpt-3.5-turbo".to_string(),
@@ -146,10 +603,324 @@ mod tests {
             base_url: None,
             keep_alive: None,
             context_length: None,
+            max_tokens: Some(300),
+            stop: Some(vec!["\n```".to_string()]),
+            extra_body: serde_json::json!({ "seed": 42 }).as_object().cloned(),
             tools: None,
         };
 
         let provider = OpenAIProvider::new(config).unwrap();
         assert_eq!(provider.model, "gpt-3.5-turbo");
+        assert_eq!(provider.extra_params["max_completion_tokens"], 300);
+        assert_eq!(provider.extra_params["seed"], 42);
+
+        let request = serde_json::to_value(provider.build_request(Vec::new()).unwrap()).unwrap();
+        assert_eq!(request["stop"], serde_json::json!(["\n```"]));
+    }
+
+    #[test]
+    fn test_clear_history_keeps_system_prompt() {
+        let config = LLMConfig {
+            provider: "openai".to_string(),
+            model: "gpt-4o".to_string(),
+            api_key: "test-key".to_string(),
+            ..Default::default()
+        };
+        let mut provider = OpenAIProvider::new(config).unwrap();
+        let user = |content: &str| -> ChatCompletionRequestMessage {
+            ChatCompletionRequestUserMessageArgs::default()
+                .content(content)
+                .build()
+                .unwrap()
+                .into()
+        };
+        provider.with_system_prompt("You are a shell assistant");
+        provider
+            .conversation_history
+            .push(user("which disk is full?"));
+
+        provider.clear_history();
+        provider.conversation_history.push(user("what's in /tmp?"));
+
+        let request = provider
+            .build_request(provider.conversation_history.clone())
+            .unwrap();
+        let messages = serde_json::to_value(request).unwrap()["messages"].clone();
+        assert_eq!(messages.as_array().unwrap().len(), 2);
+        assert_eq!(messages[0]["role"], "system");
+        assert_eq!(messages[0]["content"], "You are a shell assistant");
+        assert_eq!(messages[1]["content"], "what's in /tmp?");
+    }
+
+    #[test]
+    fn test_organization_and_project() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let config = LLMConfig {
+            provider: "openai".to_string(),
+            model: "gpt-4o".to_string(),
+            api_key: "test-key".to_string(),
+            ..Default::default()
+        };
+
+        env::set_var(ENV_OPENAI_ORG, "org-billing");
+        env::set_var(ENV_OPENAI_PROJECT, "proj_ask_sh");
+        let provider = OpenAIProvider::new(config.clone());
+        let headers = project_headers().unwrap();
+        env::set_var(ENV_OPENAI_PROJECT, "not\na header");
+        let invalid_project = project_headers();
+        env::remove_var(ENV_OPENAI_ORG);
+        env::remove_var(ENV_OPENAI_PROJECT);
+
+        let config_headers = provider.unwrap().client.config().headers();
+        assert_eq!(config_headers["OpenAI-Organization"], "org-billing");
+        assert_eq!(headers["OpenAI-Project"], "proj_ask_sh");
+        assert!(invalid_project.is_err());
+
+        let provider = OpenAIProvider::new(config).unwrap();
+        assert_eq!(provider.client.config().org_id(), "");
+        assert!(project_headers().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_deepseek_provider_creation() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        std::env::set_var(crate::ENV_LLM_PROVIDER, "deepseek");
+        std::env::set_var(crate::ENV_DEEPSEEK_API_KEY, "test-key");
+        std::env::remove_var(crate::ENV_DEEPSEEK_MODEL);
+        std::env::set_var(crate::ENV_MAX_TOKENS, "300");
+
+        let config = crate::get_llm_config().unwrap();
+        std::env::remove_var(crate::ENV_LLM_PROVIDER);
+        std::env::remove_var(crate::ENV_DEEPSEEK_API_KEY);
+        std::env::remove_var(crate::ENV_MAX_TOKENS);
+        let provider = OpenAIProvider::new(config).unwrap();
+        assert_eq!(provider.extra_params["max_tokens"], 300);
+        assert_eq!(
+            provider.client.config().api_base(),
+            "https://api.deepseek.com"
+        );
+        assert_eq!(provider.model, "deepseek-chat");
+    }
+
+    #[tokio::test]
+    async fn test_local_server_without_api_key() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        std::env::set_var(crate::ENV_LLM_PROVIDER, "openai");
+        std::env::set_var(crate::ENV_OPENAI_BASE_URL, "http://localhost:1234/v1");
+        std::env::remove_var(crate::ENV_OPENAI_API_KEY);
+
+        let config = crate::get_llm_config().unwrap();
+        std::env::remove_var(crate::ENV_OPENAI_BASE_URL);
+        std::env::remove_var(crate::ENV_LLM_PROVIDER);
+        assert_eq!(config.api_key, "");
+
+        let provider = OpenAIProvider::new(config).unwrap();
+        assert_eq!(
+            provider.client.config().api_base(),
+            "http://localhost:1234/v1"
+        );
+
+        let err = serde_json::from_str::<CreateChatCompletionStreamResponse>("{}").unwrap_err();
+        let message =
+            stream_error(OpenAIError::JSONDeserialize(err), "http://localhost:1234").to_string();
+        assert!(message.contains("is http://localhost:1234 an OpenAI-compatible endpoint"));
+
+        let unauthorized = OpenAIError::StreamError("Invalid status code: 401 Unauthorized".into());
+        let error = stream_error(unauthorized, "http://localhost:1234");
+        assert!(matches!(error, LLMError::AuthError(_)));
+
+        let invalid_key = OpenAIError::ApiError(async_openai::error::ApiError {
+            message: "Incorrect API key provided".to_string(),
+            r#type: Some("invalid_request_error".to_string()),
+            param: None,
+            code: Some("invalid_api_key".into()),
+        });
+        assert!(matches!(api_error(invalid_key), LLMError::AuthError(_)));
+    }
+
+    #[test]
+    fn test_reasoning_effort() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        std::env::set_var(ENV_REASONING_EFFORT, "High");
+        std::env::remove_var(ENV_VERBOSITY);
+
+        let params = extra_params("o3-mini").unwrap();
+        assert_eq!(params["reasoning_effort"], "high");
+        assert!(extra_params("gpt-4o").unwrap().is_empty());
+        assert!(extra_params("o1-mini").unwrap().is_empty());
+
+        std::env::set_var(ENV_VERBOSITY, "low");
+        let params = extra_params("gpt-5-mini").unwrap();
+        assert_eq!(params["reasoning_effort"], "high");
+        assert_eq!(params["verbosity"], "low");
+
+        std::env::set_var(ENV_REASONING_EFFORT, "extreme");
+        let err = extra_params("gpt-4o").unwrap_err().to_string();
+        std::env::remove_var(ENV_REASONING_EFFORT);
+        std::env::remove_var(ENV_VERBOSITY);
+        assert!(err.contains("must be one of low, medium, high"));
+    }
+
+    #[test]
+    fn test_event_buffer_handles_split_lines() {
+        let mut events = EventBuffer::default();
+        let data = concat!(
+            r#"data: {"id":"1","object":"chat.completion.chunk","created":0,"model":"o3","#,
+            r#""choices":[{"index":0,"delta":{"content":"Hi"},"finish_reason":null}]}"#,
+            "\n\ndata: [DONE]\n\n"
+        );
+        let (first, second) = data.split_at(40);
+
+        assert!(events.push(first.as_bytes()).unwrap().is_empty());
+        let responses = events.push(second.as_bytes()).unwrap();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].choices[0].delta.content.as_deref(), Some("Hi"));
+    }
+
+    fn chunk(
+        delta: serde_json::Value,
+        finish_reason: serde_json::Value,
+    ) -> CreateChatCompletionStreamResponse {
+        serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": "gpt-4o",
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }]
+        }))
+        .unwrap()
+    }
+
+    /// Streams the same chunks for every message
+    #[derive(Debug)]
+    struct ScriptedProvider(Vec<CreateChatCompletionStreamResponse>);
+
+    #[async_trait]
+    impl LLMProvider for ScriptedProvider {
+        fn with_system_prompt(&mut self, _prompt: &str) {}
+
+        async fn chat_stream(&mut self, _user_message: &Message) -> Result<ChatStream, LLMError> {
+            Ok(chat_responses(futures::stream::iter(
+                self.0.clone().into_iter().map(Ok),
+            )))
+        }
+
+        async fn list_models(&self) -> Result<Vec<String>, LLMError> {
+            Ok(Vec::new())
+        }
+
+        fn supports_tools(&self) -> bool {
+            true
+        }
+
+        fn clear_history(&mut self) {}
+
+        fn set_tools_enabled(&mut self, _enabled: bool) {}
+    }
+
+    #[tokio::test]
+    async fn test_answer_with_only_tool_calls() {
+        let tool_call = serde_json::json!({ "role": "assistant", "content": "", "tool_calls": [{
+            "index": 0,
+            "id": "call_1",
+            "type": "function",
+            "function": { "name": "execute_command", "arguments": r#"{"command":"df -h"}"# }
+        }]});
+        let finish_reasons = [
+            serde_json::json!("tool_calls"),
+            // Some servers say stop, or don't say anything before the stream ends
+            serde_json::json!("stop"),
+            serde_json::Value::Null,
+        ];
+
+        for finish_reason in finish_reasons {
+            let mut provider = ScriptedProvider(vec![
+                chunk(tool_call.clone(), serde_json::Value::Null),
+                chunk(serde_json::json!({}), finish_reason.clone()),
+            ]);
+            let mut renderer = OutputRenderer::new(RendererKind::Plain);
+
+            let response = provider
+                .chat(&Message::default(), &mut renderer)
+                .await
+                .unwrap();
+            assert_eq!(response.content, "");
+            let tool_calls = response.tool_calls.expect("tool calls were dropped");
+            assert_eq!(tool_calls.len(), 1, "finish reason {}", finish_reason);
+            assert_eq!(tool_calls[0].function.arguments["command"], "df -h");
+        }
+    }
+
+    #[test]
+    fn test_tool_calls_wait_for_finish_reason() {
+        let arguments = |index: i32, name: Option<&str>, arguments: &str| {
+            serde_json::json!({ "tool_calls": [{
+                "index": index,
+                "id": name.map(|_| format!("call_{}", index)),
+                "function": { "name": name, "arguments": arguments }
+            }]})
+        };
+
+        let mut buffer = ToolCallBuffer::default();
+        let chunks = [
+            arguments(0, Some("execute_command"), r#"{"comm"#),
+            arguments(1, Some("web_search"), r#"{"query":"#),
+            arguments(0, None, r#"and":"ls"}"#),
+            arguments(1, None, r#""tmux"}"#),
+        ];
+        for delta in chunks {
+            let response = buffer.push(chunk(delta, serde_json::Value::Null));
+            assert!(response.tool_calls.is_none());
+        }
+
+        let response = buffer.push(chunk(serde_json::json!({}), "tool_calls".into()));
+        let tool_calls = response.tool_calls.unwrap();
+        assert_eq!(tool_calls.len(), 2);
+        assert_eq!(tool_calls[0].id.as_deref(), Some("call_0"));
+        assert_eq!(tool_calls[0].function.name, "execute_command");
+        assert_eq!(tool_calls[0].function.arguments["command"], "ls");
+        assert_eq!(tool_calls[1].id.as_deref(), Some("call_1"));
+        assert_eq!(tool_calls[1].function.arguments["query"], "tmux");
+    }
+
+    #[test]
+    fn test_tool_results_are_linked_to_their_calls() {
+        let result = |id: Option<&str>, command: &str, output: &str| ToolCallResult {
+            tool_call_id: id.map(str::to_string),
+            function_call: FunctionCall {
+                name: "execute_command".to_string(),
+                arguments: serde_json::json!({ "command": command }),
+            },
+            content: output.into(),
+        };
+        let message = |tool_results: Vec<ToolCallResult>| Message {
+            role: "tool".to_string(),
+            content: "[]".to_string(),
+            tool_results,
+            ..Default::default()
+        };
+
+        let messages = tool_messages(&message(vec![
+            result(Some("call_a"), "ls", "notes.txt"),
+            result(Some("call_b"), "pwd", "/home/me"),
+        ]))
+        .unwrap()
+        .unwrap();
+        let messages = serde_json::to_value(messages).unwrap();
+
+        assert_eq!(messages[0]["role"], "assistant");
+        assert_eq!(messages[0]["tool_calls"][1]["id"], "call_b");
+        assert_eq!(
+            messages[0]["tool_calls"][1]["function"]["arguments"],
+            r#"{"command":"pwd"}"#
+        );
+        assert_eq!(messages[1]["role"], "tool");
+        assert_eq!(messages[1]["tool_call_id"], "call_a");
+        assert_eq!(messages[1]["content"], "notes.txt");
+        assert_eq!(messages[2]["tool_call_id"], "call_b");
+
+        // Without ids, the results can't be linked, so they're sent as before
+        let unlinked = message(vec![result(None, "ls", "notes.txt")]);
+        assert!(tool_messages(&unlinked).unwrap().is_none());
     }
 }
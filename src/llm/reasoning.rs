@@ -0,0 +1,81 @@
+const THINK_START: &str = "<think>";
+const THINK_END: &str = "</think>";
+
+/// Separates `<think>...</think>` reasoning from the answer in streamed text.
+/// A tag can be split across chunks, so text that may be the start of a tag is held back.
+#[derive(Debug, Default)]
+pub struct ThinkTagSplitter {
+    in_think: bool,
+    pending: String,
+}
+
+impl ThinkTagSplitter {
+    /// Returns the reasoning and the answer text found in the chunk
+    pub fn push(&mut self, text: &str) -> (String, String) {
+        self.pending.push_str(text);
+
+        let mut reasoning = String::new();
+        let mut content = String::new();
+
+        loop {
+            let (tag, output) = match self.in_think {
+                true => (THINK_END, &mut reasoning),
+                false => (THINK_START, &mut content),
+            };
+
+            match self.pending.find(tag) {
+                Some(index) => {
+                    output.push_str(&self.pending[..index]);
+                    self.pending.drain(..index + tag.len());
+                    self.in_think = !self.in_think;
+                }
+                None => {
+                    let split = self.pending.len() - partial_tag_len(&self.pending, tag);
+                    output.push_str(&self.pending[..split]);
+                    self.pending.drain(..split);
+                    break;
+                }
+            }
+        }
+
+        (reasoning, content)
+    }
+
+    /// Returns the text held back once the stream is over
+    pub fn finish(&mut self) -> (String, String) {
+        let pending = std::mem::take(&mut self.pending);
+        match self.in_think {
+            true => (pending, String::new()),
+            false => (String::new(), pending),
+        }
+    }
+}
+
+/// Length of the longest end of `text` that is the beginning of `tag`
+fn partial_tag_len(text: &str, tag: &str) -> usize {
+    (1..tag.len())
+        .rev()
+        .find(|&len| text.ends_with(&tag[..len]))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_think_tags_across_chunks() {
+        let mut splitter = ThinkTagSplitter::default();
+
+        assert_eq!(splitter.push("<thi"), (String::new(), String::new()));
+        assert_eq!(
+            splitter.push("nk>The user wants ls</th"),
+            ("The user wants ls".to_string(), String::new())
+        );
+        assert_eq!(
+            splitter.push("ink>Run `ls` <"),
+            (String::new(), "Run `ls` ".to_string())
+        );
+        assert_eq!(splitter.finish(), (String::new(), "<".to_string()));
+    }
+}
@@ -0,0 +1,98 @@
+use chrono::{DateTime, Utc};
+use reqwest::{header::RETRY_AFTER, RequestBuilder, Response, StatusCode};
+use std::time::{Duration, Instant};
+
+/// Give up retrying rate limited requests after this long
+const MAX_RETRY_TIME: Duration = Duration::from_secs(60);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Waits between retries of a rate limited request
+pub(super) struct RateLimitRetry {
+    started: Instant,
+    attempt: u32,
+}
+
+impl RateLimitRetry {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            attempt: 0,
+        }
+    }
+
+    /// Wait as long as the provider asked, or back off exponentially when it didn't say.
+    /// Returns false without waiting when the retry time is used up.
+    pub async fn wait(&mut self, retry_after: Option<Duration>) -> bool {
+        let delay = retry_after.unwrap_or(INITIAL_BACKOFF * 2u32.saturating_pow(self.attempt));
+
+        if self.started.elapsed() + delay > MAX_RETRY_TIME {
+            return false;
+        }
+
+        eprintln!(
+            "⏳ Rate limited by the provider, retrying in {:.1}s",
+            delay.as_secs_f32()
+        );
+        tokio::time::sleep(delay).await;
+        self.attempt += 1;
+
+        true
+    }
+}
+
+/// Send the request, retrying while the provider answers 429 Too Many Requests.
+/// The last response is returned as is once retrying is no longer worth it.
+pub(super) async fn send_with_retry(
+    request: impl Fn() -> RequestBuilder,
+) -> Result<Response, reqwest::Error> {
+    let mut retry = RateLimitRetry::new();
+
+    loop {
+        let response = request().send().await?;
+        if response.status() != StatusCode::TOO_MANY_REQUESTS {
+            return Ok(response);
+        }
+
+        if !retry.wait(retry_after(&response)).await {
+            return Ok(response);
+        }
+    }
+}
+
+/// How long the provider asked to wait before retrying, if it said
+pub(super) fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after)
+}
+
+/// Retry-After holds either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(seconds) = value.parse::<f64>() {
+        return Duration::try_from_secs_f64(seconds).ok();
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.with_timezone(&Utc) - Utc::now();
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("5"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_retry_after(" 0.5 "), Some(Duration::from_millis(500)));
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
@@ -1,190 +1,317 @@
 use dotenv::dotenv;
 use std::{
     env::{self},
-    io::{self, BufRead},
+    io::{self, IsTerminal},
+    path::Path,
 };
 
-mod chat_handler;
-mod command_analyser;
-mod llm;
-mod prompts;
-mod tmux_command_executor;
-mod tools;
-mod user_system_info;
+mod init_script;
 
-use chat_handler::ChatHandler;
-use llm::{LLMConfig, LLMError};
+use init_script::{get_init_script, Shell};
+
+use ask_sh::{
+    clipboard,
+    command_analyser::CommandAnalyser,
+    cost, get_llm_config, is_debug_enabled,
+    llm::{create_llm_provider, image, LLMConfig, LLMProvider},
+    load_config_env, redact,
+    renderer::{OutputRenderer, RendererKind},
+    theme::icon,
+    tools, transcript, update_check, ChatHandler, ENV_NO_UPDATE, ENV_QUIET,
+};
 
 // args
 const ARG_DEBUG: &str = "--debug_ask_sh";
 const ARG_VERSION: &str = "--version";
 const ARG_VERSION_SHORT: &str = "-v";
+const ARG_RAW: &str = "--raw";
+const ARG_PIPE: &str = "--pipe";
+const ARG_CONTINUE: &str = "--continue";
+const ARG_QUIET: &str = "--quiet";
+const ARG_NO_TOOLS: &str = "--no-tools";
 
-const ARG_STRINGS: &[&str] = &[ARG_DEBUG, ARG_VERSION, ARG_VERSION_SHORT];
+const ARG_STRINGS: &[&str] = &[
+    ARG_DEBUG,
+    ARG_VERSION,
+    ARG_VERSION_SHORT,
+    ARG_RAW,
+    ARG_PIPE,
+    ARG_CONTINUE,
+    ARG_QUIET,
+    ARG_NO_TOOLS,
+];
 
-// special arg
+// special args
 const ARG_INIT: &str = "--init";
+const ARG_COPY: &str = "--copy";
+const ARG_CHECK_UPDATE: &str = "--check-update";
+const ARG_EXPORT_LAST: &str = "--export-last";
+const ARG_LIST_MODELS: &str = "--list-models";
+const ARG_TOOLS: &str = "--tools";
+const ARG_SYSTEM: &str = "--system";
+const ARG_IMAGE: &str = "--image";
+const ARG_EXPLAIN: &str = "--explain";
+const ARG_MAX_TOKENS: &str = "--max-tokens";
+
+fn copy_command(args: Vec<String>) {
+    let text = if args.is_empty() {
+        io::read_to_string(io::stdin()).unwrap_or_default()
+    } else {
+        args.join(" ")
+    };
+
+    match clipboard::copy_to_clipboard(text.trim_end()) {
+        Ok(_) => println!("{}Copied to clipboard: {}", icon("📋 "), text.trim_end()),
+        Err(e) => {
+            eprintln!("{}{}", icon("❌ "), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Take every `--image <path>` out of the words, returning the paths
+fn take_image_paths(words: &mut Vec<String>) -> Vec<String> {
+    let mut paths = Vec::new();
 
-// LLM provider settings
-const ENV_LLM_PROVIDER: &str = "ASK_SH_LLM_PROVIDER";
-const ENV_OPENAI_API_KEY: &str = "ASK_SH_OPENAI_API_KEY";
-const ENV_OPENAI_MODEL: &str = "ASK_SH_OPENAI_MODEL";
-const ENV_OPENAI_BASE_URL: &str = "ASK_SH_OPENAI_BASE_URL";
-const ENV_ANTHROPIC_API_KEY: &str = "ASK_SH_ANTHROPIC_API_KEY";
-const ENV_ANTHROPIC_MODEL: &str = "ASK_SH_ANTHROPIC_MODEL";
-const ENV_OLLAMA_BASE_URL: &str = "ASK_SH_OLLAMA_BASE_URL";
-const ENV_OLLAMA_MODEL: &str = "ASK_SH_OLLAMA_MODEL";
-const ENV_OLLAMA_KEEP_ALIVE: &str = "ASK_SH_OLLAMA_KEEP_ALIVE";
-const ENV_OLLAMA_CONTEXT_LENGTH: &str = "ASK_SH_OLLAMA_CONTEXT_LENGTH";
-const ENV_SEARXNG_BASE_URL: &str = "ASK_SH_SEARXNG_BASE_URL";
-
-fn get_llm_config() -> Result<LLMConfig, LLMError> {
-    // Select provider (default is OpenAI)
-    let provider = env::var(ENV_LLM_PROVIDER).unwrap_or_else(|_| "openai".to_string());
-
-    match provider.as_str() {
-        "openai" => {
-            let api_key = env::var(ENV_OPENAI_API_KEY)
-                .map_err(|_| LLMError::ConfigError("OpenAI API key not found".to_string()))?;
-
-            let model = env::var(ENV_OPENAI_MODEL).unwrap_or_else(|_| "gpt-3.5-turbo".to_string());
-
-            let base_url = env::var(ENV_OPENAI_BASE_URL).ok();
-
-            Ok(LLMConfig {
-                provider,
-                api_key,
-                model,
-                base_url,
-                keep_alive: None,
-                context_length: None,
-                tools: None, // Some(tools::get_available_tools()),
-            })
-        }
-        "anthropic" => {
-            let api_key = env::var(ENV_ANTHROPIC_API_KEY)
-                .map_err(|_| LLMError::ConfigError("Anthropic API key not found".to_string()))?;
-
-            let model = env::var(ENV_ANTHROPIC_MODEL)
-                .unwrap_or_else(|_| "claude-3-5-sonnet-latest".to_string());
-
-            Ok(LLMConfig {
-                provider,
-                api_key,
-                model,
-                base_url: None, // Anthropic does not support custom endpoints
-                keep_alive: None,
-                context_length: None,
-                tools: Some(tools::get_available_tools()),
-            })
-        }
-        "ollama" => {
-            let api_key = "ollama dummy key".to_string();
-
-            let model = env::var(ENV_OLLAMA_MODEL).unwrap_or_else(|_| "gemma3:4b".to_string());
-
-            let base_url = env::var(ENV_OLLAMA_BASE_URL).ok();
-
-            let keep_alive: Option<i32> = env::var(ENV_OLLAMA_KEEP_ALIVE)
-                .ok()
-                .and_then(|s| s.parse().ok());
-
-            let context_length: Option<u32> = env::var(ENV_OLLAMA_CONTEXT_LENGTH)
-                .ok()
-                .and_then(|s| s.parse().ok());
-
-            Ok(LLMConfig {
-                provider,
-                api_key,
-                model,
-                base_url,
-                keep_alive,
-                context_length,
-                tools: Some(tools::get_available_tools()),
-            })
-        }
-        _ => Err(LLMError::ConfigError(format!(
-            "Unknown provider: {}",
-            provider
-        ))),
+    while let Some(index) = words.iter().position(|word| word == ARG_IMAGE) {
+        words.remove(index);
+        if index < words.len() {
+            paths.push(words.remove(index));
+        }
     }
+
+    paths
 }
 
-fn print_init_script() {
-    print!(
-        r#"# This function is automatically generated by ask-sh --init
-# ask.sh shell function v2
-ask() {{
-    if ! command -v ask-sh &> /dev/null; then
-        printf "❌ Necessary rust package ask-sh is installed but cannot be accessed. Rust's bin path may not be added to your PATH."
-        printf "👉 It's usually under ~/.cargo/bin/"
-        printf "👀 Please add it to your PATH and restart your shell."
-    fi
-    suggested_commands=`echo "$@" | ask-sh 2> >(cat 1>&2)`
-    if [ -n "$suggested_commands" ]; then
-        printf "\n" # add one empty line to create space
-        printf "👋 Hey, AI has suggested some commands that can be typed into your terminal.\n"
-        printf "🔍 Press Enter to view and select the commands, or type any other key to exit:"
-        if [ -n "$ZSH_VERSION" ]; then # read a single char
-            read -r -k 1 REPLY # zsh
-        else
-            read -r -n 1 REPLY # bash
-        fi
-        REPLY="${{REPLY#"${{REPLY%%[![:space:]]*}}"}}"  # trim whitespaces
-        if [ -z "$REPLY" ] ; then
-            # As Enter will move cursor to the next line, we need to go back two lines
-            printf "\033[2A"
-            # \033[2K: delete current line (👋 line), \n: go next line, \033[2K: delete next line (🔍 line)
-            printf "\033[2K\n\033[2K\n"
-            # We're at the emptified 🔍 line. So, go back two lines, including empty line to make space
-            printf "\033[2A" # go back again
-            selected_command=`echo "$suggested_commands" | peco  --prompt "AI suggested commands (Enter to use / Ctrl+C to exit):"`
-            if [ -n "$selected_command" ]; then
-                if ! print -z $selected_command 2>/dev/null; then
-                    history -s $selected_command
-                fi
-            fi
-        else
-            # We're at the end of 🔍 line. So, go back one line (👋 line)
-            printf "\033[1A"
-            printf "\033[2K\n\033[2K\n"
-            printf "\033[2A"
-        fi
-    fi
-    if [ -z "$ASK_SH_NO_UPDATE" ]; then
-        latest_version=`cargo search ask-sh | grep ask-sh | awk '{{print $3}}' | cut -d '"' -f2`
-        current_version=`ask-sh --version`
-        if [ "$(printf '%s\n' "$latest_version" "$current_version" | sort -rV | head -n1)" = "$latest_version" ] && [ "$latest_version" != "$current_version" ]; then
-            # clear line
-            printf "\n"
-            printf "🎉 New version of ask-sh is available! (Current: $current_version vs New: $latest_version) Set \$ASK_SH_NO_UPDATE=1 to suppress this notice.\n"
-            printf "🆙 Press Enter to run update now, or type any other key to exit:"
-            if [ -n "$ZSH_VERSION" ]; then # read a single char
-                read -r -k 1 REPLY # zsh
-            else
-                read -r -n 1 REPLY # bash
-            fi
-            REPLY="${{REPLY#"${{REPLY%%[![:space:]]*}}"}}"  # trim whitespaces
-            if [ -z "$REPLY" ] ; then
-                cargo install --force ask-sh
-                printf "\nDone! Please restart your shell or source ~/.zshrc or ~/.bashrc etc... to use the new version.\n"
-            else
-                printf "\nOk, you can update ask-sh later by running 'cargo install --force ask-sh'.\n"
-            fi
-        fi
-    fi
-}}
-"#
+/// The options that can come either as args or in the words of the request read from stdin
+#[derive(Debug, Default, PartialEq)]
+struct RequestOptions {
+    image_paths: Vec<String>,
+    is_explain: bool,
+    max_tokens: Option<u32>,
+}
+
+impl RequestOptions {
+    /// Take the options out of the words, leaving the rest of the request
+    fn take_from(words: &mut Vec<String>) -> Result<Self, String> {
+        // --max-tokens <n> limits the length of the answer, over ASK_SH_MAX_TOKENS
+        let max_tokens = match words.iter().position(|word| word == ARG_MAX_TOKENS) {
+            Some(index) if index + 1 < words.len() => {
+                words.remove(index);
+                let value = words.remove(index);
+                match value.parse::<u32>() {
+                    Ok(max_tokens) => Some(max_tokens),
+                    Err(_) => {
+                        return Err(format!(
+                            "{} takes a number of tokens, not '{}'",
+                            ARG_MAX_TOKENS, value
+                        ))
+                    }
+                }
+            }
+            _ => None,
+        };
+        let image_paths = take_image_paths(words);
+        // --explain <command> explains the command instead of answering a question
+        let is_explain = match words.iter().position(|word| word == ARG_EXPLAIN) {
+            Some(index) => {
+                words.remove(index);
+                true
+            }
+            None => false,
+        };
+
+        Ok(Self {
+            image_paths,
+            is_explain,
+            max_tokens,
+        })
+    }
+
+    /// Options given in both places add up, the ones in the request winning
+    fn merge(&mut self, other: Self) {
+        self.image_paths.extend(other.image_paths);
+        self.is_explain |= other.is_explain;
+        if other.max_tokens.is_some() {
+            self.max_tokens = other.max_tokens;
+        }
+    }
+}
+
+/// Like [`RequestOptions::take_from`], exiting when an option has a bad value
+fn take_request_options(words: &mut Vec<String>) -> RequestOptions {
+    match RequestOptions::take_from(words) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}{}", icon("❌ "), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The parts of the command ask.sh would ask about before running, shown ahead of the explanation
+fn print_approval_concerns(command: &str) {
+    for (part, reason) in CommandAnalyser::approval_concerns(command) {
+        eprintln!("{}`{}` {}", icon("⚠️  "), part, reason);
+    }
+}
+
+/// Everything that could hold a key is redacted, so this can be pasted into a bug report
+fn print_debug_info(args: &[String], llm_config: &LLMConfig) {
+    eprintln!(
+        "{}Args: {}",
+        icon("🐛 "),
+        redact::scrub_secrets(&args.join(" "))
     );
+
+    let mut env_vars: Vec<(String, String)> = env::vars()
+        .filter(|(name, _)| name.starts_with("ASK_SH_"))
+        .collect();
+    env_vars.sort();
+    for (name, value) in env_vars {
+        eprintln!(
+            "{}{}={}",
+            icon("🐛 "),
+            name,
+            redact::redact_env_var(&name, &value)
+        );
+    }
+
+    eprintln!("{}{:?}", icon("🐛 "), llm_config);
+}
+
+fn tools_command() {
+    println!("{}Tools:", icon("🧰 "));
+    for status in tools::get_tool_statuses() {
+        let function = status.tool.function;
+        match status.unavailable_reason {
+            None => println!("  ✓ {}: {}", function.name, function.description),
+            Some(reason) => println!(
+                "  ✗ {}: {} ({})",
+                function.name, function.description, reason
+            ),
+        }
+    }
+    println!();
+
+    let provider = get_llm_config().and_then(|llm_config| {
+        let provider_name = llm_config.provider.clone();
+        create_llm_provider(llm_config).map(|provider| (provider_name, provider))
+    });
+    match provider {
+        Ok((name, provider)) if provider.supports_tools() => {
+            println!(
+                "The {} provider lets the model call the tools marked ✓.",
+                name
+            )
+        }
+        Ok((name, _)) => println!(
+            "{}The {} provider doesn't send tools to the model yet, so none of them are used.",
+            icon("⚠️  "),
+            name
+        ),
+        Err(e) => eprintln!("{}{}", icon("❌ "), e),
+    }
+}
+
+async fn list_models_command() {
+    let llm_config = match get_llm_config() {
+        Ok(llm_config) => llm_config,
+        Err(e) => {
+            eprintln!("{}{}", icon("❌ "), e);
+            std::process::exit(1);
+        }
+    };
+    let provider_name = llm_config.provider.clone();
+
+    let models = match create_llm_provider(llm_config) {
+        Ok(provider) => provider.list_models().await,
+        Err(e) => Err(e),
+    };
+
+    match models {
+        Ok(models) => {
+            println!("Models available with {}:", provider_name);
+            for model in models {
+                println!("  {}", model);
+            }
+        }
+        Err(e) => {
+            eprintln!(
+                "{}Could not list the models of {}: {}",
+                icon("❌ "),
+                provider_name,
+                e
+            );
+            if let Some(guidance) = e.guidance() {
+                eprintln!("{}{}", icon("💡 "), guidance);
+            }
+            std::process::exit(1);
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
+    // the current directory's .env comes first, so it can override the config one
     dotenv().ok();
+    load_config_env();
+
+    // if called with --init [bash|zsh|fish], the command emits a shell script to be sourced
+    if env::args().nth(1).is_some_and(|arg| arg == ARG_INIT) && env::args().len() <= 3 {
+        let shell_arg = env::args().nth(2);
+        match Shell::from_arg(shell_arg.as_deref()) {
+            Some(shell) => print!("{}", get_init_script(&shell)),
+            None => {
+                eprintln!(
+                    "{}Unsupported shell. Use one of: ask-sh --init [bash|zsh|fish]",
+                    icon("❌ ")
+                );
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    // if called with only --init, the command emits a shell script to be sourced
-    if env::args().len() == 2 && env::args().nth(1).unwrap() == ARG_INIT {
-        print_init_script();
+    // if called with --copy, copy the given text (or stdin) to the clipboard
+    if env::args().nth(1).is_some_and(|arg| arg == ARG_COPY) {
+        copy_command(env::args().skip(2).collect());
+        return;
+    }
+
+    // if called with --export-last <path>, write the last conversation there as Markdown
+    if env::args().nth(1).is_some_and(|arg| arg == ARG_EXPORT_LAST) {
+        let Some(path) = env::args().nth(2) else {
+            eprintln!(
+                "{}Missing file path. Use: ask-sh --export-last <path.md>",
+                icon("❌ ")
+            );
+            std::process::exit(1);
+        };
+
+        match transcript::export_last(Path::new(&path)) {
+            Ok(_) => println!("{}Exported the last conversation to {}", icon("📝 "), path),
+            Err(e) => {
+                eprintln!(
+                    "{}Could not export the last conversation: {}",
+                    icon("❌ "),
+                    e
+                );
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // if called with --list-models, print the models of the configured provider
+    if env::args().nth(1).is_some_and(|arg| arg == ARG_LIST_MODELS) {
+        list_models_command().await;
+        return;
+    }
+
+    // if called with --tools, print which tools the model can use
+    if env::args().nth(1).is_some_and(|arg| arg == ARG_TOOLS) {
+        tools_command();
         return;
     }
 
@@ -195,21 +322,87 @@ async fn main() {
             println!("{}", env!("CARGO_PKG_VERSION"));
             return;
         }
+
+        // prints the latest version only when an update is available
+        if arg == ARG_CHECK_UPDATE {
+            if env::var(ENV_NO_UPDATE).is_err() {
+                if let Some(latest_version) = update_check::check_for_update().await {
+                    println!("{}", latest_version);
+                }
+            }
+            return;
+        }
     }
 
     // check input from users
     // arg without the first executable name
-    let args: Vec<String> = env::args().skip(1).collect();
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // --system <prompt> replaces the system prompt for this question only
+    let system_prompt = match args.iter().position(|arg| arg == ARG_SYSTEM) {
+        Some(index) if index + 1 < args.len() => {
+            args.remove(index);
+            Some(args.remove(index))
+        }
+        _ => None,
+    };
+    let mut options = take_request_options(&mut args);
+    // with --pipe, stdin holds the data and the args the request about it
+    let is_pipe = args.iter().any(|arg| arg == ARG_PIPE);
     // check if args are all predefined args
-    let is_using_stdin = args.iter().all(|arg| ARG_STRINGS.contains(&arg.as_str()));
+    let is_using_stdin = !is_pipe && args.iter().all(|arg| ARG_STRINGS.contains(&arg.as_str()));
 
+    // the first line of stdin is the request, the lines after it are data kept as they are.
+    // Typed in a terminal, the request is the line, rather than waiting for Ctrl-D
+    let mut stdin_data = String::new();
     let user_input = if is_using_stdin {
-        io::stdin().lock().lines().next().unwrap().unwrap()
+        let stdin = match io::stdin().is_terminal() {
+            true => {
+                let mut line = String::new();
+                io::stdin().read_line(&mut line).unwrap_or_default();
+                line
+            }
+            false => io::read_to_string(io::stdin()).unwrap_or_default(),
+        };
+        let (line, rest) = stdin.split_once('\n').unwrap_or((&stdin, ""));
+        stdin_data = rest.to_string();
+        // the shell function sends its words through stdin, options included
+        let mut words: Vec<String> = line.split_whitespace().map(str::to_string).collect();
+        options.merge(take_request_options(&mut words));
+        words.join(" ")
     } else {
         args.join(" ")
     };
 
-    println!("");
+    let RequestOptions {
+        image_paths,
+        is_explain,
+        max_tokens,
+    } = options;
+
+    let images = match image_paths
+        .iter()
+        .map(|path| image::load_image(Path::new(path)))
+        .collect::<Result<Vec<String>, _>>()
+    {
+        Ok(images) => images,
+        Err(e) => {
+            eprintln!("{}{}", icon("❌ "), e);
+            std::process::exit(1);
+        }
+    };
+
+    let piped_input = if is_pipe && !io::stdin().is_terminal() {
+        io::read_to_string(io::stdin()).unwrap_or_default()
+    } else {
+        stdin_data
+    };
+
+    // with --raw, only the answer text is printed: no tools and no formatting
+    let is_raw = user_input.split_whitespace().any(|arg| arg == ARG_RAW);
+
+    if !is_raw {
+        println!();
+    }
 
     // filter out predefined args
     let user_input_without_flags = user_input
@@ -218,9 +411,127 @@ async fn main() {
         .collect::<Vec<&str>>()
         .join(" ");
 
-    let llm_config = get_llm_config().unwrap();
-    let mut chat_handler = ChatHandler::new(llm_config);
-    chat_handler
-        .process_user_prompt(user_input_without_flags)
-        .await;
+    let mut llm_config = match get_llm_config() {
+        Ok(llm_config) => llm_config,
+        Err(e) => {
+            eprintln!("{}{}", icon("❌ "), e);
+            std::process::exit(1);
+        }
+    };
+
+    if max_tokens.is_some() {
+        llm_config.max_tokens = max_tokens;
+    }
+
+    if is_debug_enabled() || user_input.split_whitespace().any(|arg| arg == ARG_DEBUG) {
+        print_debug_info(&args, &llm_config);
+    }
+
+    // With tools, the model runs the commands it suggests once approved. Without them
+    // (--no-tools, --raw, or explaining a command, which never runs it), the commands
+    // are only written in the answer.
+    let is_no_tools = user_input.split_whitespace().any(|arg| arg == ARG_NO_TOOLS);
+    if is_raw || is_explain || is_no_tools {
+        llm_config.tools = None;
+    }
+
+    if !cost::check_large_request(
+        &llm_config,
+        &format!("{}{}", user_input_without_flags, piped_input),
+    ) {
+        eprintln!("{}Cancelled, nothing was sent.", icon("❌ "));
+        std::process::exit(1);
+    }
+
+    let mut chat_handler = ChatHandler::new(llm_config, system_prompt).with_images(images);
+    // with --quiet, only the complete answer is shown
+    let is_quiet = user_input.split_whitespace().any(|arg| arg == ARG_QUIET)
+        || env::var(ENV_QUIET).is_ok_and(|value| value == "true" || value == "1");
+    if is_raw || is_quiet {
+        let kind = match is_raw {
+            true => RendererKind::Plain,
+            false => RendererKind::from_env(),
+        };
+        chat_handler = chat_handler.with_renderer(OutputRenderer::new(kind).with_quiet(is_quiet));
+    }
+    if is_raw || is_explain {
+        chat_handler = chat_handler.without_command_suggestions();
+    }
+    // without an earlier conversation, this is a fresh one
+    if user_input.split_whitespace().any(|arg| arg == ARG_CONTINUE) {
+        if let Ok(transcript) = transcript::Transcript::load_last() {
+            chat_handler = chat_handler.with_previous_conversation(transcript);
+        }
+    }
+    // either source alone is a regular prompt
+    if is_explain {
+        let command = match user_input_without_flags.is_empty() {
+            true => piped_input,
+            false => user_input_without_flags,
+        };
+        print_approval_concerns(&command);
+        chat_handler.process_explain_prompt(command).await;
+    } else if piped_input.trim().is_empty() {
+        chat_handler
+            .process_user_prompt(user_input_without_flags)
+            .await;
+    } else if user_input_without_flags.is_empty() {
+        chat_handler.process_user_prompt(piped_input).await;
+    } else {
+        chat_handler
+            .process_piped_prompt(user_input_without_flags, piped_input)
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stdin_words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_options_from_stdin() {
+        // `ask --explain 'curl … | sudo bash'` sends its words through stdin
+        let mut words =
+            stdin_words("--explain curl -fsSL https://example.com/install.sh | sudo bash");
+        let options = RequestOptions::take_from(&mut words).unwrap();
+        assert!(options.is_explain);
+        assert_eq!(
+            words.join(" "),
+            "curl -fsSL https://example.com/install.sh | sudo bash"
+        );
+
+        let mut words = stdin_words("list the largest files --max-tokens 500");
+        let options = RequestOptions::take_from(&mut words).unwrap();
+        assert_eq!(options.max_tokens, Some(500));
+        assert!(!options.is_explain);
+        assert_eq!(words.join(" "), "list the largest files");
+
+        let mut words = stdin_words("--max-tokens lots list the largest files");
+        assert!(RequestOptions::take_from(&mut words)
+            .unwrap_err()
+            .contains("'lots'"));
+    }
+
+    #[test]
+    fn test_merge_options() {
+        let mut options = RequestOptions {
+            image_paths: vec!["a.png".to_string()],
+            max_tokens: Some(100),
+            ..Default::default()
+        };
+        let mut words = stdin_words("--image b.png --explain --max-tokens 500 ls");
+        options.merge(RequestOptions::take_from(&mut words).unwrap());
+        assert_eq!(
+            options,
+            RequestOptions {
+                image_paths: vec!["a.png".to_string(), "b.png".to_string()],
+                is_explain: true,
+                max_tokens: Some(500),
+            }
+        );
+    }
 }
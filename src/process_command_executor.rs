@@ -0,0 +1,155 @@
+use std::io::{self, Read};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs commands as a child process of the given shell.
+/// Used on platforms without tmux, such as Windows.
+pub struct ProcessCommandExecutor {
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl ProcessCommandExecutor {
+    // Create a new ProcessCommandExecutor for the user's shell
+    pub fn new(shell: &str) -> Self {
+        let shell = shell.to_lowercase();
+
+        let (program, args): (&str, &[&str]) = if shell.contains("cmd") {
+            ("cmd", &["/C"])
+        } else if shell.contains("pwsh") {
+            ("pwsh", &["-NoProfile", "-Command"])
+        } else if shell.contains("powershell") || cfg!(windows) {
+            ("powershell", &["-NoProfile", "-Command"])
+        } else {
+            ("sh", &["-c"])
+        };
+
+        Self {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            timeout: DEFAULT_COMMAND_TIMEOUT,
+        }
+    }
+
+    /// Set how long to wait for a command before killing it
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn execute_command(&self, command: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .arg(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Read the output in the background so a full pipe can't block the command
+        let stdout_reader = Self::read_in_background(child.stdout.take());
+        let stderr_reader = Self::read_in_background(child.stderr.take());
+
+        let status = match self.wait_with_timeout(&mut child)? {
+            Some(status) => status,
+            None => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!(
+                    "Command was killed after timing out ({}s)",
+                    self.timeout.as_secs_f32()
+                )
+                .into());
+            }
+        };
+
+        let stdout = stdout_reader.join().unwrap_or_default();
+        let stderr = stderr_reader.join().unwrap_or_default();
+
+        let mut content = format!("{}{}", stdout, stderr);
+        if content.is_empty() {
+            content = "stdout and stderr are empty!".to_string();
+        }
+
+        let exit_code = status.code().unwrap_or(-1);
+
+        if status.success() {
+            Ok(format!(
+                "Command executed successfully:\n{}\nexit code: {}",
+                content.trim_end(),
+                exit_code
+            ))
+        } else {
+            Err(Box::new(io::Error::other(format!(
+                "An error occurred running the command:\n{}\nexit code: {}",
+                content.trim_end(),
+                exit_code
+            ))))
+        }
+    }
+
+    fn wait_with_timeout(&self, child: &mut Child) -> io::Result<Option<std::process::ExitStatus>> {
+        let started = Instant::now();
+
+        while started.elapsed() < self.timeout {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Some(status));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        Ok(None)
+    }
+
+    fn read_in_background<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<String> {
+        thread::spawn(move || {
+            let mut buffer = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buffer);
+            }
+            String::from_utf8_lossy(&buffer).to_string()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_execute_command() {
+        let executor = ProcessCommandExecutor::new("sh");
+
+        let output = executor.execute_command("echo hello").unwrap();
+        assert!(output.contains("hello"));
+
+        let error = executor.execute_command("exit 3").unwrap_err().to_string();
+        assert!(error.contains("exit code: 3"));
+    }
+
+    #[test]
+    fn test_timeout_kills_command() {
+        let executor = ProcessCommandExecutor::new("sh").with_timeout(Duration::from_secs(1));
+
+        let error = executor
+            .execute_command("sleep 999")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("killed after timing out"));
+    }
+
+    #[test]
+    fn test_shell_selection() {
+        assert_eq!(ProcessCommandExecutor::new("cmd.exe").program, "cmd");
+        assert_eq!(ProcessCommandExecutor::new("pwsh").program, "pwsh");
+        assert_eq!(
+            ProcessCommandExecutor::new("PowerShell").program,
+            "powershell"
+        );
+    }
+}
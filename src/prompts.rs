@@ -13,6 +13,18 @@ static PROMPTS: Lazy<Vec<(String, String)>> = Lazy::new(|| {
             "USER_PROMPT".to_string(),
             get_env_or_default("USER_PROMPT", USER_PROMPT).into_owned(),
         ),
+        (
+            "PIPE_PROMPT".to_string(),
+            get_env_or_default("PIPE_PROMPT", PIPE_PROMPT).into_owned(),
+        ),
+        (
+            "CONTINUE_PROMPT".to_string(),
+            get_env_or_default("CONTINUE_PROMPT", CONTINUE_PROMPT).into_owned(),
+        ),
+        (
+            "EXPLAIN_PROMPT".to_string(),
+            get_env_or_default("EXPLAIN_PROMPT", EXPLAIN_PROMPT).into_owned(),
+        ),
         (
             "TERMINAL_OUTPUT_PROMPT".to_string(),
             get_env_or_default("TERMINAL_OUTPUT_PROMPT", TERMINAL_OUTPUT_PROMPT).into_owned(),
@@ -32,12 +44,43 @@ fn system_prompt() -> String {
 
 const USER_PROMPT: &str = r#"
 User's request:
-{user_input}
+{user_input | unescaped}
 "#;
 
+const PIPE_PROMPT: &str = r#"
+User's request:
+{user_input | unescaped}
+
+Data the user piped in, to be used by the request above:
+<piped_data>
+{piped_input | unescaped}
+</piped_data>
+"#;
+
+const CONTINUE_PROMPT: &str = r#"
+This continues an earlier conversation with the user:
+<previous_conversation>
+{conversation | unescaped}
+</previous_conversation>
+{prompt | unescaped}"#;
+
+const EXPLAIN_PROMPT: &str = r#"
+Explain this shell command to the user. Don't run it, and don't suggest commands to run.
+<command>
+{command | unescaped}
+</command>
+
+Go through it part by part and flag by flag, saying what each one does.
+Then point out anything dangerous: data that could be lost, files or system settings it changes,
+what it downloads or runs, and anything that needs root.
+{{ if concerns }}
+These parts would need the user's approval before ask.sh ran them:
+{concerns | unescaped}
+{{ endif }}"#;
+
 const TERMINAL_OUTPUT_PROMPT: &str = r#"
 Command result:
-{terminal_text}
+{terminal_text | unescaped}
 "#;
 
 pub fn get_template() -> TinyTemplate<'static> {
@@ -45,8 +88,99 @@ pub fn get_template() -> TinyTemplate<'static> {
 
     // Add templates from static PROMPTS
     for (name, content) in PROMPTS.iter() {
-        templates.add_template(name, &content).unwrap();
+        templates.add_template(name, content).unwrap();
     }
 
     templates
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_prompt_user_context() {
+        let mut vars = std::collections::HashMap::new();
+        vars.insert("user_arch", "aarch64");
+        vars.insert("user_shell", "zsh");
+        vars.insert("user_os", "macos");
+        vars.insert("user_distro", "");
+        vars.insert("user_context", "");
+        vars.insert("use_tools", "true");
+        vars.insert("user_lang", "Japanese");
+        vars.insert("user_lang_forced", "true");
+
+        let prompt = get_template().render("SYSTEM_PROMPT", &vars).unwrap();
+        assert!(!prompt.contains("## User preferences"));
+        assert!(prompt.contains("using the `execute_command` tool"));
+        assert!(prompt.contains("Respond in Japanese, even when"));
+
+        // Only the locale's language, the user may still write in another
+        vars.insert("user_lang_forced", "");
+        let prompt = get_template().render("SYSTEM_PROMPT", &vars).unwrap();
+        assert!(prompt.contains("Prefer Japanese, unless the user writes in another language"));
+        assert!(!prompt.contains("even when"));
+
+        vars.insert("use_tools", "");
+        let prompt = get_template().render("SYSTEM_PROMPT", &vars).unwrap();
+        assert!(prompt.contains("each in its own code block"));
+
+        vars.insert("user_context", "- Use `rg` rather than `grep`");
+        let prompt = get_template().render("SYSTEM_PROMPT", &vars).unwrap();
+        assert!(prompt.contains("## User preferences"));
+        assert!(prompt.contains("- Use `rg` rather than `grep`"));
+    }
+
+    #[test]
+    fn test_piped_input_keeps_its_formatting() {
+        let piped_input = "Filesystem      Size  Used Avail Use% Mounted on\n\
+            /dev/nvme0n1p2  468G  401G   44G  91% /\n\
+            tmpfs           7.8G  1.2M  7.8G   1% /run\n\
+            {\"status\": \"ok\", \"tags\": [\"<none>\"]}\n";
+
+        let mut vars = std::collections::HashMap::new();
+        vars.insert("user_input", "which disk is almost full?");
+        vars.insert("piped_input", piped_input);
+
+        let prompt = get_template().render("PIPE_PROMPT", &vars).unwrap();
+        assert!(prompt.contains(&format!("<piped_data>\n{}\n</piped_data>", piped_input)));
+
+        vars.insert("user_input", piped_input);
+        let prompt = get_template().render("USER_PROMPT", &vars).unwrap();
+        assert!(prompt.contains(piped_input));
+    }
+
+    #[test]
+    fn test_explain_prompt() {
+        let mut vars = std::collections::HashMap::new();
+        vars.insert(
+            "command",
+            "tar -xzvf backup.tar.gz -C / && rm backup.tar.gz",
+        );
+        vars.insert("concerns", "");
+
+        let prompt = get_template().render("EXPLAIN_PROMPT", &vars).unwrap();
+        assert!(prompt
+            .contains("<command>\ntar -xzvf backup.tar.gz -C / && rm backup.tar.gz\n</command>"));
+        assert!(!prompt.contains("need the user's approval"));
+
+        vars.insert(
+            "concerns",
+            "- `rm backup.tar.gz`: modifies files or system state",
+        );
+        let prompt = get_template().render("EXPLAIN_PROMPT", &vars).unwrap();
+        assert!(prompt.contains("need the user's approval"));
+        assert!(prompt.contains("- `rm backup.tar.gz`: modifies files or system state"));
+    }
+
+    #[test]
+    fn test_pipe_prompt_keeps_data_as_is() {
+        let mut vars = std::collections::HashMap::new();
+        vars.insert("user_input", "why does a && b fail");
+        vars.insert("piped_input", "ERROR <main> failed: a && b");
+
+        let prompt = get_template().render("PIPE_PROMPT", &vars).unwrap();
+        assert!(prompt.contains("User's request:\nwhy does a && b fail\n"));
+        assert!(prompt.contains("<piped_data>\nERROR <main> failed: a && b\n</piped_data>"));
+    }
+}
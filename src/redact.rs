@@ -0,0 +1,67 @@
+//! Masks API keys and tokens, so debug output can be pasted into bug reports safely
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Tokens that are obviously credentials: OpenAI, Anthropic, GitHub, AWS and Slack keys
+static KEY_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"sk-[A-Za-z0-9_-]{16,}|gh[pousr]_[A-Za-z0-9]{20,}|github_pat_[A-Za-z0-9_]{20,}|AKIA[0-9A-Z]{16}|xox[abprs]-[A-Za-z0-9-]{10,}",
+    )
+    .unwrap()
+});
+
+/// Keep just enough of a secret to tell which one it is, like `sk-...abcd`
+pub fn redact(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() < 12 {
+        return "***".to_string();
+    }
+
+    let head: String = chars[..3].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}...{}", head, tail)
+}
+
+/// Redact every key-like token found in the text
+pub fn scrub_secrets(text: &str) -> String {
+    KEY_PATTERN
+        .replace_all(text, |captures: &regex::Captures| redact(&captures[0]))
+        .to_string()
+}
+
+/// Values of variables named like a secret are redacted entirely, others only where they hold keys
+pub fn redact_env_var(name: &str, value: &str) -> String {
+    let name = name.to_uppercase();
+    match ["KEY", "TOKEN", "SECRET", "PASSWORD"]
+        .iter()
+        .any(|word| name.contains(word))
+    {
+        true => redact(value),
+        false => scrub_secrets(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_secrets() {
+        assert_eq!(redact("sk-proj-1234567890abcd"), "sk-...abcd");
+        assert_eq!(redact("short"), "***");
+
+        let output =
+            "export OPENAI_API_KEY=sk-proj-1234567890abcd\nuser: ghp_aaaaaaaaaaaaaaaaaaaaaaaa1234";
+        assert_eq!(
+            scrub_secrets(output),
+            "export OPENAI_API_KEY=sk-...abcd\nuser: ghp...1234"
+        );
+
+        assert_eq!(
+            redact_env_var("ASK_SH_ANTHROPIC_API_KEY", "my-anthropic-key-0000"),
+            "my-...0000"
+        );
+        assert_eq!(redact_env_var("ASK_SH_RENDERER", "plain"), "plain");
+    }
+}
@@ -0,0 +1,404 @@
+use std::env;
+use std::error::Error;
+use std::io::{stderr, stdout, IsTerminal, Write};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use console::style;
+use indicatif::{ProgressBar, ProgressStyle};
+use termimad::crossterm::{cursor, terminal, ExecutableCommand};
+use termimad::MadSkin;
+
+use crate::{theme::assistant_name, ENV_FLUSH_INTERVAL_MS, ENV_NO_RERENDER, ENV_RENDERER};
+
+/// Frames of the spinners shown while waiting, for the model or a command
+pub(crate) fn spinner_frames() -> Vec<String> {
+    ['⣷', '⣯', '⣟', '⡿', '⢿', '⣻', '⣽', '⣾']
+        .into_iter()
+        .map(|s| style(s).cyan().bright().to_string())
+        .collect()
+}
+
+/// How the model's answer is displayed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RendererKind {
+    /// Format markdown line by line as it streams in
+    Stream,
+    /// Print tokens as they stream, then re-render the whole answer with glow
+    Glow,
+    /// Print tokens as they stream, without any formatting
+    Plain,
+}
+
+impl RendererKind {
+    /// Read the renderer from ASK_SH_RENDERER
+    pub fn from_env() -> Self {
+        select_renderer(
+            env::var(ENV_RENDERER).ok().as_deref(),
+            stdout().is_terminal(),
+            get_glow_installed,
+        )
+    }
+}
+
+/// Markdown is rendered by glow when asked for and installed, and by termimad otherwise.
+/// Streamed markdown needs a terminal, so it falls back to glow (or plain output) otherwise.
+fn select_renderer(
+    renderer: Option<&str>,
+    is_terminal: bool,
+    glow_installed: impl Fn() -> bool,
+) -> RendererKind {
+    if !is_terminal {
+        return match glow_installed() {
+            true => RendererKind::Glow,
+            false => RendererKind::Plain,
+        };
+    }
+
+    match renderer {
+        Some("plain") => RendererKind::Plain,
+        Some("glow" | "auto") if glow_installed() => RendererKind::Glow,
+        _ => RendererKind::Stream,
+    }
+}
+
+pub struct OutputRenderer {
+    kind: RendererKind,
+    skin: MadSkin,
+    pending_line: String,
+    code_block: Option<String>,
+    start_line: u16,
+    /// The glow rendering replaces the streamed text, rather than following it
+    rerender: bool,
+    showing_reasoning: bool,
+    /// Streamed text is flushed at most this often, complete lines are printed right away
+    flush_interval: Duration,
+    last_flush: Instant,
+    /// Nothing is shown while the answer streams in, only the complete answer
+    quiet: bool,
+}
+
+impl OutputRenderer {
+    pub fn new(kind: RendererKind) -> Self {
+        Self {
+            kind,
+            skin: MadSkin::default(),
+            pending_line: String::new(),
+            code_block: None,
+            start_line: 0,
+            rerender: should_rerender(
+                env::var(ENV_NO_RERENDER).ok().as_deref(),
+                stdout().is_terminal(),
+            ),
+            showing_reasoning: false,
+            flush_interval: flush_interval_from_env(),
+            last_flush: Instant::now(),
+            quiet: false,
+        }
+    }
+
+    /// Only display the answer once it's complete, without the reasoning
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Spinner on stderr until the first chunk of the answer arrives, cleared with
+    /// `finish_and_clear`. It's hidden when stderr isn't a terminal.
+    pub fn waiting_spinner(&self) -> ProgressBar {
+        let frames = spinner_frames();
+        let frames: Vec<&str> = frames.iter().map(String::as_str).collect();
+
+        let spinner = ProgressBar::new_spinner();
+        spinner.set_style(
+            ProgressStyle::with_template("{spinner} {msg}")
+                .unwrap()
+                .tick_strings(&frames),
+        );
+        spinner.set_message(
+            style(format!("Waiting for {}…", assistant_name()))
+                .dim()
+                .to_string(),
+        );
+        spinner.enable_steady_tick(Duration::from_millis(150));
+
+        spinner
+    }
+
+    /// Prepare for a new answer
+    pub fn start(&mut self) -> Result<(), Box<dyn Error>> {
+        self.pending_line.clear();
+        self.code_block = None;
+        self.showing_reasoning = false;
+
+        if self.kind == RendererKind::Glow && !self.quiet && self.rerender {
+            // Save cursor position to re-render from there
+            self.start_line = cursor::position()?.1;
+        }
+
+        Ok(())
+    }
+
+    /// Display a streamed chunk of the model's reasoning, dimmed on stderr
+    pub fn push_reasoning(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        if self.quiet {
+            return Ok(());
+        }
+
+        self.showing_reasoning = true;
+        eprint!("{}", style(text).dim());
+        stderr().flush()?;
+        Ok(())
+    }
+
+    /// Display a streamed chunk of the answer
+    pub fn push(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        if self.quiet {
+            return Ok(());
+        }
+
+        self.end_reasoning();
+
+        if self.kind != RendererKind::Stream {
+            print!("{}", text);
+            self.flush_if_due()?;
+            return Ok(());
+        }
+
+        self.pending_line.push_str(text);
+
+        // Only complete lines can be formatted
+        while let Some(newline) = self.pending_line.find('\n') {
+            let line: String = self.pending_line.drain(..=newline).collect();
+            self.render_line(line.trim_end_matches('\n'));
+        }
+        self.flush_if_due()?;
+
+        Ok(())
+    }
+
+    /// Display whatever is left once the answer is complete
+    pub fn finish(&mut self, content: &str) -> Result<(), Box<dyn Error>> {
+        if self.quiet {
+            return self.display_complete(content);
+        }
+
+        self.end_reasoning();
+
+        match self.kind {
+            RendererKind::Stream => {
+                let line = std::mem::take(&mut self.pending_line);
+                if !line.is_empty() {
+                    self.render_line(&line);
+                }
+
+                // Print an unterminated code block as is
+                if let Some(code_block) = self.code_block.take() {
+                    print!("{}", self.skin.term_text(&code_block));
+                }
+            }
+            RendererKind::Glow => {
+                println!();
+
+                // Clear from start position and re-render
+                if self.rerender {
+                    let mut stdout = stdout();
+                    stdout.execute(cursor::MoveTo(0, self.start_line))?;
+                    stdout.execute(terminal::Clear(terminal::ClearType::FromCursorDown))?;
+                }
+
+                // glow can still fail to run, termimad then formats the answer instead
+                if display_with_glow_pipe(content).is_err() {
+                    self.skin.print_text(content);
+                }
+            }
+            RendererKind::Plain => println!(),
+        }
+
+        stdout().flush()?;
+        Ok(())
+    }
+
+    /// The whole answer at once, as the renderer would have shown it
+    fn display_complete(&self, content: &str) -> Result<(), Box<dyn Error>> {
+        if content.is_empty() {
+            return Ok(());
+        }
+
+        match self.kind {
+            RendererKind::Plain => println!("{}", content),
+            RendererKind::Glow if display_with_glow_pipe(content).is_ok() => {}
+            _ => self.skin.print_text(content),
+        }
+
+        stdout().flush()?;
+        Ok(())
+    }
+
+    /// Flushing every token of a fast model makes the terminal flicker
+    fn flush_if_due(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.last_flush.elapsed() >= self.flush_interval {
+            stdout().flush()?;
+            self.last_flush = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Put the answer on its own line after the reasoning
+    fn end_reasoning(&mut self) {
+        if self.showing_reasoning {
+            eprintln!("\n");
+            self.showing_reasoning = false;
+        }
+    }
+
+    fn render_line(&mut self, line: &str) {
+        let is_fence = line.trim_start().starts_with("```");
+
+        // Code blocks are rendered once they are closed
+        if let Some(code_block) = self.code_block.as_mut() {
+            code_block.push_str(line);
+            code_block.push('\n');
+
+            if is_fence {
+                print!("{}", self.skin.term_text(code_block));
+                self.code_block = None;
+            }
+            return;
+        }
+
+        if is_fence {
+            self.code_block = Some(format!("{}\n", line));
+        } else if line.trim().is_empty() {
+            println!();
+        } else {
+            print!("{}", self.skin.term_text(line));
+        }
+    }
+}
+
+/// Replacing the streamed text relies on the cursor position, which only a terminal has,
+/// and which some multiplexers report wrong: ASK_SH_NO_RERENDER prints below it instead
+fn should_rerender(no_rerender: Option<&str>, is_terminal: bool) -> bool {
+    is_terminal && !matches!(no_rerender, Some("true" | "1"))
+}
+
+/// ASK_SH_FLUSH_INTERVAL_MS, every chunk is flushed by default
+fn flush_interval_from_env() -> Duration {
+    let millis = env::var(ENV_FLUSH_INTERVAL_MS)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0);
+    Duration::from_millis(millis)
+}
+
+fn get_glow_installed() -> bool {
+    let glow_version = Command::new("glow").arg("-v").output();
+    glow_version.is_ok()
+}
+
+fn display_with_glow_pipe(content: &str) -> Result<(), Box<dyn Error>> {
+    // glow is run directly rather than through sh, which isn't there on Windows
+    let mut child = Command::new("glow")
+        .args(["-s", "auto", "-w", "100", "-"])
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content.as_bytes())?;
+    }
+
+    match child.wait()?.success() {
+        true => Ok(()),
+        false => Err("glow failed to render the answer".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_renderer_buffers_code_blocks() {
+        let mut renderer = OutputRenderer::new(RendererKind::Stream);
+        renderer.start().unwrap();
+
+        renderer.push("Run this:\n```sh\nls -la").unwrap();
+        assert_eq!(renderer.pending_line, "ls -la");
+        assert!(renderer.code_block.is_some());
+
+        renderer.push("\n```\nDone").unwrap();
+        assert_eq!(renderer.pending_line, "Done");
+        assert!(renderer.code_block.is_none());
+
+        renderer.finish("").unwrap();
+        assert!(renderer.pending_line.is_empty());
+    }
+
+    #[test]
+    fn test_quiet_renderer_waits_for_the_answer() {
+        let mut renderer = OutputRenderer::new(RendererKind::Stream).with_quiet(true);
+        renderer.start().unwrap();
+
+        renderer.push_reasoning("thinking").unwrap();
+        renderer.push("Run this:\n```sh\nls").unwrap();
+        assert!(!renderer.showing_reasoning);
+        assert!(renderer.pending_line.is_empty());
+        assert!(renderer.code_block.is_none());
+
+        renderer.finish("Run this:\n```sh\nls\n```").unwrap();
+    }
+
+    #[test]
+    fn test_flush_interval() {
+        let mut renderer = OutputRenderer::new(RendererKind::Plain);
+        renderer.flush_interval = Duration::from_secs(3600);
+
+        renderer.flush_if_due().unwrap();
+        let last_flush = renderer.last_flush;
+        renderer.push("token").unwrap();
+        assert_eq!(renderer.last_flush, last_flush);
+
+        renderer.flush_interval = Duration::ZERO;
+        renderer.push("token").unwrap();
+        assert!(renderer.last_flush > last_flush);
+    }
+
+    #[test]
+    fn test_no_rerender_without_terminal() {
+        assert!(should_rerender(None, true));
+        assert!(!should_rerender(None, false));
+        assert!(!should_rerender(Some("true"), true));
+        assert!(should_rerender(Some("false"), true));
+
+        // Without a terminal, the cursor position is never asked for
+        let mut renderer = OutputRenderer::new(RendererKind::Glow);
+        renderer.rerender = should_rerender(None, false);
+        renderer.start().unwrap();
+        assert_eq!(renderer.start_line, 0);
+    }
+
+    #[test]
+    fn test_select_renderer_without_glow() {
+        let with_glow = || true;
+        let without_glow = || false;
+
+        assert_eq!(
+            select_renderer(Some("glow"), true, with_glow),
+            RendererKind::Glow
+        );
+        assert_eq!(
+            select_renderer(Some("glow"), true, without_glow),
+            RendererKind::Stream
+        );
+        assert_eq!(
+            select_renderer(Some("auto"), true, without_glow),
+            RendererKind::Stream
+        );
+        assert_eq!(select_renderer(None, true, with_glow), RendererKind::Stream);
+        assert_eq!(
+            select_renderer(Some("stream"), false, without_glow),
+            RendererKind::Plain
+        );
+    }
+}
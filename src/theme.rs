@@ -0,0 +1,80 @@
+//! User-facing wording: the assistant's name, and emojis that can be turned off
+//! for terminals with poor emoji fonts
+
+use std::env;
+
+use crate::{ENV_ASSISTANT_NAME, ENV_NO_EMOJI};
+
+const DEFAULT_ASSISTANT_NAME: &str = "AI";
+
+pub fn no_emoji() -> bool {
+    env::var(ENV_NO_EMOJI).is_ok_and(|value| value == "true" || value == "1")
+}
+
+/// The emoji (with its trailing spaces), or nothing with ASK_SH_NO_EMOJI
+pub fn icon(emoji: &'static str) -> &'static str {
+    match no_emoji() {
+        true => "",
+        false => emoji,
+    }
+}
+
+/// How ask.sh refers to the model, e.g. "AI has suggested some commands"
+pub fn assistant_name() -> String {
+    env::var(ENV_ASSISTANT_NAME)
+        .ok()
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_ASSISTANT_NAME.to_string())
+}
+
+/// Remove every emoji along with the spaces after it
+pub fn strip_emoji(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if is_emoji(c) {
+            while chars
+                .peek()
+                .is_some_and(|&next| next == ' ' || is_emoji(next))
+            {
+                chars.next();
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+
+    stripped
+}
+
+/// ✓ and ✗ are kept, they show up fine in any font
+fn is_emoji(c: char) -> bool {
+    match c as u32 {
+        0x2713 | 0x2717 => false,
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B50 | 0xFE0F | 0x200D => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_emoji() {
+        assert_eq!(
+            strip_emoji(r#"printf "👋 Hey, AI has suggested some commands.\n""#),
+            r#"printf "Hey, AI has suggested some commands.\n""#
+        );
+        assert_eq!(
+            strip_emoji("⚠️  Stopped after 3 rounds"),
+            "Stopped after 3 rounds"
+        );
+        assert_eq!(
+            strip_emoji("# go back one line (👋 line)"),
+            "# go back one line (line)"
+        );
+        assert_eq!(strip_emoji("  ✓ web_search: ✗"), "  ✓ web_search: ✗");
+    }
+}
@@ -1,79 +1,175 @@
-use std::process::Command;
-use std::time::Duration;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
 use std::{env, io, thread};
 
+use once_cell::sync::Lazy;
 use uuid::Uuid;
 
-const TMUX_SESSION_NAME: &str = "ask_sh_session";
+/// Unique to this process, so concurrent ask.sh runs never share a session
+static TMUX_SESSION_NAME: Lazy<String> =
+    Lazy::new(|| format!("ask_sh_{}_{}", std::process::id(), Uuid::new_v4().simple()));
+/// Polling starts fast for quick commands, and slows down while a command prints nothing
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(20);
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Checked once, minimal systems and containers often don't have tmux
+static TMUX_INSTALLED: Lazy<bool> = Lazy::new(|| is_installed("tmux"));
+
+fn is_installed(program: &str) -> bool {
+    Command::new(program)
+        .arg("-V")
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+type OutputHandler = Box<dyn Fn(&str)>;
 
 pub struct TmuxCommandExecutor {
     session: String,
     prompt_pattern: String,
+    timeout: Duration,
+    /// Only sessions created by ask.sh are ever killed
+    created_session: bool,
+    attach: bool,
+    /// Whether commands may read from the pane, otherwise their stdin is /dev/null
+    keep_stdin: bool,
+    /// Given each new line of output while the command runs
+    on_output: Option<OutputHandler>,
+}
+
+/// Shows the session to the user while a command runs, and hides it again when dropped
+enum SessionWatcher {
+    /// The session attached read-only in the user's terminal
+    Attached { session: String, client: Child },
+    /// A pane split off the user's tmux window
+    Pane { pane_id: String },
+}
+
+impl Drop for SessionWatcher {
+    fn drop(&mut self) {
+        match self {
+            SessionWatcher::Attached { session, client } => {
+                let _ = Command::new("tmux")
+                    .args(["detach-client", "-s", session])
+                    .output();
+                let _ = client.wait();
+            }
+            SessionWatcher::Pane { pane_id } => {
+                let _ = Command::new("tmux")
+                    .args(["kill-pane", "-t", pane_id])
+                    .output();
+            }
+        }
+    }
 }
 
 impl TmuxCommandExecutor {
+    /// Whether the tmux binary can be run at all
+    pub fn is_available() -> bool {
+        *TMUX_INSTALLED
+    }
+
     // Create a new TmuxCommandExecutor for a specific pane
     pub fn new() -> Self {
-        let executor = Self {
+        let mut executor = Self {
             session: TMUX_SESSION_NAME.to_string(),
-            prompt_pattern: Self::capture_prompt_pattern(TMUX_SESSION_NAME),
+            prompt_pattern: String::new(),
+            timeout: DEFAULT_COMMAND_TIMEOUT,
+            created_session: false,
+            attach: false,
+            keep_stdin: false,
+            on_output: None,
         };
 
-        // Create the session
-        let result = executor.ensure_session();
-
-        if result.is_err() {}
+        // Create the session, its prompt can only be captured once it exists
+        executor.created_session = executor.ensure_session().is_ok();
+        executor.prompt_pattern = Self::capture_prompt_pattern(&executor.session);
 
         executor
     }
 
+    /// Set how long to wait for a command before killing it
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Show the session to the user while commands run
+    pub fn with_attach(mut self, attach: bool) -> Self {
+        self.attach = attach;
+        self
+    }
+
+    /// Let commands read what's typed in the session, e.g. by someone attached to it.
+    /// By default a command like a bare `cat` gets EOF instead of waiting until it times out
+    pub fn with_stdin(mut self, keep_stdin: bool) -> Self {
+        self.keep_stdin = keep_stdin;
+        self
+    }
+
+    /// Follow the output of commands as it's printed, rather than once they're done
+    pub fn with_output_handler(mut self, on_output: impl Fn(&str) + 'static) -> Self {
+        self.on_output = Some(Box::new(on_output));
+        self
+    }
+
     pub fn execute_command(&self, command: &str) -> Result<String, Box<dyn std::error::Error>> {
-        let session_pane = format!("{}", self.session);
+        let session_pane = self.session.to_string();
 
         // Send command with marker
         let marker = format!("__CMD_COMPLETE_{}__", Uuid::new_v4());
+        let command = match self.keep_stdin {
+            true => format!("({})", command),
+            false => format!("({}) < /dev/null", command),
+        };
+        // Piped to `cat` so nothing opens a pager, the exit code is taken before the pipe ends
         let full_command = format!(
-            "(({0} | cat) && echo exit code: $? && echo {1}) || (echo exit code: $? && echo {1})",
+            "{{ {0}; echo exit code: $?; }} | cat; echo {1}",
             command, marker
         );
 
         // Set Tmux window size
         Command::new("tmux")
-            .args(&["set-option", "-g", "window-size", "manual"])
+            .args(["set-option", "-g", "window-size", "manual"])
             .output()?;
         Command::new("tmux")
-            .args(&["resize-window", "-x", "1000"])
+            .args(["resize-window", "-x", "1000"])
             .output()?;
 
         // Clear history
         Command::new("tmux")
-            .args(&["clear-history", "-t", &session_pane])
+            .args(["clear-history", "-t", &session_pane])
             .output()?;
 
         // Clear visible screen
         Command::new("tmux")
-            .args(&["send-keys", "-t", &session_pane, "C-l"])
+            .args(["send-keys", "-t", &session_pane, "C-l"])
             .output()?;
 
         // Small delay to ensure clear completes
         thread::sleep(Duration::from_millis(100));
 
+        // Kept until the command is done, so the user can watch it run
+        let _watcher = self.watch_session();
+
         // Send the command
         Command::new("tmux")
-            .args(&["send-keys", "-t", &session_pane, &full_command, "Enter"])
+            .args(["send-keys", "-t", &session_pane, &full_command, "Enter"])
             .output()?;
 
         // Wait for command to complete
         // Poll until prompt reappears or timeout
-        let mut attempts = 0;
-        let max_attempts = 100;
+        let started = Instant::now();
+        let mut poll_interval = MIN_POLL_INTERVAL;
+        let mut previous_output = String::new();
         let mut command_returned_error = false;
 
         loop {
-            thread::sleep(Duration::from_millis(100));
+            thread::sleep(poll_interval);
 
             let output = Command::new("tmux")
-                .args(&["capture-pane", "-p", "-t", &session_pane])
+                .args(["capture-pane", "-p", "-t", &session_pane])
                 .output()?;
 
             let output_stdout = String::from_utf8_lossy(&output.stdout).to_string();
@@ -84,6 +180,22 @@ impl TmuxCommandExecutor {
                 .lines()
                 .any(|line| line.contains(&marker) && !line.contains(&format!("echo {}", marker)));
 
+            if let Some(on_output) = &self.on_output {
+                // Once done, the last line is complete too
+                let current = match marker_found {
+                    true => format!("{}\n", content_stdout),
+                    false => content_stdout.to_string(),
+                };
+                new_lines(&previous_output, &current)
+                    .into_iter()
+                    .filter(|line| {
+                        !line.trim().is_empty()
+                            && !line.contains(&marker)
+                            && !line.starts_with("exit code: ")
+                    })
+                    .for_each(on_output);
+            }
+
             if marker_found {
                 break;
             }
@@ -91,21 +203,27 @@ impl TmuxCommandExecutor {
             let output_stderr = String::from_utf8_lossy(&output.stderr).to_string();
             let content_stderr = output_stderr.trim_end();
 
-            if content_stdout == "" && content_stderr != "" {
+            if content_stdout.is_empty() && !content_stderr.is_empty() {
                 command_returned_error = true;
                 break;
             }
 
-            attempts += 1;
+            poll_interval = next_poll_interval(poll_interval, content_stdout != previous_output);
+            previous_output = content_stdout.to_string();
 
-            if attempts >= max_attempts {
-                return Err("Command timed out".into());
+            if started.elapsed() >= self.timeout {
+                self.kill_running_command();
+                return Err(format!(
+                    "Command was killed after timing out ({}s)",
+                    self.timeout.as_secs_f32()
+                )
+                .into());
             }
         }
 
         // Capture the final output
         let output = Command::new("tmux")
-            .args(&[
+            .args([
                 "capture-pane",
                 "-pJ",
                 "-t",
@@ -126,40 +244,81 @@ impl TmuxCommandExecutor {
             final_output = "An error occurred running the command:\n".to_string();
         }
 
-        if content == "" {
+        if content.is_empty() {
             content = String::from_utf8_lossy(&output.stderr).to_string();
-            if content == "" {
+            if content.is_empty() {
                 command_returned_error = true;
                 content = "stdout and stderr are empty!".to_string();
             }
         }
 
-        let cleaned_output = self.clean_command_output(&content, &marker);
+        let cleaned_output = clean_command_output(&content, &marker, &self.prompt_pattern);
         final_output = format!("{}{}", final_output, cleaned_output);
 
         if command_returned_error {
-            Err(Box::new(io::Error::new(io::ErrorKind::Other, final_output)))
+            Err(Box::new(io::Error::other(final_output)))
         } else {
             Ok(final_output.to_string())
         }
     }
 
+    /// Attach the session read-only, or split a pane to show it when already inside tmux
+    fn watch_session(&self) -> Option<SessionWatcher> {
+        if !self.attach {
+            return None;
+        }
+
+        if env::var("TMUX").is_ok() {
+            let view = format!("TMUX= tmux attach-session -r -t {}", self.session);
+            let output = Command::new("tmux")
+                .args(["split-window", "-d", "-P", "-F", "#{pane_id}", &view])
+                .output()
+                .ok()?;
+            let pane_id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+            return (!pane_id.is_empty()).then_some(SessionWatcher::Pane { pane_id });
+        }
+
+        let client = Command::new("tmux")
+            .args(["attach-session", "-r", "-t", &self.session])
+            .spawn()
+            .ok()?;
+
+        Some(SessionWatcher::Attached {
+            session: self.session.clone(),
+            client,
+        })
+    }
+
+    /// Interrupt the running command, then kill the session so nothing keeps running
+    fn kill_running_command(&self) {
+        let _ = Command::new("tmux")
+            .args(["send-keys", "-t", &self.session, "C-c"])
+            .output();
+
+        // Give the command a moment to handle the interrupt
+        thread::sleep(Duration::from_millis(200));
+
+        self.terminate_session();
+    }
+
+    /// Kill this executor's session, and only that one.
+    /// Sessions ask.sh didn't create, e.g. when running inside tmux, are left alone.
     pub fn terminate_session(&self) {
-        Command::new("tmux")
-            .arg("kill-session")
-            .arg("-a")
-            .arg("-t")
-            .arg(&self.session);
+        if !self.created_session {
+            return;
+        }
+
+        let _ = Command::new("tmux")
+            .args(["kill-session", "-t", &self.session])
+            .output();
     }
 
     fn capture_prompt_pattern(pane: &str) -> String {
         // Send a newline to trigger a fresh prompt
-        Command::new("tmux")
-            .arg("send-keys")
-            .arg("-t")
-            .arg(&pane)
-            .arg("")
-            .arg("Enter");
+        let _ = Command::new("tmux")
+            .args(["send-keys", "-t", pane, "", "Enter"])
+            .output();
 
         let mut prompt_line = "".to_string();
 
@@ -175,24 +334,17 @@ impl TmuxCommandExecutor {
             let output = Command::new("tmux")
                 .arg("capture-pane")
                 .arg("-t")
-                .arg(&pane)
+                .arg(pane)
                 .arg("-p")
                 .output();
 
-            let output_stdout = String::from_utf8_lossy(&output.unwrap().stdout).to_string();
-
-            if output_stdout.trim() != "" {
-                // Get the last few lines (your prompt)
-                prompt_line = output_stdout
-                    .trim()
-                    .lines()
-                    .last()
-                    .unwrap_or("")
-                    .to_string();
+            let output_stdout = output
+                .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+                .unwrap_or_default();
 
-                if prompt_line != "" {
-                    break;
-                }
+            if let Some(line) = last_non_empty_line(&output_stdout) {
+                prompt_line = line.to_string();
+                break;
             }
 
             attempts += 1;
@@ -205,89 +357,315 @@ impl TmuxCommandExecutor {
         prompt_line
     }
 
-    /// Ensure the tmux session exists
+    /// Create the session, inside tmux too, since its name is only known to this process
     fn ensure_session(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let in_tmux: bool;
+        // Start server if not running
+        let _ = Command::new("tmux")
+            .arg("start-server")
+            .env_remove("TMUX")
+            .output();
+
+        thread::sleep(Duration::from_millis(100));
+
+        // Create session
+        let output = Command::new("tmux")
+            .args(["new-session", "-d", "-s", &self.session])
+            .env_remove("TMUX")
+            .output()?;
 
-        match env::var("TMUX") {
-            Ok(_value) => in_tmux = true,
-            Err(_) => in_tmux = false,
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("Failed to create session: {}", error).into());
         }
 
-        if !in_tmux {
-            // Start server if not running
-            let _ = Command::new("tmux")
-                .arg("start-server")
-                .env_remove("TMUX")
-                .output();
+        // Wait for session to be ready
+        thread::sleep(Duration::from_millis(200));
 
-            thread::sleep(Duration::from_millis(100));
+        // Verify session was created
+        let verify = Command::new("tmux")
+            .args(["has-session", "-t", &self.session])
+            .env_remove("TMUX")
+            .output()?;
 
-            // Check if session exists
-            let check = Command::new("tmux")
-                .args(&["has-session", "-t", &self.session])
-                .output()?;
+        if !verify.status.success() {
+            return Err("Session created but not found".into());
+        }
 
-            if check.status.success() {
-                Command::new("tmux")
-                    .arg("kill-pane")
-                    .arg("-t")
-                    .arg(&self.session)
-                    .arg("Enter");
+        Ok(())
+    }
+}
+
+/// The output of the command is what's between the line the command was typed on and the marker.
+/// Going up from the marker, collecting stops at the typed command, recognized by its
+/// `echo <marker>`, or at a prompt line in case the command line was cut.
+fn clean_command_output(content: &str, marker: &str, prompt_pattern: &str) -> String {
+    let echo_marker = format!("echo {}", marker);
+    let is_prompt = |line: &str| !prompt_pattern.is_empty() && line.starts_with(prompt_pattern);
+
+    let mut result = Vec::new();
+    let mut collecting = false;
+
+    for line in content.lines().rev() {
+        if line.contains(marker) && !line.contains(&echo_marker) {
+            // Found marker line - clean it and start collecting
+            let cleaned = line.replace(marker, "");
+            if !cleaned.trim().is_empty() {
+                result.push(cleaned.to_string());
+            }
+            collecting = true;
+        } else if collecting {
+            if line.contains(&echo_marker) || is_prompt(line) {
+                break;
+            }
+            if !line.trim().is_empty() {
+                result.push(line.to_string());
             }
+        }
+    }
 
-            // Create session
-            let output = Command::new("tmux")
-                .args(&["new-session", "-d", "-s", &self.session])
-                .output()?;
+    result.reverse();
+    result.join("\n")
+}
 
-            if !output.status.success() {
-                let error = String::from_utf8_lossy(&output.stderr);
-                return Err(format!("Failed to create session: {}", error).into());
-            }
+/// The prompt is the last line holding anything, as the pane is padded with empty lines
+fn last_non_empty_line(text: &str) -> Option<&str> {
+    text.lines()
+        .map(str::trim_end)
+        .rev()
+        .find(|line| !line.trim().is_empty())
+}
 
-            // Wait for session to be ready
-            thread::sleep(Duration::from_millis(200));
+/// The complete lines of the pane that weren't in the previous snapshot. The snapshots are the
+/// visible screen, which scrolls, so the new lines follow where the end of the previous one
+/// shows up again. The last line can still be printing and is left for the next snapshot.
+fn new_lines<'a>(previous: &str, current: &'a str) -> Vec<&'a str> {
+    let previous = complete_lines(previous);
+    let current = complete_lines(current);
 
-            // Verify session was created
-            let verify = Command::new("tmux")
-                .args(&["has-session", "-t", &self.session])
-                .output()?;
+    let overlap = (0..=previous.len().min(current.len()))
+        .rev()
+        .find(|&count| previous[previous.len() - count..] == current[..count])
+        .unwrap_or(0);
 
-            if !verify.status.success() {
-                return Err("Session created but not found".into());
-            }
+    current[overlap..].to_vec()
+}
+
+fn complete_lines(text: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = text.lines().collect();
+    lines.pop();
+    lines
+}
+
+/// Back to polling fast when new output appears, otherwise twice as slow up to the cap
+fn next_poll_interval(current: Duration, output_changed: bool) -> Duration {
+    match output_changed {
+        true => MIN_POLL_INTERVAL,
+        false => (current * 2).min(MAX_POLL_INTERVAL),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_binary_is_detected() {
+        assert!(!is_installed("ask-sh-no-such-tmux"));
+    }
+
+    #[test]
+    fn test_timeout_kills_command() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        if !TmuxCommandExecutor::is_available() {
+            return;
         }
+        // Outside of tmux, on the default server
+        env::remove_var("TMUX");
 
-        Ok(())
+        let executor = TmuxCommandExecutor::new().with_timeout(Duration::from_secs(1));
+        let result = executor.execute_command("sleep 999");
+
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("killed after timing out"), "{}", error);
+
+        let has_session = Command::new("tmux")
+            .args(["has-session", "-t", &executor.session])
+            .output()
+            .unwrap();
+        assert!(!has_session.status.success());
     }
 
-    fn clean_command_output(&self, content: &str, marker: &str) -> String {
-        let lines: Vec<&str> = content.lines().collect();
-        let mut result = Vec::new();
-        let mut collecting = false;
-
-        for line in lines.iter().rev() {
-            if line.contains(marker) && !line.contains(&format!("echo {}", marker)) {
-                // Found marker line - clean it and start collecting
-                let cleaned = line.replace(marker, "");
-                if !cleaned.trim().is_empty() {
-                    result.push(cleaned.to_string());
-                }
-                collecting = true;
-            } else if collecting {
-                // Stop when we hit the prompt line
-                if line.starts_with(&self.prompt_pattern) {
-                    break;
-                }
-                // Skip empty lines and wrapped prompts
-                if !line.trim().is_empty() && !line.starts_with(&self.prompt_pattern) {
-                    result.push(line.to_string());
-                }
-            }
+    #[test]
+    fn test_never_kills_sessions_it_did_not_create() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        if !TmuxCommandExecutor::is_available() {
+            return;
+        }
+        // Use the default tmux server, like the executor does outside of tmux
+        let session = format!("ask_sh_test_{}", Uuid::new_v4().simple());
+        Command::new("tmux")
+            .args(["new-session", "-d", "-s", &session])
+            .env_remove("TMUX")
+            .output()
+            .unwrap();
+
+        let executor = TmuxCommandExecutor {
+            session: session.clone(),
+            prompt_pattern: String::new(),
+            timeout: DEFAULT_COMMAND_TIMEOUT,
+            created_session: false,
+            attach: false,
+            keep_stdin: false,
+            on_output: None,
+        };
+        executor.terminate_session();
+
+        let has_session = Command::new("tmux")
+            .args(["has-session", "-t", &session])
+            .env_remove("TMUX")
+            .output()
+            .unwrap();
+        let _ = Command::new("tmux")
+            .args(["kill-session", "-t", &session])
+            .env_remove("TMUX")
+            .output();
+        assert!(has_session.status.success());
+    }
+
+    #[test]
+    fn test_session_name_is_unique_to_the_process() {
+        let session = TMUX_SESSION_NAME.as_str();
+
+        assert!(session.starts_with(&format!("ask_sh_{}_", std::process::id())));
+        assert_ne!(session, "ask_sh_session");
+    }
+
+    #[test]
+    fn test_session_is_created_inside_tmux() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        if !TmuxCommandExecutor::is_available() {
+            return;
+        }
+        // As if ask.sh ran in the user's tmux, on the default server
+        let uid = Command::new("id").arg("-u").output().unwrap().stdout;
+        let tmpdir = env::var("TMUX_TMPDIR").unwrap_or_else(|_| "/tmp".to_string());
+        let socket = format!(
+            "{}/tmux-{}/default",
+            tmpdir,
+            String::from_utf8_lossy(&uid).trim()
+        );
+        env::set_var("TMUX", format!("{},0,0", socket));
+
+        let executor = TmuxCommandExecutor::new();
+        let output = executor.execute_command("echo inside");
+        executor.terminate_session();
+        env::remove_var("TMUX");
+
+        assert!(executor.created_session);
+        assert!(output.unwrap().contains("inside"));
+    }
+
+    #[test]
+    fn test_stdin_is_closed() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        if !TmuxCommandExecutor::is_available() {
+            return;
         }
+        env::remove_var("TMUX");
+
+        let executor = TmuxCommandExecutor::new().with_timeout(Duration::from_secs(5));
+        let started = Instant::now();
+        let output = executor.execute_command("cat").unwrap();
+        executor.terminate_session();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(output.contains("exit code: 0"), "{}", output);
+    }
+
+    #[test]
+    fn test_output_is_followed_while_running() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        if !TmuxCommandExecutor::is_available() {
+            return;
+        }
+        env::remove_var("TMUX");
+
+        let lines = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let handler_lines = lines.clone();
+        let executor = TmuxCommandExecutor::new()
+            .with_output_handler(move |line| handler_lines.borrow_mut().push(line.to_string()));
+        let output = executor
+            .execute_command("echo first; sleep 1; echo second; sleep 1")
+            .unwrap();
+        executor.terminate_session();
+
+        // The shell's startup files may print to the pane too, keep the command's lines only
+        let lines = lines.borrow();
+        let lines: Vec<&String> = lines
+            .iter()
+            .filter(|line| matches!(line.as_str(), "first" | "second"))
+            .collect();
+        assert_eq!(lines, vec!["first", "second"]);
+        assert!(output.contains("first\nsecond"));
+    }
+
+    #[test]
+    fn test_new_lines_between_snapshots() {
+        let first = "$ npm install\nadded 1 package\nadded 2 pack";
+        assert_eq!(
+            new_lines("", first),
+            vec!["$ npm install", "added 1 package"]
+        );
 
-        result.reverse();
-        result.join("\n")
+        let second = "$ npm install\nadded 1 package\nadded 2 packages\nadded 3";
+        assert_eq!(new_lines(first, second), vec!["added 2 packages"]);
+
+        // The screen scrolled, the first line is gone
+        let third = "added 1 package\nadded 2 packages\nadded 3 packages\ndone\n$";
+        assert_eq!(new_lines(second, third), vec!["added 3 packages", "done"]);
+
+        assert!(new_lines(third, third).is_empty());
+    }
+
+    #[test]
+    fn test_poll_interval_backs_off_until_output_changes() {
+        let mut interval = MIN_POLL_INTERVAL;
+        for _ in 0..10 {
+            interval = next_poll_interval(interval, false);
+        }
+        assert_eq!(interval, MAX_POLL_INTERVAL);
+
+        assert_eq!(next_poll_interval(interval, true), MIN_POLL_INTERVAL);
+        assert_eq!(
+            next_poll_interval(MIN_POLL_INTERVAL, false),
+            Duration::from_millis(40)
+        );
+    }
+
+    #[test]
+    fn test_clean_command_output() {
+        let marker = "__CMD_COMPLETE_test__";
+        let typed = format!(
+            "user@host:~$ {{ (ls) < /dev/null; echo exit code: $?; }} | cat; echo {}",
+            marker
+        );
+        let content = format!(
+            "user@host:~$\n{}\nCargo.toml\n\nsrc\nexit code: 0\n{}\nuser@host:~$\n\n",
+            typed, marker
+        );
+
+        let expected = "Cargo.toml\nsrc\nexit code: 0";
+        assert_eq!(
+            clean_command_output(&content, marker, "user@host:~$"),
+            expected
+        );
+        // Without a prompt, the typed command still ends the output
+        assert_eq!(clean_command_output(&content, marker, ""), expected);
+
+        assert_eq!(
+            last_non_empty_line("$ ls\nfile\nuser@host:~$   \n\n  \n"),
+            Some("user@host:~$")
+        );
+        assert_eq!(last_non_empty_line("\n\n"), None);
     }
 }
@@ -0,0 +1,221 @@
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::fs;
+
+use crate::{
+    theme::icon,
+    tools::{
+        execute_command::ExecuteCommandTool, final_answer::FINAL_ANSWER_TOOL, FunctionCall,
+        FunctionDef, Tool, ToolCallResult,
+    },
+};
+
+const CONFIG_FILE: &str = "config.json";
+const BUILTIN_TOOLS: &[&str] = &[
+    "execute_command",
+    "web_search",
+    "get_shell_history",
+    FINAL_ANSWER_TOOL,
+];
+
+/// Tools defined in ~/.config/ask-sh/config.json, read once per process
+static CUSTOM_TOOLS: Lazy<Vec<CustomToolDef>> = Lazy::new(|| {
+    let config = crate::get_config_dir()
+        .and_then(|dir| fs::read_to_string(dir.join(CONFIG_FILE)).ok())
+        .unwrap_or_default();
+
+    parse_custom_tools(&config)
+});
+
+#[derive(Debug, Deserialize)]
+struct Config {
+    #[serde(default)]
+    custom_tools: Vec<CustomToolDef>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct CustomToolDef {
+    name: String,
+    description: String,
+    #[serde(default = "empty_parameters")]
+    parameters: serde_json::Value,
+    /// Shell command where `{arg}` is replaced with the quoted value of the argument
+    command: String,
+}
+
+fn empty_parameters() -> serde_json::Value {
+    serde_json::json!({ "type": "object", "properties": {} })
+}
+
+pub struct CustomToolBuilder;
+
+impl CustomToolBuilder {
+    pub fn create_tools() -> Vec<Tool> {
+        CUSTOM_TOOLS
+            .iter()
+            .map(|custom_tool| Tool {
+                tool_type: "function".to_string(),
+                function: FunctionDef {
+                    name: custom_tool.name.clone(),
+                    description: custom_tool.description.clone(),
+                    parameters: custom_tool.parameters.clone(),
+                },
+            })
+            .collect()
+    }
+}
+
+pub struct CustomTool;
+
+impl CustomTool {
+    /// Run the custom tool of that name, None when there's no such tool
+    pub fn call_tool_function(function_call: &FunctionCall) -> Option<ToolCallResult> {
+        let custom_tool = CUSTOM_TOOLS
+            .iter()
+            .find(|custom_tool| custom_tool.name == function_call.name)?;
+
+        // Custom tools run like any other command, approval included
+        let command_call = FunctionCall {
+            name: "execute_command".to_string(),
+            arguments: serde_json::json!({
+                "command": render_command(custom_tool, &function_call.arguments),
+            }),
+        };
+        let mut result = ExecuteCommandTool::call_tool_function(&command_call);
+        result.function_call = function_call.clone();
+
+        Some(result)
+    }
+}
+
+/// Custom tools can't replace the built-in ones
+fn parse_custom_tools(config: &str) -> Vec<CustomToolDef> {
+    if config.trim().is_empty() {
+        return Vec::new();
+    }
+
+    let config: Config = match serde_json::from_str(config) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!(
+                "{}Ignoring custom tools, the config file is invalid: {}",
+                icon("❌ "),
+                e
+            );
+            return Vec::new();
+        }
+    };
+
+    config
+        .custom_tools
+        .into_iter()
+        .filter(|custom_tool| !BUILTIN_TOOLS.contains(&custom_tool.name.as_str()))
+        .collect()
+}
+
+/// Replace each `{arg}` with the argument's value, quoted so it stays a single shell word.
+/// Missing arguments become `''`. The template is read once, so the inserted values are
+/// never searched for placeholders themselves.
+fn render_command(custom_tool: &CustomToolDef, arguments: &serde_json::Value) -> String {
+    // Other braces, like in `awk '{print $1}'`, are part of the command
+    let is_placeholder = |name: &str| {
+        custom_tool.parameters["properties"].get(name).is_some() || arguments.get(name).is_some()
+    };
+
+    let mut command = String::new();
+    let mut rest = custom_tool.command.as_str();
+    while let Some(start) = rest.find('{') {
+        command.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let name = after
+            .find('}')
+            .map(|end| &after[..end])
+            .filter(|name| is_placeholder(name));
+        match name {
+            Some(name) => {
+                let value = match arguments.get(name) {
+                    Some(serde_json::Value::String(text)) => text.clone(),
+                    Some(serde_json::Value::Null) | None => String::new(),
+                    Some(value) => value.to_string(),
+                };
+                command.push_str(&shell_quote(&value));
+                rest = &after[name.len() + 1..];
+            }
+            None => {
+                command.push('{');
+                rest = after;
+            }
+        }
+    }
+    command.push_str(rest);
+
+    command
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_tools_from_config() {
+        let config = r#"{
+            "custom_tools": [
+                {
+                    "name": "deploy_status",
+                    "description": "Show the deploy status of a service",
+                    "parameters": {
+                        "type": "object",
+                        "properties": {
+                            "service": { "type": "string" },
+                            "env": { "type": "integer" }
+                        },
+                        "required": ["service"]
+                    },
+                    "command": "deployctl status {service} --env {env}"
+                },
+                { "name": "execute_command", "description": "Shadowing", "command": "true" },
+                { "name": "final_answer", "description": "Shadowing", "command": "true" }
+            ]
+        }"#;
+
+        let custom_tools = parse_custom_tools(config);
+        assert_eq!(custom_tools.len(), 1);
+
+        let arguments = serde_json::json!({ "service": "api'; rm -rf ~", "env": 2 });
+        assert_eq!(
+            render_command(&custom_tools[0], &arguments),
+            r"deployctl status 'api'\''; rm -rf ~' --env '2'"
+        );
+
+        // Optional arguments the model left out
+        let arguments = serde_json::json!({ "service": "api" });
+        assert_eq!(
+            render_command(&custom_tools[0], &arguments),
+            "deployctl status 'api' --env ''"
+        );
+    }
+
+    #[test]
+    fn test_values_are_not_rendered_again() {
+        let custom_tool = CustomToolDef {
+            name: "notify".to_string(),
+            description: "Notify".to_string(),
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": { "a": { "type": "string" }, "b": { "type": "string" } }
+            }),
+            command: "notify {a} {b} | awk '{print $1}'".to_string(),
+        };
+
+        let arguments = serde_json::json!({ "a": "{b}", "b": "; touch pwned #" });
+        assert_eq!(
+            render_command(&custom_tool, &arguments),
+            "notify '{b}' '; touch pwned #' | awk '{print $1}'"
+        );
+    }
+}
@@ -1,14 +1,55 @@
+use std::{
+    env,
+    io::{stdin, IsTerminal, Write},
+    process::{Command, Stdio},
+    sync::Once,
+    thread,
+    time::{Duration, Instant},
+};
+
 use console::style;
 use indicatif::{ProgressBar, ProgressStyle};
 use inquire::Confirm;
-use unicode_width::UnicodeWidthStr;
+use termimad::crossterm::terminal;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 use crate::{
+    audit_log::AuditEntry,
     command_analyser::CommandAnalyser,
+    is_debug_enabled,
+    process_command_executor::ProcessCommandExecutor,
+    redact::scrub_secrets,
+    renderer::spinner_frames,
+    theme::{assistant_name, icon},
     tmux_command_executor::TmuxCommandExecutor,
-    tools::{FunctionCall, FunctionDef, Tool, ToolCallResult},
+    tools::{
+        output_diff::show_output_diff, output_summary::summarize_output, FunctionCall, FunctionDef,
+        Tool, ToolCall, ToolCallResult,
+    },
+    user_system_info::UserSystemInfo,
+    ENV_APPROVAL_HOOK, ENV_APPROVE_DEFAULT, ENV_ATTACH_TMUX, ENV_AUTO_APPROVE, ENV_BATCH_APPROVE,
+    ENV_COMMAND_TIMEOUT, ENV_KEEP_STDIN, ENV_MAX_OUTPUT_CHARS, ENV_NEVER_APPROVE, ENV_SAFE_MODE,
+    ENV_STREAM_COMMAND_OUTPUT, ENV_SUMMARIZE_OUTPUT,
 };
 
+const DEFAULT_TERMINAL_WIDTH: usize = 80;
+const DEFAULT_MAX_OUTPUT_CHARS: usize = 8000;
+/// A hook that doesn't decide by then rejects the command
+const APPROVAL_HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+const APPROVAL_HOOK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Signs that a command failed for lack of permissions, in lowercase
+const PERMISSION_ERRORS: &[&str] = &[
+    "permission denied",
+    "operation not permitted",
+    "must be root",
+    "are you root",
+    "eacces",
+];
+
+/// Shown before the first command only
+static MISSING_TMUX_WARNING: Once = Once::new();
+
 pub struct ExecuteCommandToolBuilder;
 
 impl ExecuteCommandToolBuilder {
@@ -38,26 +79,44 @@ impl ExecuteCommandTool {
     pub fn call_tool_function(function_call: &FunctionCall) -> ToolCallResult {
         let command = function_call.arguments["command"].as_str().unwrap_or("");
 
-        let mut prompt_result: Option<Result<bool, inquire::InquireError>> = None;
+        let mut approval = Approval::Approved;
 
         let (needs_approval, approval_reason) = CommandAnalyser::requires_approval(command);
 
-        if needs_approval {
-            let result = Confirm::new("Is it alright if I run this command and read the output?")
-                .with_help_message(format!("{} ({})", &command, &approval_reason.unwrap()).as_ref())
-                .with_default(false)
-                .prompt();
-            prompt_result = Some(result);
+        // Safe mode rejects these without asking, and interactive commands are never run
+        let is_interactive = CommandAnalyser::interactive_alternative(command).is_some();
+        if needs_approval && !get_env_flag(ENV_SAFE_MODE) && !is_interactive {
+            approval = get_approval(command, approval_reason.unwrap(), stdin().is_terminal());
+        }
 
-            println!();
+        Self::call_tool_function_with_approval(function_call, approval)
+    }
+
+    /// Run the command once it's been decided whether it may run
+    pub fn call_tool_function_with_approval(
+        function_call: &FunctionCall,
+        approval: Approval,
+    ) -> ToolCallResult {
+        let command = function_call.arguments["command"].as_str().unwrap_or("");
+
+        // Checked here so no approval, batch or automatic, can get around it
+        let interactive_alternative = CommandAnalyser::interactive_alternative(command);
+        let approval = match (interactive_alternative, is_blocked_by_safe_mode(command)) {
+            (Some(_), _) => Approval::Interactive,
+            (None, true) => Approval::SafeMode,
+            (None, false) => approval,
+        };
+
+        if approval == Approval::Approved {
+            warn_if_tmux_missing();
         }
 
         let spinner = display_command_with_spinner_status(command);
         let command_output: String;
+        let mut hint: Option<&str> = None;
 
-        if prompt_result.is_none() || prompt_result.unwrap().is_ok_and(|r| r == true) {
-            let tmux_executor = TmuxCommandExecutor::new();
-            let command_result = tmux_executor.execute_command(command);
+        if approval == Approval::Approved {
+            let command_result = run_command(command, &spinner);
 
             match command_result {
                 Ok(output) => {
@@ -67,9 +126,34 @@ impl ExecuteCommandTool {
                 Err(error_output) => {
                     update_spinner_status(&spinner, command, false);
                     command_output = error_output.to_string();
+                    hint = permission_hint(command, &command_output);
                 }
             }
-            tmux_executor.terminate_session();
+        } else if approval == Approval::NoTerminal {
+            update_spinner_status(&spinner, command, false);
+            command_output =
+                "Command rejected: it needs approval, but no terminal is available to ask the user."
+                    .to_string();
+        } else if approval == Approval::SafeMode {
+            update_spinner_status(&spinner, command, false);
+            command_output = "Command rejected: ask.sh is in safe mode, only read-only commands \
+                can run. Suggest a read-only alternative instead."
+                .to_string();
+        } else if let Some(alternative) = interactive_alternative {
+            update_spinner_status(&spinner, command, false);
+            command_output = format!(
+                "Command rejected: it waits for keyboard input and would hang, since nobody can \
+                type into it. Use a non-interactive equivalent instead. {}",
+                alternative
+            );
+        } else if approval == Approval::RejectedByHook {
+            update_spinner_status(&spinner, command, false);
+            command_output =
+                "Command rejected: the user's approval policy doesn't allow it.".to_string();
+        } else if approval == Approval::Denied {
+            update_spinner_status(&spinner, command, false);
+            command_output =
+                "Command rejected: the user never allows commands like this one.".to_string();
         } else {
             update_spinner_status(&spinner, command, false);
             command_output = "Command rejected by the user.".to_string();
@@ -77,19 +161,399 @@ impl ExecuteCommandTool {
 
         println!();
 
+        if approval == Approval::Approved {
+            show_output_diff(command, &command_output);
+        }
+
+        if is_debug_enabled() {
+            eprintln!(
+                "{}Output of `{}`:\n{}",
+                icon("🐛 "),
+                scrub_secrets(command),
+                scrub_secrets(&command_output)
+            );
+        }
+
+        let approval_required = CommandAnalyser::requires_approval(command).0;
+        let audit_entry = AuditEntry::new(
+            command,
+            approval_required,
+            approval == Approval::Approved,
+            &command_output,
+        );
+        if let Err(e) = audit_entry.write() {
+            eprintln!("{}Could not write to the audit log: {}", icon("⚠️  "), e);
+        }
+
+        let max_output_chars = env::var(ENV_MAX_OUTPUT_CHARS)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(DEFAULT_MAX_OUTPUT_CHARS);
+
+        // Summarizing only pays off when the output would be cut anyway
+        let summary = match command_output.chars().count() > max_output_chars
+            && get_env_flag(ENV_SUMMARIZE_OUTPUT)
+        {
+            true => summarize_output(&command_output),
+            false => None,
+        };
+        let output = summary.as_deref().unwrap_or(&command_output);
+        let mut content = truncate_middle(output, max_output_chars);
+        if let Some(hint) = hint {
+            content = format!("{}\n\n{}", content, hint);
+        }
+
         ToolCallResult {
+            tool_call_id: None,
             function_call: function_call.clone(),
-            content: serde_json::Value::String(command_output),
+            content: serde_json::Value::String(content),
         }
     }
 }
 
+/// Models rarely think of sudo on their own after a permission error, so it's pointed out.
+/// Running it with sudo is left to the user, as a password prompt would hang the command.
+fn permission_hint(command: &str, output: &str) -> Option<&'static str> {
+    let output = output.to_lowercase();
+    if !PERMISSION_ERRORS.iter().any(|error| output.contains(error)) {
+        return None;
+    }
+
+    Some(match command.trim_start().starts_with("sudo ") {
+        true => "Hint: permission was denied, even with sudo.",
+        false => {
+            "Hint: permission was denied, the command may require sudo. \
+            Suggest the corrected command to the user instead of running it."
+        }
+    })
+}
+
+/// Said before the first command runs, as commands then run differently than expected
+fn warn_if_tmux_missing() {
+    if cfg!(windows) || TmuxCommandExecutor::is_available() {
+        return;
+    }
+
+    MISSING_TMUX_WARNING.call_once(|| {
+        eprintln!(
+            "{}tmux isn't installed, so commands run directly with sh instead. \
+            Install tmux (e.g. `brew install tmux` or `sudo apt install tmux`) \
+            to run them in your shell and watch them with {}.\n",
+            icon("⚠️  "),
+            ENV_ATTACH_TMUX
+        );
+    });
+}
+
+/// Runs the command in tmux, or directly through the user's shell on Windows
+/// and wherever tmux isn't installed
+fn run_command(command: &str, spinner: &ProgressBar) -> Result<String, Box<dyn std::error::Error>> {
+    let timeout = get_command_timeout();
+
+    if cfg!(windows) || !TmuxCommandExecutor::is_available() {
+        let mut process_executor = ProcessCommandExecutor::new(&UserSystemInfo::new().shell);
+        if let Some(timeout) = timeout {
+            process_executor = process_executor.with_timeout(timeout);
+        }
+        return process_executor.execute_command(command);
+    }
+
+    let mut tmux_executor = TmuxCommandExecutor::new()
+        .with_attach(get_env_flag(ENV_ATTACH_TMUX))
+        .with_stdin(get_env_flag(ENV_KEEP_STDIN));
+    if let Some(timeout) = timeout {
+        tmux_executor = tmux_executor.with_timeout(timeout);
+    }
+    // printed above the spinner, which keeps spinning below
+    if get_env_flag(ENV_STREAM_COMMAND_OUTPUT) {
+        let spinner = spinner.clone();
+        tmux_executor = tmux_executor.with_output_handler(move |line| {
+            spinner.suspend(|| eprintln!("{}", style(line).dim()))
+        });
+    }
+    let command_result = tmux_executor.execute_command(command);
+    tmux_executor.terminate_session();
+
+    command_result
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Approval {
+    Approved,
+    Rejected,
+    NoTerminal,
+    /// Matches ASK_SH_NEVER_APPROVE, so it was left out of a batch
+    Denied,
+    /// Not read-only while ASK_SH_SAFE_MODE is on
+    SafeMode,
+    /// Waits for keyboard input, which would hang the executor
+    Interactive,
+    /// ASK_SH_APPROVAL_HOOK exited with an error
+    RejectedByHook,
+}
+
+/// With ASK_SH_BATCH_APPROVE, lists every command of the turn and asks once for all of them.
+/// Returns the approval of each tool call, or None when it's decided per command as usual.
+pub fn get_batch_approval(tool_calls: &[ToolCall]) -> Vec<Option<Approval>> {
+    batch_approval(tool_calls, stdin().is_terminal())
+}
+
+fn batch_approval(tool_calls: &[ToolCall], interactive: bool) -> Vec<Option<Approval>> {
+    let commands: Vec<Option<&str>> = tool_calls
+        .iter()
+        .map(|tool_call| match tool_call.function.name.as_str() {
+            "execute_command" => tool_call.function.arguments["command"].as_str(),
+            _ => None,
+        })
+        .collect();
+
+    // The approval hook decides about each command on its own
+    if !get_env_flag(ENV_BATCH_APPROVE)
+        || approval_hook().is_some()
+        || commands.iter().flatten().count() == 0
+    {
+        return vec![None; tool_calls.len()];
+    }
+
+    println!(
+        "{}The {} wants to run these commands:",
+        icon("📋 "),
+        assistant_name()
+    );
+    for (number, command) in commands.iter().flatten().enumerate() {
+        match is_never_approved(command) {
+            true => println!(
+                "  {}. {} (never approved)",
+                number + 1,
+                style(command).strikethrough().dim()
+            ),
+            false => println!("  {}. {}", number + 1, command),
+        }
+    }
+    println!();
+
+    let approval = decide_batch_approval(interactive);
+
+    commands
+        .iter()
+        .map(|command| {
+            command.map(|command| match is_never_approved(command) {
+                true => Approval::Denied,
+                false => approval,
+            })
+        })
+        .collect()
+}
+
+fn decide_batch_approval(interactive: bool) -> Approval {
+    if get_env_flag(ENV_AUTO_APPROVE) {
+        return Approval::Approved;
+    }
+
+    if !interactive {
+        return match get_env_flag(ENV_APPROVE_DEFAULT) {
+            true => Approval::Approved,
+            false => Approval::NoTerminal,
+        };
+    }
+
+    let result = Confirm::new("Is it alright if I run these commands and read the output?")
+        .with_default(get_env_flag(ENV_APPROVE_DEFAULT))
+        .prompt();
+
+    println!();
+
+    match result {
+        Ok(true) => Approval::Approved,
+        _ => Approval::Rejected,
+    }
+}
+
+/// With ASK_SH_APPROVE_ONCE, the first command needing approval is confirmed as usual, and
+/// later ones run without asking once one was approved, except dangerous and never approved
+/// commands, which are left to the usual approval. `approved` is whether one was approved
+/// earlier in the session.
+pub fn approve_once(
+    tool_calls: &[ToolCall],
+    approvals: Vec<Option<Approval>>,
+    approved: &mut bool,
+    mut confirm: impl FnMut(&str, &str) -> Approval,
+) -> Vec<Option<Approval>> {
+    tool_calls
+        .iter()
+        .zip(approvals)
+        .map(|(tool_call, approval)| {
+            let command = match tool_call.function.name.as_str() {
+                "execute_command" => tool_call.function.arguments["command"].as_str()?,
+                _ => return approval,
+            };
+            let (needs_approval, reason) = CommandAnalyser::requires_approval(command);
+
+            if approval.is_some()
+                || !needs_approval
+                || is_blocked_by_safe_mode(command)
+                || CommandAnalyser::interactive_alternative(command).is_some()
+                || CommandAnalyser::is_dangerous(command)
+                || is_never_approved(command)
+            {
+                return approval;
+            }
+
+            if *approved {
+                return Some(Approval::Approved);
+            }
+
+            let approval = confirm(command, reason.unwrap_or("needs approval"));
+            *approved = approval == Approval::Approved;
+            Some(approval)
+        })
+        .collect()
+}
+
+/// Ask about a command the way it's asked before running it
+pub fn confirm_command(command: &str, reason: &str) -> Approval {
+    get_approval(command, reason, stdin().is_terminal())
+}
+
+/// Decides whether a command needing approval may run.
+/// The user is only prompted when a terminal is available to answer.
+fn get_approval(command: &str, reason: &str, interactive: bool) -> Approval {
+    // Commands on the denylist are never approved automatically
+    let denied = is_never_approved(command);
+
+    if let Some(hook) = approval_hook() {
+        return match denied {
+            true => Approval::Denied,
+            false => run_approval_hook(&hook, command, reason, APPROVAL_HOOK_TIMEOUT),
+        };
+    }
+
+    if !denied && get_env_flag(ENV_AUTO_APPROVE) {
+        return Approval::Approved;
+    }
+
+    if !interactive {
+        return match !denied && get_env_flag(ENV_APPROVE_DEFAULT) {
+            true => Approval::Approved,
+            false => Approval::NoTerminal,
+        };
+    }
+
+    let result = Confirm::new("Is it alright if I run this command and read the output?")
+        .with_help_message(format!("{} ({})", command, reason).as_ref())
+        .with_default(get_env_flag(ENV_APPROVE_DEFAULT))
+        .prompt();
+
+    println!();
+
+    match result {
+        Ok(true) => Approval::Approved,
+        _ => Approval::Rejected,
+    }
+}
+
+fn approval_hook() -> Option<String> {
+    env::var(ENV_APPROVAL_HOOK)
+        .ok()
+        .filter(|hook| !hook.trim().is_empty())
+}
+
+/// With ASK_SH_APPROVAL_HOOK, a policy command decides instead of the user. It's given the
+/// command on stdin, and as its argument outside of Windows, and approves it by exiting with 0.
+/// cmd can't be given the command as an argument safely, its quoting rules are its own.
+fn run_approval_hook(hook: &str, command: &str, reason: &str, timeout: Duration) -> Approval {
+    let mut process = match cfg!(windows) {
+        true => {
+            let mut process = Command::new("cmd");
+            process.args(["/C", hook]);
+            process
+        }
+        false => {
+            let mut process = Command::new("sh");
+            process.args(["-c", &format!("{} \"$@\"", hook), "ask-sh", command]);
+            process
+        }
+    };
+    let child = process
+        .env("ASK_SH_APPROVAL_REASON", reason)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn();
+
+    let status = child.and_then(|mut child| {
+        if let Some(mut stdin) = child.stdin.take() {
+            // A hook that only reads its argument may exit before reading stdin
+            let _ = writeln!(stdin, "{}", command);
+        }
+
+        let started = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Some(status));
+            }
+            if started.elapsed() >= timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Ok(None);
+            }
+            thread::sleep(APPROVAL_HOOK_POLL_INTERVAL);
+        }
+    });
+
+    match status {
+        Ok(Some(status)) if status.success() => Approval::Approved,
+        Ok(Some(_)) => Approval::RejectedByHook,
+        Ok(None) => {
+            eprintln!(
+                "{}{} didn't decide within {}s, the command is rejected",
+                icon("⚠️  "),
+                ENV_APPROVAL_HOOK,
+                timeout.as_secs_f32()
+            );
+            Approval::RejectedByHook
+        }
+        Err(e) => {
+            eprintln!(
+                "{}Could not run {} ({}), the command is rejected",
+                icon("⚠️  "),
+                ENV_APPROVAL_HOOK,
+                e
+            );
+            Approval::RejectedByHook
+        }
+    }
+}
+
+/// In safe mode, any command that isn't known to be read-only is rejected
+fn is_blocked_by_safe_mode(command: &str) -> bool {
+    get_env_flag(ENV_SAFE_MODE) && !CommandAnalyser::is_read_only(command)
+}
+
+pub(crate) fn get_env_flag(name: &str) -> bool {
+    env::var(name).is_ok_and(|value| value == "true" || value == "1")
+}
+
+/// Checks the command against the comma separated ASK_SH_NEVER_APPROVE denylist
+fn is_never_approved(command: &str) -> bool {
+    env::var(ENV_NEVER_APPROVE).is_ok_and(|denylist| {
+        denylist
+            .split(',')
+            .map(|pattern| pattern.trim())
+            .any(|pattern| !pattern.is_empty() && command.contains(pattern))
+    })
+}
+
+/// Command timeout in seconds, read from the environment
+fn get_command_timeout() -> Option<Duration> {
+    env::var(ENV_COMMAND_TIMEOUT)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .map(Duration::from_secs)
+}
+
 fn display_command_with_spinner_status(command: &str) -> ProgressBar {
     let template = create_progress_bar_template(command);
-    let spinner: Vec<String> = vec!['⣷', '⣯', '⣟', '⡿', '⢿', '⣻', '⣽', '⣾']
-        .into_iter()
-        .map(|s| style(s).cyan().bright().to_string())
-        .collect();
+    let spinner = spinner_frames();
 
     let spinner_ref: Vec<&str> = spinner.iter().map(|s| s.as_str()).collect();
     let progress_bar = ProgressBar::new_spinner();
@@ -98,7 +562,6 @@ fn display_command_with_spinner_status(command: &str) -> ProgressBar {
             .unwrap()
             .tick_strings(&spinner_ref),
     );
-    progress_bar.set_message(command.to_string());
     progress_bar.enable_steady_tick(std::time::Duration::from_millis(150));
 
     progress_bar
@@ -121,22 +584,403 @@ fn update_spinner_status(progress_bar: &ProgressBar, command: &str, command_succ
                 .tick_strings(&[style("✗").red().to_string().as_ref()]),
         );
     }
-    progress_bar.finish_with_message(command.to_string());
+    progress_bar.finish();
 }
 
 fn create_progress_bar_template(command: &str) -> String {
+    let terminal_width = terminal::size()
+        .map(|(columns, _)| columns as usize)
+        .unwrap_or(DEFAULT_TERMINAL_WIDTH);
+
+    build_box_template(command, terminal_width)
+}
+
+/// Builds a box around the command, with the spinner in front of the first line.
+/// Each line of the command, e.g. of a heredoc, is truncated with an ellipsis to fit the terminal.
+fn build_box_template(command: &str, terminal_width: usize) -> String {
     let padding = 1;
-    let unstyled_content = command;
-    let content_width = UnicodeWidthStr::width(unstyled_content);
-    let box_width = content_width + 2 + 2 * padding;
-
-    let template = format!(
-        "{top_bar}\n│{left_pad}{{spinner}} {{msg}}{right_pad}│\n{bottom_bar}\n",
-        top_bar = format!("╭{}╮", "─".repeat(box_width)),
-        left_pad = " ".repeat(padding),
-        right_pad = " ".repeat(padding),
-        bottom_bar = format!("╰{}╯", "─".repeat(box_width)),
-    );
+    let spinner_width = 2; // spinner and a space
+    let max_content_width = terminal_width
+        .saturating_sub(2 + 2 * padding + spinner_width)
+        .max(1);
+
+    let mut lines: Vec<String> = command
+        .lines()
+        .map(|line| truncate_to_width(&printable_line(line), max_content_width))
+        .collect();
+    if lines.is_empty() {
+        lines.push(String::new());
+    }
+    let content_width = lines
+        .iter()
+        .map(|line| UnicodeWidthStr::width(line.as_str()))
+        .max()
+        .unwrap_or(0);
+    let box_width = content_width + spinner_width + 2 * padding;
+
+    let mut template = format!("╭{}╮\n", "─".repeat(box_width));
+    for (index, line) in lines.iter().enumerate() {
+        let prefix = if index == 0 { "{spinner} " } else { "  " };
+        let right_pad = content_width - UnicodeWidthStr::width(line.as_str());
+
+        // Braces are escaped so the command isn't parsed as template placeholders
+        template.push_str(&format!(
+            "│{pad}{prefix}{line}{right_pad}{pad}│\n",
+            pad = " ".repeat(padding),
+            line = line.replace('{', "{{").replace('}', "}}"),
+            right_pad = " ".repeat(right_pad),
+        ));
+    }
+    template.push_str(&format!("╰{}╯\n", "─".repeat(box_width)));
 
     template
 }
+
+/// The terminal moves the cursor on tabs and carriage returns instead of printing one column,
+/// which would push the right border out of place
+fn printable_line(line: &str) -> String {
+    line.chars()
+        .filter_map(|c| match c {
+            '\t' => Some("    ".to_string()),
+            c if c.is_control() => None,
+            c => Some(c.to_string()),
+        })
+        .collect()
+}
+
+fn truncate_to_width(text: &str, max_width: usize) -> String {
+    if UnicodeWidthStr::width(text) <= max_width {
+        return text.to_string();
+    }
+
+    let mut truncated = String::new();
+    let mut width = 0;
+    for c in text.chars() {
+        let char_width = UnicodeWidthChar::width(c).unwrap_or(0);
+        // Keep one column for the ellipsis
+        if width + char_width + 1 > max_width {
+            break;
+        }
+        truncated.push(c);
+        width += char_width;
+    }
+    truncated.push('…');
+
+    truncated
+}
+
+/// Keeps the beginning and the end of long command output, which matter most to the model
+fn truncate_middle(text: &str, max_chars: usize) -> String {
+    let char_count = text.chars().count();
+    if char_count <= max_chars {
+        return text.to_string();
+    }
+
+    let head_chars = max_chars / 2;
+    let tail_chars = max_chars - head_chars;
+    let omitted = char_count - max_chars;
+
+    let head: String = text.chars().take(head_chars).collect();
+    let tail: String = text.chars().skip(char_count - tail_chars).collect();
+
+    format!("{}\n[...{} chars omitted...]\n{}", head, omitted, tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Renders the template as the user would see it, returning the width of each line
+    fn rendered_line_widths(template: &str) -> Vec<usize> {
+        template
+            .replace("{spinner}", "⣷")
+            .replace("{{", "{")
+            .replace("}}", "}")
+            .lines()
+            .map(UnicodeWidthStr::width)
+            .collect()
+    }
+
+    #[test]
+    fn test_long_command_fits_terminal() {
+        let command = format!("echo {}\nls -la", "a".repeat(100));
+        let template = build_box_template(&command, 40);
+
+        let widths = rendered_line_widths(&template);
+        assert_eq!(widths.len(), 4);
+        assert!(widths.iter().all(|&width| width == 40), "{:?}", widths);
+        assert!(template.contains('…'));
+        assert!(template.contains("ls -la"));
+    }
+
+    #[test]
+    fn test_multibyte_command_box_borders() {
+        let template = build_box_template("ls ~/Документы/café/日本語", 80);
+
+        let widths = rendered_line_widths(&template);
+        assert_eq!(widths.len(), 3);
+        assert_eq!(widths[0], widths[1]);
+        assert_eq!(widths[2], widths[1]);
+    }
+
+    #[test]
+    fn test_heredoc_command_box_borders() {
+        let command = "cat <<-EOF > notes.txt\r\n\tfirst line\n\tEOF";
+        let template = build_box_template(command, 80);
+
+        let widths = rendered_line_widths(&template);
+        assert_eq!(widths.len(), 5);
+        assert!(
+            widths.iter().all(|&width| width == widths[0]),
+            "{:?}",
+            widths
+        );
+        assert!(!template.contains(['\t', '\r']));
+        assert!(template.contains("│       first line"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_approval_hook() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let hook = env::temp_dir().join(format!("ask-sh-hook-{}.sh", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &hook,
+            "#!/bin/sh\n\
+            read -r piped\n\
+            [ \"$piped\" = \"$1\" ] || exit 2\n\
+            case \"$1\" in ls|ls\\ *) exit 0 ;; *) exit 1 ;; esac\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let reason = "modifies files or system state";
+
+        env::set_var(ENV_APPROVAL_HOOK, &hook);
+        env::set_var(ENV_AUTO_APPROVE, "true");
+        let ls = get_approval("ls -la", reason, false);
+        let rm = get_approval("rm file.txt", reason, true);
+        env::set_var(ENV_APPROVAL_HOOK, "/nonexistent/ask-sh-hook");
+        let missing_hook = get_approval("ls -la", reason, true);
+        env::remove_var(ENV_APPROVAL_HOOK);
+        env::remove_var(ENV_AUTO_APPROVE);
+        std::fs::remove_file(&hook).unwrap();
+
+        assert_eq!(ls, Approval::Approved);
+        assert_eq!(rm, Approval::RejectedByHook);
+        assert_eq!(missing_hook, Approval::RejectedByHook);
+
+        let started = Instant::now();
+        let hanging_hook = run_approval_hook("sleep 60 &&", "ls", reason, Duration::from_secs(1));
+        assert_eq!(hanging_hook, Approval::RejectedByHook);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_non_interactive_approval() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let reason = "modifies files or system state";
+
+        env::remove_var(ENV_AUTO_APPROVE);
+        env::remove_var(ENV_APPROVE_DEFAULT);
+        assert_eq!(
+            get_approval("rm file.txt", reason, false),
+            Approval::NoTerminal
+        );
+
+        env::set_var(ENV_APPROVE_DEFAULT, "true");
+        assert_eq!(
+            get_approval("rm file.txt", reason, false),
+            Approval::Approved
+        );
+
+        env::set_var(ENV_NEVER_APPROVE, "rm");
+        assert_eq!(
+            get_approval("rm file.txt", reason, false),
+            Approval::NoTerminal
+        );
+
+        env::remove_var(ENV_APPROVE_DEFAULT);
+        env::remove_var(ENV_NEVER_APPROVE);
+    }
+
+    #[test]
+    fn test_approve_once() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        env::remove_var(ENV_SAFE_MODE);
+        env::set_var(ENV_NEVER_APPROVE, "git push");
+
+        let tool_call = |command: &str| ToolCall {
+            id: None,
+            function: FunctionCall {
+                name: "execute_command".to_string(),
+                arguments: serde_json::json!({ "command": command }),
+            },
+        };
+        let tool_calls = vec![
+            tool_call("ls"),
+            tool_call("mkdir build"),
+            tool_call("touch build/.keep"),
+            tool_call("rm -rf target"),
+            tool_call("git push"),
+            tool_call("mkdir x && git reset --hard"),
+            tool_call("touch a; git push --force"),
+            tool_call("chmod 777 x; dd if=x of=y"),
+        ];
+
+        let mut approved = false;
+        let mut prompts = Vec::new();
+        let approvals = approve_once(
+            &tool_calls,
+            vec![None; tool_calls.len()],
+            &mut approved,
+            |command, _| {
+                prompts.push(command.to_string());
+                Approval::Approved
+            },
+        );
+        env::remove_var(ENV_NEVER_APPROVE);
+
+        // Only the first command needing approval was asked about
+        assert_eq!(prompts, vec!["mkdir build"]);
+        for command in [
+            "mkdir x && git reset --hard",
+            "touch a; git push --force",
+            "chmod 777 x; dd if=x of=y",
+        ] {
+            assert!(CommandAnalyser::is_dangerous(command), "{}", command);
+        }
+        assert!(approved);
+        assert_eq!(
+            approvals,
+            vec![
+                None,
+                Some(Approval::Approved),
+                Some(Approval::Approved),
+                None,
+                None,
+                None,
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_batch_approval_strikes_denied_commands() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let tool_call = |name: &str, arguments: serde_json::Value| ToolCall {
+            id: None,
+            function: FunctionCall {
+                name: name.to_string(),
+                arguments,
+            },
+        };
+        let tool_calls = vec![
+            tool_call("execute_command", serde_json::json!({ "command": "ls" })),
+            tool_call("web_search", serde_json::json!({ "query": "tmux" })),
+            tool_call(
+                "execute_command",
+                serde_json::json!({ "command": "rm -rf x" }),
+            ),
+        ];
+
+        env::remove_var(ENV_AUTO_APPROVE);
+        env::set_var(ENV_APPROVE_DEFAULT, "true");
+        env::set_var(ENV_NEVER_APPROVE, "rm");
+
+        env::remove_var(ENV_BATCH_APPROVE);
+        assert_eq!(batch_approval(&tool_calls, false), vec![None, None, None]);
+
+        env::set_var(ENV_BATCH_APPROVE, "true");
+        assert_eq!(
+            batch_approval(&tool_calls, false),
+            vec![Some(Approval::Approved), None, Some(Approval::Denied)]
+        );
+
+        env::remove_var(ENV_BATCH_APPROVE);
+        env::remove_var(ENV_APPROVE_DEFAULT);
+        env::remove_var(ENV_NEVER_APPROVE);
+    }
+
+    #[test]
+    fn test_safe_mode_only_runs_read_only_commands() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let run = |command: &str| {
+            let function_call = FunctionCall {
+                name: "execute_command".to_string(),
+                arguments: serde_json::json!({ "command": command }),
+            };
+            let result = ExecuteCommandTool::call_tool_function_with_approval(
+                &function_call,
+                Approval::Approved,
+            );
+            result.content.as_str().unwrap().to_string()
+        };
+
+        env::set_var(ENV_SAFE_MODE, "true");
+        let ls_output = run("ls");
+        let rm_output = run("rm ask-sh-safe-mode-test-file");
+        let list_output = run("ls && rm -f ask-sh-safe-mode-test-file");
+        let redirect_output = run("echo hi > ask-sh-safe-mode-test-file");
+        env::remove_var(ENV_SAFE_MODE);
+
+        assert!(!ls_output.contains("safe mode"), "{}", ls_output);
+        assert!(rm_output.contains("safe mode"), "{}", rm_output);
+        assert!(list_output.contains("safe mode"), "{}", list_output);
+        assert!(redirect_output.contains("safe mode"), "{}", redirect_output);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_permission_denied_hint() {
+        // Safe mode, set by other tests, would reject the commands
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // Not executable, which even root can't run
+        let script = env::temp_dir().join(format!("ask-sh-script-{}.sh", uuid::Uuid::new_v4()));
+        std::fs::write(&script, "#!/bin/sh\necho hello\n").unwrap();
+        let function_call = FunctionCall {
+            name: "execute_command".to_string(),
+            arguments: serde_json::json!({ "command": script.display().to_string() }),
+        };
+
+        let result = ExecuteCommandTool::call_tool_function_with_approval(
+            &function_call,
+            Approval::Approved,
+        );
+        std::fs::remove_file(&script).unwrap();
+
+        let content = result.content.as_str().unwrap();
+        assert!(content.contains("may require sudo"), "{}", content);
+
+        // Only failures get the hint, not output that happens to mention it
+        let function_call = FunctionCall {
+            name: "execute_command".to_string(),
+            arguments: serde_json::json!({ "command": "echo Permission denied" }),
+        };
+        let result = ExecuteCommandTool::call_tool_function_with_approval(
+            &function_call,
+            Approval::Approved,
+        );
+        let content = result.content.as_str().unwrap();
+        assert!(content.contains("Permission denied"), "{}", content);
+        assert!(!content.contains("Hint:"), "{}", content);
+        assert_eq!(permission_hint("ls", "Command executed successfully"), None);
+        assert!(permission_hint("sudo cat x", "Permission denied")
+            .unwrap()
+            .contains("even with sudo"));
+    }
+
+    #[test]
+    fn test_truncate_middle_keeps_both_ends() {
+        let output = format!("first line\n{}\nlast line", "é".repeat(10_000));
+
+        let truncated = truncate_middle(&output, 100);
+        assert!(truncated.starts_with("first line\n"));
+        assert!(truncated.ends_with("\nlast line"));
+        assert!(truncated.contains("[...9921 chars omitted...]"));
+
+        assert_eq!(truncate_middle("short", 100), "short");
+    }
+}
@@ -0,0 +1,100 @@
+//! With ASK_SH_STRUCTURED_OUTPUT, the model ends its turn by calling `final_answer` with
+//! arguments following a JSON schema, which ask.sh prints as the answer for other programs
+
+use std::env;
+
+use crate::{
+    theme::icon,
+    tools::{FunctionDef, Tool, ToolCall},
+    ENV_STRUCTURED_OUTPUT,
+};
+
+pub const FINAL_ANSWER_TOOL: &str = "final_answer";
+
+/// The answer expected when ASK_SH_STRUCTURED_OUTPUT is just turned on
+fn default_schema() -> serde_json::Value {
+    serde_json::json!({
+        "type": "object",
+        "properties": {
+            "command": {
+                "type": "string",
+                "description": "The shell command that does what the user asked, empty if there's none"
+            },
+            "explanation": {
+                "type": "string",
+                "description": "What the command does, or the answer when there's no command"
+            },
+            "risk": {
+                "type": "string",
+                "enum": ["none", "low", "high"],
+                "description": "How much the command could damage data or the system"
+            }
+        },
+        "required": ["command", "explanation", "risk"]
+    })
+}
+
+/// ASK_SH_STRUCTURED_OUTPUT is `true` for the default answer, or the JSON schema of the answer
+fn structured_output_schema(value: &str) -> Option<serde_json::Value> {
+    match value.trim() {
+        "" | "false" | "0" => None,
+        "true" | "1" => Some(default_schema()),
+        schema => match serde_json::from_str(schema) {
+            Ok(schema @ serde_json::Value::Object(_)) => Some(schema),
+            _ => {
+                eprintln!(
+                    "{}{} must be true or a JSON schema object, ignoring it",
+                    icon("⚠️  "),
+                    ENV_STRUCTURED_OUTPUT
+                );
+                None
+            }
+        },
+    }
+}
+
+pub struct FinalAnswerToolBuilder;
+
+impl FinalAnswerToolBuilder {
+    pub fn create_tool() -> Option<Tool> {
+        let schema = structured_output_schema(&env::var(ENV_STRUCTURED_OUTPUT).ok()?)?;
+
+        Some(Tool {
+            tool_type: "function".to_string(),
+            function: FunctionDef {
+                name: FINAL_ANSWER_TOOL.to_string(),
+                description: "Give the final answer to the user's request. Once you're done, \
+                    always call this instead of answering in text, and don't call other tools \
+                    along with it."
+                    .to_string(),
+                parameters: schema,
+            },
+        })
+    }
+}
+
+/// The arguments of the `final_answer` call, if the model made one
+pub fn find_final_answer(tool_calls: &[ToolCall]) -> Option<&serde_json::Value> {
+    tool_calls
+        .iter()
+        .find(|tool_call| tool_call.function.name == FINAL_ANSWER_TOOL)
+        .map(|tool_call| &tool_call.function.arguments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_structured_output_schema() {
+        assert!(structured_output_schema("").is_none());
+        assert_eq!(structured_output_schema("1"), Some(default_schema()));
+
+        let schema = r#"{"type":"object","properties":{"summary":{"type":"string"}}}"#;
+        assert_eq!(
+            structured_output_schema(schema).unwrap()["properties"]["summary"]["type"],
+            "string"
+        );
+        assert!(structured_output_schema("[1, 2]").is_none());
+    }
+}
@@ -1,11 +1,19 @@
+pub mod custom_tool;
 pub mod execute_command;
+pub mod final_answer;
+mod output_diff;
+mod output_summary;
 pub mod searxng_web_search;
+pub mod shell_history;
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::tools::execute_command::{ExecuteCommandTool, ExecuteCommandToolBuilder};
+use crate::tools::custom_tool::{CustomTool, CustomToolBuilder};
+use crate::tools::execute_command::{Approval, ExecuteCommandTool, ExecuteCommandToolBuilder};
+use crate::tools::final_answer::{FinalAnswerToolBuilder, FINAL_ANSWER_TOOL};
 use crate::tools::searxng_web_search::{WebSearchTool, WebSearchToolBuilder};
+use crate::tools::shell_history::{ShellHistoryTool, ShellHistoryToolBuilder};
 
 #[derive(Debug, Error)]
 pub enum ToolError {
@@ -29,6 +37,9 @@ pub struct FunctionDef {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolCall {
+    /// Given by OpenAI, whose tool results must refer to the call they answer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub function: FunctionCall,
 }
 
@@ -38,10 +49,12 @@ pub struct FunctionCall {
     pub arguments: serde_json::Value,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ToolCallResult {
-    function_call: FunctionCall,
-    content: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) tool_call_id: Option<String>,
+    pub(crate) function_call: FunctionCall,
+    pub(crate) content: serde_json::Value,
 }
 
 pub fn get_available_tools() -> Vec<Tool> {
@@ -51,9 +64,53 @@ pub fn get_available_tools() -> Vec<Tool> {
         available_tools.push(WebSearchToolBuilder::create_tool());
     }
 
+    if ShellHistoryToolBuilder::tool_available() {
+        available_tools.push(ShellHistoryToolBuilder::create_tool());
+    }
+
+    available_tools.extend(CustomToolBuilder::create_tools());
+    available_tools.extend(FinalAnswerToolBuilder::create_tool());
+
     available_tools
 }
 
+/// A tool ask.sh knows, and why it isn't offered to the model when it isn't
+pub struct ToolStatus {
+    pub tool: Tool,
+    pub unavailable_reason: Option<String>,
+}
+
+/// Every tool, including the ones left out by `get_available_tools`
+pub fn get_tool_statuses() -> Vec<ToolStatus> {
+    let status = |tool: Tool, unavailable_reason: Option<&str>| ToolStatus {
+        tool,
+        unavailable_reason: unavailable_reason.map(str::to_string),
+    };
+
+    let mut statuses = vec![
+        status(ExecuteCommandToolBuilder::create_tool(), None),
+        status(
+            WebSearchToolBuilder::create_tool(),
+            (!WebSearchToolBuilder::tool_available())
+                .then_some("set ASK_SH_SEARXNG_BASE_URL to enable it"),
+        ),
+        status(
+            ShellHistoryToolBuilder::create_tool(),
+            (!ShellHistoryToolBuilder::tool_available()).then_some("no shell history file found"),
+        ),
+    ];
+    statuses.extend(
+        CustomToolBuilder::create_tools()
+            .into_iter()
+            .map(|tool| status(tool, None)),
+    );
+    if let Some(tool) = FinalAnswerToolBuilder::create_tool() {
+        statuses.push(status(tool, None));
+    }
+
+    statuses
+}
+
 pub async fn execute_tool(
     function_call: &FunctionCall,
 ) -> Result<ToolCallResult, Box<dyn std::error::Error>> {
@@ -66,6 +123,148 @@ pub async fn execute_tool(
             let result = WebSearchTool::call_tool_function(function_call).await;
             Ok(result)
         }
-        _ => Err(format!("Unknown function: {}", function_call.name).into()),
+        "get_shell_history" => {
+            let result = ShellHistoryTool::call_tool_function(function_call);
+            Ok(result)
+        }
+        // Handled by the chat handler, which stops at the final answer
+        FINAL_ANSWER_TOOL => Ok(ToolCallResult {
+            tool_call_id: None,
+            function_call: function_call.clone(),
+            content: serde_json::Value::String("Final answer received.".to_string()),
+        }),
+        _ => CustomTool::call_tool_function(function_call)
+            .ok_or_else(|| format!("Unknown function: {}", function_call.name).into()),
+    }
+}
+
+/// Tools that only read, so they can run alongside anything else
+fn is_read_only_tool(name: &str) -> bool {
+    matches!(name, "web_search" | "get_shell_history")
+}
+
+/// Run the tool calls of one turn, returning the results in the same order.
+/// Commands may depend on each other's side effects (`mkdir x` then `ls x`), so they run
+/// one after the other in the order the model gave them, while read-only tools run concurrently.
+pub async fn execute_tool_calls(
+    tool_calls: Vec<ToolCall>,
+    approvals: Vec<Option<Approval>>,
+) -> Vec<ToolCallResult> {
+    let mut sequential_calls = Vec::new();
+    let mut concurrent_handles = Vec::new();
+
+    for (index, (tool_call, approval)) in tool_calls.into_iter().zip(approvals).enumerate() {
+        if is_read_only_tool(&tool_call.function.name) {
+            concurrent_handles.push(tokio::spawn(async move {
+                (index, run_tool_call(&tool_call, None).await)
+            }));
+        } else {
+            sequential_calls.push((index, tool_call, approval));
+        }
+    }
+
+    let sequential_handle = tokio::spawn(async move {
+        let mut results = Vec::new();
+        for (index, tool_call, approval) in sequential_calls {
+            results.push((index, run_tool_call(&tool_call, approval).await));
+        }
+        results
+    });
+
+    let mut results = sequential_handle.await.unwrap();
+    for handle in concurrent_handles {
+        results.push(handle.await.unwrap());
+    }
+
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+async fn run_tool_call(tool_call: &ToolCall, approval: Option<Approval>) -> ToolCallResult {
+    let function_call = &tool_call.function;
+    let result = match approval {
+        Some(approval) => Ok(ExecuteCommandTool::call_tool_function_with_approval(
+            function_call,
+            approval,
+        )),
+        None => execute_tool(function_call).await,
+    };
+
+    // Errors are given back to the model, as any other tool output
+    let mut result = result.unwrap_or_else(|error| ToolCallResult {
+        tool_call_id: None,
+        function_call: function_call.clone(),
+        content: serde_json::Value::String(error.to_string()),
+    });
+    result.tool_call_id = tool_call.id.clone();
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dependent_commands_run_in_order() {
+        let directory = std::env::temp_dir().join(format!("ask-sh-test-{}", uuid::Uuid::new_v4()));
+        let command = |command: String| ToolCall {
+            id: command
+                .split(' ')
+                .next()
+                .map(|name| format!("call_{}", name)),
+            function: FunctionCall {
+                name: "execute_command".to_string(),
+                arguments: serde_json::json!({ "command": command }),
+            },
+        };
+        let tool_calls = vec![
+            command(format!("mkdir {}", directory.display())),
+            command(format!("ls -d {}", directory.display())),
+        ];
+        let approvals = vec![Some(Approval::Approved); 2];
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let results = runtime.block_on(execute_tool_calls(tool_calls, approvals));
+        let _ = std::fs::remove_dir(&directory);
+
+        assert_eq!(results.len(), 2);
+        // Each result answers its call
+        assert_eq!(results[0].tool_call_id.as_deref(), Some("call_mkdir"));
+        assert_eq!(results[1].tool_call_id.as_deref(), Some("call_ls"));
+        assert_eq!(
+            results[0].function_call.arguments["command"],
+            format!("mkdir {}", directory.display())
+        );
+        let output = results[1].content.as_str().unwrap();
+        assert!(
+            output.contains("Command executed successfully"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_tool_statuses_explain_unavailable_tools() {
+        let _env_lock = crate::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        std::env::remove_var(crate::ENV_SEARXNG_BASE_URL);
+
+        let statuses = get_tool_statuses();
+        let web_search = statuses
+            .iter()
+            .find(|status| status.tool.function.name == "web_search")
+            .unwrap();
+        assert!(web_search.unavailable_reason.is_some());
+
+        let available: Vec<String> = get_available_tools()
+            .into_iter()
+            .map(|tool| tool.function.name)
+            .collect();
+        for status in &statuses {
+            assert_eq!(
+                available.contains(&status.tool.function.name),
+                status.unavailable_reason.is_none()
+            );
+        }
     }
 }
@@ -0,0 +1,92 @@
+//! With ASK_SH_DIFF_OUTPUT, what changed in a command's output since it last ran
+//! in this conversation, e.g. a build before and after the model's fix
+
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Mutex, PoisonError},
+};
+
+use console::style;
+use once_cell::sync::Lazy;
+use similar::TextDiff;
+
+use crate::{theme::icon, ENV_DIFF_OUTPUT};
+
+/// Lines of unchanged output kept around each change
+const CONTEXT_LINES: usize = 3;
+
+/// Last output of each command run so far, by command
+static PREVIOUS_OUTPUTS: Lazy<Mutex<HashMap<String, String>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Remember the output, and show how it differs from the last run of the same command
+pub fn show_output_diff(command: &str, output: &str) {
+    if !env::var(ENV_DIFF_OUTPUT).is_ok_and(|value| value == "true" || value == "1") {
+        return;
+    }
+
+    let previous = PREVIOUS_OUTPUTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(command.trim().to_string(), output.to_string());
+
+    let Some(previous) = previous else {
+        return;
+    };
+
+    match output_diff(&previous, output) {
+        Some(diff) => {
+            println!("{}Changes since the last run:", icon("🔀 "));
+            println!("{}\n", colorize(&diff));
+        }
+        None => println!("{}Same output as the last run.\n", icon("🔀 ")),
+    }
+}
+
+/// Unified diff of the two outputs, or None when they're the same
+fn output_diff(previous: &str, current: &str) -> Option<String> {
+    if previous == current {
+        return None;
+    }
+
+    let diff = TextDiff::from_lines(previous, current);
+    Some(
+        diff.unified_diff()
+            .context_radius(CONTEXT_LINES)
+            .header("previous output", "current output")
+            .to_string(),
+    )
+}
+
+fn colorize(diff: &str) -> String {
+    diff.lines()
+        .map(|line| match line.chars().next() {
+            Some('+') => style(line).green().to_string(),
+            Some('-') => style(line).red().to_string(),
+            Some('@') => style(line).cyan().to_string(),
+            _ => line.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_diff() {
+        let previous = "Compiling app\nerror[E0425]: cannot find value `x`\nbuild failed\n";
+        let current = "Compiling app\nFinished dev profile\n";
+
+        let diff = output_diff(previous, current).unwrap();
+        assert!(diff.starts_with("--- previous output\n+++ current output\n"));
+        assert!(diff.contains("\n Compiling app\n"));
+        assert!(diff.contains("\n-error[E0425]: cannot find value `x`\n"));
+        assert!(diff.contains("\n-build failed\n"));
+        assert!(diff.contains("\n+Finished dev profile\n"));
+
+        assert!(output_diff(current, current).is_none());
+    }
+}
@@ -0,0 +1,115 @@
+//! With ASK_SH_SUMMARIZE_OUTPUT, long command output is cut down to its errors and warnings
+//! before the model reads it, e.g. a build log of thousands of lines with two failures in it
+
+/// Lines of output kept before and after each error or warning
+const CONTEXT_LINES: usize = 2;
+/// Lines always kept at the start (the command, how it ended) and the end (the final status)
+const HEAD_LINES: usize = 2;
+const TAIL_LINES: usize = 5;
+
+/// Lowercase words that usually mark the lines worth reading in a log
+const PATTERNS: &[&str] = &[
+    "error",
+    "warning",
+    "fail",
+    "fatal",
+    "panic",
+    "exception",
+    "traceback",
+    "denied",
+    "not found",
+    "no such file",
+    "undefined reference",
+    "segmentation fault",
+];
+
+/// The lines matching an error or warning pattern with some context, and the start and end
+/// of the output. None when nothing matches, as there's nothing better to keep.
+pub(super) fn summarize_output(output: &str) -> Option<String> {
+    let lines: Vec<&str> = output.lines().collect();
+    let matches: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| {
+            let line = line.to_lowercase();
+            PATTERNS.iter().any(|pattern| line.contains(pattern))
+        })
+        .map(|(index, _)| index)
+        .collect();
+    if matches.is_empty() {
+        return None;
+    }
+
+    let mut keep = vec![false; lines.len()];
+    keep[..HEAD_LINES.min(lines.len())].fill(true);
+    keep[lines.len().saturating_sub(TAIL_LINES)..].fill(true);
+    for index in matches {
+        let end = (index + CONTEXT_LINES + 1).min(lines.len());
+        keep[index.saturating_sub(CONTEXT_LINES)..end].fill(true);
+    }
+
+    let kept = keep.iter().filter(|&&kept| kept).count();
+    if kept == lines.len() {
+        return None;
+    }
+
+    let mut summary = format!(
+        "[Summarized by ask.sh: {} of {} lines kept, around errors and warnings]\n",
+        kept,
+        lines.len()
+    );
+    let mut omitted = 0;
+    for (line, kept) in lines.iter().zip(keep) {
+        if !kept {
+            omitted += 1;
+            continue;
+        }
+        if omitted > 0 {
+            summary.push_str(&format!("[...{} lines omitted...]\n", omitted));
+            omitted = 0;
+        }
+        summary.push_str(line);
+        summary.push('\n');
+    }
+
+    Some(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_buried_in_noise() {
+        let mut log = vec!["Command executed successfully:".to_string()];
+        for index in 0..3000 {
+            log.push(format!("   Compiling crate-{} v0.1.0", index));
+            if index == 1200 {
+                log.push("error[E0308]: mismatched types".to_string());
+                log.push("  --> src/main.rs:4:18".to_string());
+            }
+            if index == 2500 {
+                log.push("warning: unused variable: `x`".to_string());
+            }
+        }
+        log.push("exit code: 101".to_string());
+        let log = log.join("\n");
+
+        let summary = summarize_output(&log).unwrap();
+
+        assert!(summary.len() < log.len() / 20);
+        assert!(summary.starts_with("[Summarized by ask.sh: "));
+        assert!(summary.contains("Command executed successfully:"));
+        assert!(summary.contains("   Compiling crate-1200 v0.1.0\nerror[E0308]: mismatched types\n  --> src/main.rs:4:18\n"));
+        assert!(summary.contains("warning: unused variable: `x`"));
+        assert!(summary.contains("lines omitted"));
+        assert!(summary.ends_with("exit code: 101\n"));
+    }
+
+    #[test]
+    fn test_output_without_errors_is_left_alone() {
+        let log = "Downloading...\n".repeat(1000);
+
+        assert!(summarize_output(&log).is_none());
+    }
+}
@@ -1,16 +1,36 @@
 // Credits: nagarx/LLM-based-Search-Engine
 // https://github.com/nagarx/LLM-based-Search-Engine/blob/main/src/search/searxng.rs
 
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::env;
+use std::sync::Mutex;
+use std::time::Duration;
 
 use crate::{
+    http_client, is_debug_enabled,
+    theme::icon,
     tools::{FunctionCall, FunctionDef, Tool, ToolCallResult, ToolError},
-    ENV_SEARXNG_BASE_URL,
+    ENV_SEARCH_CACHE_SIZE, ENV_SEARCH_MAX_CHARS, ENV_SEARXNG_BASE_URL,
 };
 
+const DEFAULT_SEARCH_CACHE_SIZE: usize = 32;
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_SEARCH_MAX_CHARS: usize = 4000;
+
+/// Search results cached for the lifetime of the process
+static SEARCH_CACHE: Lazy<Mutex<SearchCache>> = Lazy::new(|| {
+    let capacity = env::var(ENV_SEARCH_CACHE_SIZE)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_SEARCH_CACHE_SIZE);
+
+    Mutex::new(SearchCache::new(capacity))
+});
+
 pub struct WebSearchToolBuilder;
 
 impl WebSearchToolBuilder {
@@ -30,6 +50,15 @@ impl WebSearchToolBuilder {
                         "query": {
                             "type": "string",
                             "description": "The search query to run on the search engine."
+                        },
+                        "time_range": {
+                            "type": "string",
+                            "enum": ["day", "week", "month", "year"],
+                            "description": "Only return results from this time range. Use it when the user asks for recent information."
+                        },
+                        "categories": {
+                            "type": "string",
+                            "description": "Comma separated search categories, e.g. 'news' or 'science'."
                         }
                     },
                     "required": ["query"]
@@ -44,16 +73,178 @@ pub struct WebSearchTool;
 impl WebSearchTool {
     pub async fn call_tool_function(function_call: &FunctionCall) -> ToolCallResult {
         let query = function_call.arguments["query"].as_str().unwrap();
-        let searxng_client = SearxngClient::new(env::var(ENV_SEARXNG_BASE_URL).unwrap());
-        let query_result = searxng_client.search(query).await;
+        let filters = SearchFilters {
+            time_range: function_call.arguments["time_range"]
+                .as_str()
+                .map(|s| s.to_string()),
+            categories: function_call.arguments["categories"]
+                .as_str()
+                .map(|s| s.to_string()),
+        };
+        let searxng_client = SearxngClient::new(&env::var(ENV_SEARXNG_BASE_URL).unwrap());
+        let query_result = search_with_cache(&searxng_client, &SEARCH_CACHE, query, &filters).await;
+
+        let max_chars = env::var(ENV_SEARCH_MAX_CHARS)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(DEFAULT_SEARCH_MAX_CHARS);
+
+        // The model is told the search failed rather than the whole request failing
+        let content = match query_result {
+            Ok(results) => serde_json::to_value(trim_results(results, max_chars)).unwrap(),
+            Err(e) => serde_json::Value::String(e.to_string()),
+        };
 
         ToolCallResult {
-            content: serde_json::to_value(&query_result.unwrap()).unwrap(),
+            tool_call_id: None,
+            content,
             function_call: function_call.clone(),
         }
     }
 }
 
+/// Run a search, reusing the cached results of an identical earlier search
+pub async fn search_with_cache(
+    backend: &impl SearchBackend,
+    cache: &Mutex<SearchCache>,
+    query: &str,
+    filters: &SearchFilters,
+) -> Result<Vec<SearchResult>, ToolError> {
+    let key = SearchCache::key(query, filters);
+
+    if let Some(results) = cache.lock().unwrap().get(&key) {
+        println!(
+            "{}Reusing cached search results for '{query}'",
+            icon("♻️  ")
+        );
+        return Ok(results);
+    }
+
+    let results = backend.search(query, filters).await?;
+    cache.lock().unwrap().insert(key, results.clone());
+
+    Ok(results)
+}
+
+/// Keep the results within `max_chars` so they don't crowd out the conversation.
+/// Duplicate URLs are dropped, then each result gets an equal share of the text left
+/// after the titles and URLs, which are kept whole.
+pub fn trim_results(results: Vec<SearchResult>, max_chars: usize) -> Vec<SearchResult> {
+    let mut seen_urls = Vec::new();
+    let mut results: Vec<SearchResult> = results
+        .into_iter()
+        .filter(|result| {
+            let url = normalize_url(&result.url);
+            let is_new = !seen_urls.contains(&url);
+            seen_urls.push(url);
+            is_new
+        })
+        .collect();
+
+    let fixed_length =
+        |result: &SearchResult| result.title.chars().count() + result.url.chars().count();
+    while results.iter().map(fixed_length).sum::<usize>() > max_chars {
+        results.pop();
+    }
+
+    if results.is_empty() {
+        return results;
+    }
+
+    let content_budget = max_chars - results.iter().map(fixed_length).sum::<usize>();
+    let content_chars = content_budget / results.len();
+    for result in &mut results {
+        result.content = truncate(&result.content, content_chars);
+    }
+
+    results
+}
+
+/// Same page, whatever the scheme, "www.", trailing slash or fragment
+fn normalize_url(url: &str) -> String {
+    let url = url.split('#').next().unwrap_or(url);
+    let url = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url);
+    let url = url.strip_prefix("www.").unwrap_or(url);
+
+    url.trim_end_matches('/').to_lowercase()
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+
+    // Leave room for the ellipsis
+    let mut truncated: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    if max_chars > 0 {
+        truncated.push('…');
+    }
+    truncated
+}
+
+/// Small LRU cache of search results keyed on the normalized query
+pub struct SearchCache {
+    capacity: usize,
+    entries: VecDeque<(String, Vec<SearchResult>)>,
+}
+
+impl SearchCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::new(),
+        }
+    }
+
+    fn key(query: &str, filters: &SearchFilters) -> String {
+        let normalized_query = query
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase();
+
+        format!(
+            "{}|{}|{}",
+            normalized_query,
+            filters.time_range.as_deref().unwrap_or(""),
+            filters.categories.as_deref().unwrap_or("")
+        )
+    }
+
+    fn get(&mut self, key: &str) -> Option<Vec<SearchResult>> {
+        let position = self.entries.iter().position(|(k, _)| k == key)?;
+
+        // Move the entry to the back as the most recently used
+        let entry = self.entries.remove(position)?;
+        let results = entry.1.clone();
+        self.entries.push_back(entry);
+
+        Some(results)
+    }
+
+    fn insert(&mut self, key: String, results: Vec<SearchResult>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.entries.retain(|(k, _)| k != &key);
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((key, results));
+    }
+}
+
+/// Optional filters forwarded to SearXNG
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilters {
+    pub time_range: Option<String>,
+    pub categories: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchResult {
     pub title: String,
@@ -79,35 +270,36 @@ struct SearxngResult {
 }
 
 pub struct SearxngClient {
-    base_url: String,
+    /// Instances tried in order, until one of them answers
+    base_urls: Vec<String>,
     client: Client,
 }
 
 impl SearxngClient {
-    pub fn new(base_url: String) -> Self {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(10))
-            .build()
-            .expect("Failed to create HTTP client");
+    /// `base_urls` is a comma separated list of SearXNG instances
+    pub fn new(base_urls: &str) -> Self {
+        let client =
+            http_client::build_client(SEARCH_TIMEOUT).expect("Failed to create HTTP client");
+        let base_urls = base_urls
+            .split(',')
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty())
+            .collect();
 
-        Self { base_url, client }
+        Self { base_urls, client }
     }
 
-    pub async fn search(&self, query: &str) -> Result<Vec<SearchResult>, ToolError> {
-        let mut params = HashMap::new();
-        params.insert("q", query);
-        params.insert("format", "json");
-        params.insert("engines", "google,bing,duckduckgo");
-
-        let url = format!("{}/search", self.base_url);
-
-        println!("🔍 Searching with SearXNG: '{query}'");
+    async fn search_instance(
+        &self,
+        base_url: &str,
+        params: &HashMap<&str, &str>,
+    ) -> Result<Vec<SearchResult>, ToolError> {
+        let url = format!("{}/search", base_url);
 
         let response = self
             .client
             .get(&url)
-            .query(&params)
-            .header("User-Agent", "ash-sh-rust/1.0.0")
+            .query(params)
             .send()
             .await
             .map_err(|e| ToolError::ApiError(e.to_string()))?;
@@ -124,9 +316,12 @@ impl SearxngClient {
             )));
         }
 
-        let searxng_response: SearxngResponse = response.json().await.unwrap();
+        let searxng_response: SearxngResponse = response
+            .json()
+            .await
+            .map_err(|e| ToolError::ApiError(e.to_string()))?;
 
-        let results: Vec<SearchResult> = searxng_response
+        Ok(searxng_response
             .results
             .into_iter()
             .take(5) // Limit to top 5 results
@@ -136,10 +331,214 @@ impl SearxngClient {
                 content: r.content,
                 img_src: r.img_src,
             })
-            .collect();
+            .collect())
+    }
+}
+
+#[async_trait]
+pub trait SearchBackend {
+    async fn search(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+    ) -> Result<Vec<SearchResult>, ToolError>;
+}
+
+#[async_trait]
+impl SearchBackend for SearxngClient {
+    async fn search(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+    ) -> Result<Vec<SearchResult>, ToolError> {
+        let mut params = HashMap::new();
+        params.insert("q", query);
+        params.insert("format", "json");
+
+        // Let SearXNG pick the engines matching the requested categories
+        match &filters.categories {
+            Some(categories) => params.insert("categories", categories),
+            None => params.insert("engines", "google,bing,duckduckgo"),
+        };
 
-        println!("✅ Processing {} search results", results.len());
-        println!();
-        Ok(results)
+        if let Some(time_range) = &filters.time_range {
+            params.insert("time_range", time_range);
+        }
+
+        println!("{}Searching with SearXNG: '{query}'", icon("🔍 "));
+
+        // An instance that is down or times out shouldn't fail the search
+        let mut errors = Vec::new();
+        for base_url in &self.base_urls {
+            match self.search_instance(base_url, &params).await {
+                Ok(results) => {
+                    if is_debug_enabled() {
+                        eprintln!("{}Searched with SearXNG instance {}", icon("🐛 "), base_url);
+                    }
+                    println!("{}Processing {} search results", icon("✅ "), results.len());
+                    println!();
+                    return Ok(results);
+                }
+                Err(e) => errors.push(format!("{}: {}", base_url, e)),
+            }
+        }
+
+        Err(ToolError::ApiError(format!(
+            "No SearXNG instance could be reached. {}",
+            errors.join("; ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingBackend {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl SearchBackend for CountingBackend {
+        async fn search(
+            &self,
+            query: &str,
+            _filters: &SearchFilters,
+        ) -> Result<Vec<SearchResult>, ToolError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![SearchResult {
+                title: query.to_string(),
+                url: "https://example.com".to_string(),
+                content: String::new(),
+                img_src: None,
+            }])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_repeated_query_uses_cache() {
+        let backend = CountingBackend {
+            calls: AtomicUsize::new(0),
+        };
+        let cache = Mutex::new(SearchCache::new(2));
+        let filters = SearchFilters::default();
+
+        search_with_cache(&backend, &cache, "rust news", &filters)
+            .await
+            .unwrap();
+        let results = search_with_cache(&backend, &cache, "  Rust   News ", &filters)
+            .await
+            .unwrap();
+
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(results[0].title, "rust news");
+    }
+
+    #[tokio::test]
+    async fn test_cache_evicts_least_recently_used() {
+        let backend = CountingBackend {
+            calls: AtomicUsize::new(0),
+        };
+        let cache = Mutex::new(SearchCache::new(2));
+        let filters = SearchFilters::default();
+
+        for query in ["a", "b", "a", "c", "a"] {
+            search_with_cache(&backend, &cache, query, &filters)
+                .await
+                .unwrap();
+        }
+
+        // "b" was evicted when "c" was added, "a" stayed cached
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_trim_results_respects_cap() {
+        let result = |url: &str, content_length: usize| SearchResult {
+            title: "Rust 1.80 released".to_string(),
+            url: url.to_string(),
+            content: "é".repeat(content_length),
+            img_src: None,
+        };
+        let results = vec![
+            result(
+                "https://blog.rust-lang.org/2024/07/25/Rust-1.80.0.html",
+                5000,
+            ),
+            result(
+                "http://www.blog.rust-lang.org/2024/07/25/Rust-1.80.0.html/",
+                5000,
+            ),
+            result("https://news.ycombinator.com/item?id=1", 10),
+            result("https://www.reddit.com/r/rust/1", 5000),
+        ];
+
+        let trimmed = trim_results(results, 500);
+        let total: usize = trimmed
+            .iter()
+            .map(|r| r.title.chars().count() + r.url.chars().count() + r.content.chars().count())
+            .sum();
+
+        assert_eq!(trimmed.len(), 3);
+        assert!(total <= 500);
+        assert_eq!(
+            trimmed[0].url,
+            "https://blog.rust-lang.org/2024/07/25/Rust-1.80.0.html"
+        );
+        assert!(trimmed[0].content.ends_with('…'));
+        assert_eq!(trimmed[1].content, "é".repeat(10));
+
+        // Titles and URLs are never cut, results that don't fit are dropped
+        let trimmed = trim_results(trimmed, 80);
+        assert_eq!(trimmed.len(), 1);
+        assert_eq!(trimmed[0].title, "Rust 1.80 released");
+        assert_eq!(
+            trimmed[0].content.chars().count(),
+            80 - trimmed[0].title.len() - trimmed[0].url.len()
+        );
+    }
+
+    /// Serve a single HTTP response on a local port
+    async fn serve_once(status: &'static str, body: &'static str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4096];
+            let _ = socket.read(&mut request).await;
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        url
+    }
+
+    #[tokio::test]
+    async fn test_failover_to_next_instance() {
+        let down = serve_once("502 Bad Gateway", "").await;
+        let up = serve_once(
+            "200 OK",
+            r#"{"query":"rust","results":[{"title":"Rust","url":"https://www.rust-lang.org","content":"A language"}]}"#,
+        )
+        .await;
+
+        let client = SearxngClient::new(&format!("{}, {}/", down, up));
+        assert_eq!(client.base_urls, [down, up]);
+
+        let results = client
+            .search("rust", &SearchFilters::default())
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].url, "https://www.rust-lang.org");
     }
 }
@@ -0,0 +1,158 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    theme::icon,
+    tools::{FunctionCall, FunctionDef, Tool, ToolCallResult},
+    user_system_info::UserSystemInfo,
+    ENV_HISTORY_LINES,
+};
+
+const DEFAULT_HISTORY_LINES: usize = 20;
+const REDACTED_LINE: &str = "[redacted: this command may contain a secret]";
+
+/// Lines that look like they hold credentials are never sent to the model
+static SECRET_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)(passw(or)?d|secret|token|api[_-]?key|authorization|bearer|credential|private[_-]?key|sk-[a-z0-9_-]{16,}|gh[pousr]_[a-z0-9]{20,}|AKIA[0-9A-Z]{16})",
+    )
+    .unwrap()
+});
+
+pub struct ShellHistoryToolBuilder;
+
+impl ShellHistoryToolBuilder {
+    pub fn tool_available() -> bool {
+        get_history_file().is_some()
+    }
+
+    pub fn create_tool() -> Tool {
+        Tool {
+            tool_type: "function".to_string(),
+            function: FunctionDef {
+                name: "get_shell_history".to_string(),
+                description: "Get the commands the user ran most recently in their shell, when the user refers to a command they ran earlier".to_string(),
+                parameters: serde_json::json!({
+                    "type": "object",
+                    "properties": {}
+                }),
+            },
+        }
+    }
+}
+
+pub struct ShellHistoryTool;
+
+impl ShellHistoryTool {
+    pub fn call_tool_function(function_call: &FunctionCall) -> ToolCallResult {
+        let line_count = env::var(ENV_HISTORY_LINES)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(DEFAULT_HISTORY_LINES);
+
+        println!(
+            "{}Reading your last {} shell commands",
+            icon("📜 "),
+            line_count
+        );
+
+        let content = match get_history_file().and_then(|file| fs::read(file).ok()) {
+            Some(bytes) => {
+                let history = String::from_utf8_lossy(&bytes);
+                serde_json::Value::String(get_recent_commands(&history, line_count).join("\n"))
+            }
+            None => serde_json::Value::String("No shell history found.".to_string()),
+        };
+
+        ToolCallResult {
+            tool_call_id: None,
+            function_call: function_call.clone(),
+            content,
+        }
+    }
+}
+
+/// Find the history file of the user's shell, honoring HISTFILE when it is exported
+fn get_history_file() -> Option<PathBuf> {
+    if let Some(file) = env::var("HISTFILE").ok().map(PathBuf::from) {
+        if file.is_file() {
+            return Some(file);
+        }
+    }
+
+    let home = PathBuf::from(env::var("HOME").ok()?);
+    let shell = UserSystemInfo::new().shell.to_lowercase();
+
+    let file = if shell.contains("zsh") {
+        home.join(".zsh_history")
+    } else if shell.contains("fish") {
+        let data_dir = env::var("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| Path::new(&home).join(".local").join("share"));
+        data_dir.join("fish").join("fish_history")
+    } else {
+        home.join(".bash_history")
+    };
+
+    file.is_file().then_some(file)
+}
+
+/// Last `count` commands of a bash, zsh or fish history file, oldest first
+fn get_recent_commands(history: &str, count: usize) -> Vec<String> {
+    let commands: Vec<String> = history
+        .lines()
+        .filter_map(parse_history_line)
+        .map(|command| match SECRET_PATTERN.is_match(&command) {
+            true => REDACTED_LINE.to_string(),
+            false => command,
+        })
+        .collect();
+
+    commands[commands.len().saturating_sub(count)..].to_vec()
+}
+
+fn parse_history_line(line: &str) -> Option<String> {
+    // zsh extended history: ": <timestamp>:<duration>;<command>"
+    let command = match line.strip_prefix(": ") {
+        Some(extended) => extended
+            .split_once(';')
+            .map_or(line, |(_, command)| command),
+        None => line,
+    };
+
+    // fish history is YAML-like, only the "- cmd:" lines hold commands
+    if line.starts_with("  ") {
+        return None;
+    }
+    let command = command.strip_prefix("- cmd: ").unwrap_or(command);
+
+    // bash writes timestamps as comments when HISTTIMEFORMAT is set
+    if command.trim().is_empty() || command.starts_with('#') {
+        return None;
+    }
+
+    Some(command.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_recent_commands() {
+        let zsh_history = ": 1700000000:0;ls -la\n: 1700000001:0;export GITHUB_TOKEN=abc\n: 1700000002:0;cargo build\n";
+        assert_eq!(
+            get_recent_commands(zsh_history, 2),
+            vec![REDACTED_LINE.to_string(), "cargo build".to_string()]
+        );
+
+        let fish_history = "- cmd: git status\n  when: 1700000000\n- cmd: mysql -p hunter2 --password=x\n  when: 1700000001\n";
+        assert_eq!(
+            get_recent_commands(fish_history, 20),
+            vec!["git status".to_string(), REDACTED_LINE.to_string()]
+        );
+    }
+}
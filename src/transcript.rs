@@ -0,0 +1,177 @@
+//! Record of the last conversation, so it can be exported with `ask-sh --export-last`
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::llm::Message;
+
+const LAST_CONVERSATION_FILE: &str = "last-conversation.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Transcript {
+    entries: Vec<TranscriptEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TranscriptEntry {
+    timestamp: DateTime<Local>,
+    message: Message,
+}
+
+impl Transcript {
+    pub fn push(&mut self, message: Message) {
+        self.entries.push(TranscriptEntry {
+            timestamp: Local::now(),
+            message,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Keep the conversation in the cache directory, replacing the previous one
+    pub fn save_as_last(&self) -> io::Result<()> {
+        let file = get_last_conversation_file()?;
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let json = serde_json::to_string(self).map_err(io::Error::other)?;
+        fs::write(file, json)
+    }
+
+    pub fn load_last() -> io::Result<Self> {
+        let json = fs::read_to_string(get_last_conversation_file()?)?;
+        serde_json::from_str(&json).map_err(io::Error::other)
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = String::from("# ask.sh conversation\n");
+
+        for entry in &self.entries {
+            let message = &entry.message;
+            let timestamp = entry.timestamp.format("%Y-%m-%d %H:%M:%S");
+
+            // Tool calls are shown along with their results in the tool message
+            if message.content.trim().is_empty() {
+                continue;
+            }
+
+            match message.role.as_str() {
+                "tool" => {
+                    markdown.push_str(&format!("\n## Tool ({})\n", timestamp));
+                    markdown.push_str(&render_tool_results(&message.content));
+                }
+                role => {
+                    markdown.push_str(&format!("\n## {} ({})\n\n", capitalize(role), timestamp));
+                    markdown.push_str(message.content.trim_end());
+                    markdown.push('\n');
+                }
+            }
+        }
+
+        markdown
+    }
+}
+
+/// Write the last conversation to `path` as Markdown
+pub fn export_last(path: &Path) -> io::Result<()> {
+    let transcript = Transcript::load_last()?;
+    fs::write(path, transcript.to_markdown())
+}
+
+fn get_last_conversation_file() -> io::Result<PathBuf> {
+    crate::get_cache_dir()
+        .map(|dir| dir.join(LAST_CONVERSATION_FILE))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No cache directory found"))
+}
+
+/// Tool messages hold the JSON list of tool call results
+fn render_tool_results(content: &str) -> String {
+    let results = match serde_json::from_str::<Vec<serde_json::Value>>(content) {
+        Ok(results) => results,
+        Err(_) => return format!("\n{}", fenced(content, "text")),
+    };
+
+    let mut markdown = String::new();
+
+    for result in results {
+        let function_call = &result["function_call"];
+        let output = match &result["content"] {
+            serde_json::Value::String(text) => text.clone(),
+            value => serde_json::to_string_pretty(value).unwrap_or_default(),
+        };
+
+        match function_call["arguments"]["command"].as_str() {
+            Some(command) => {
+                markdown.push_str(&format!("\n{}", fenced(command, "sh")));
+                markdown.push_str(&format!("\n{}", fenced(&output, "text")));
+            }
+            None => {
+                let name = function_call["name"].as_str().unwrap_or("tool");
+                let arguments =
+                    serde_json::to_string_pretty(&function_call["arguments"]).unwrap_or_default();
+                markdown.push_str(&format!("\n`{}`\n\n{}", name, fenced(&arguments, "json")));
+                markdown.push_str(&format!("\n{}", fenced(&output, "json")));
+            }
+        }
+    }
+
+    markdown
+}
+
+/// Wrap text in a code fence longer than any backtick run inside it
+fn fenced(text: &str, language: &str) -> String {
+    let longest_run = text
+        .split(|c| c != '`')
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+
+    format!("{}{}\n{}\n{}\n", fence, language, text.trim_end(), fence)
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_markdown() {
+        let mut transcript = Transcript::default();
+        transcript.push(Message {
+            role: "user".to_string(),
+            content: "list files".to_string(),
+            ..Default::default()
+        });
+        transcript.push(Message {
+            role: "tool".to_string(),
+            content: r#"[{"function_call":{"name":"execute_command","arguments":{"command":"ls"}},"content":"a.txt\n```"}]"#.to_string(),
+            ..Default::default()
+        });
+        transcript.push(Message {
+            role: "assistant".to_string(),
+            content: "There is one file.".to_string(),
+            ..Default::default()
+        });
+
+        let markdown = transcript.to_markdown();
+        assert!(markdown.contains("## User ("));
+        assert!(markdown.contains("```sh\nls\n```"));
+        assert!(markdown.contains("````text\na.txt\n```\n````"));
+        assert!(markdown.contains("## Assistant (") && markdown.contains("There is one file."));
+    }
+}
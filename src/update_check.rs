@@ -0,0 +1,119 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::http_client;
+
+const CRATES_IO_URL: &str = "https://crates.io/api/v1/crates/ask-sh";
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Deserialize)]
+struct CratesIoResponse {
+    #[serde(rename = "crate")]
+    krate: CrateInfo,
+}
+
+#[derive(Debug, Deserialize)]
+struct CrateInfo {
+    max_stable_version: String,
+}
+
+/// Returns the latest published version when it is newer than the running one.
+/// The crates.io lookup is cached for 24 hours, and any failure is treated as "no update".
+pub async fn check_for_update() -> Option<String> {
+    let cache_file = get_cache_file()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+
+    let latest_version = match read_cache(&cache_file, now) {
+        Some(version) => version,
+        None => {
+            let version = fetch_latest_version().await?;
+            write_cache(&cache_file, now, &version);
+            version
+        }
+    };
+
+    is_newer_version(&latest_version, env!("CARGO_PKG_VERSION")).then_some(latest_version)
+}
+
+async fn fetch_latest_version() -> Option<String> {
+    let client = http_client::build_client(REQUEST_TIMEOUT).ok()?;
+
+    let response: CratesIoResponse = client
+        .get(CRATES_IO_URL)
+        .send()
+        .await
+        .ok()?
+        .json()
+        .await
+        .ok()?;
+
+    Some(response.krate.max_stable_version)
+}
+
+fn get_cache_file() -> Option<PathBuf> {
+    Some(crate::get_cache_dir()?.join("update-check"))
+}
+
+/// The cache file holds the check timestamp and the latest version on two lines
+fn read_cache(cache_file: &Path, now: Duration) -> Option<String> {
+    let content = fs::read_to_string(cache_file).ok()?;
+    let mut lines = content.lines();
+
+    let checked_at: u64 = lines.next()?.parse().ok()?;
+    let version = lines.next()?.trim().to_string();
+
+    if now.as_secs().saturating_sub(checked_at) > CHECK_INTERVAL.as_secs() || version.is_empty() {
+        return None;
+    }
+
+    Some(version)
+}
+
+fn write_cache(cache_file: &Path, now: Duration, version: &str) {
+    if let Some(parent) = cache_file.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let _ = fs::write(cache_file, format!("{}\n{}\n", now.as_secs(), version));
+}
+
+fn is_newer_version(latest: &str, current: &str) -> bool {
+    let parse = |version: &str| -> Vec<u64> {
+        version
+            .split('.')
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    };
+
+    parse(latest) > parse(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_newer_version() {
+        assert!(is_newer_version("1.0.10", "1.0.9"));
+        assert!(is_newer_version("2.0.0", "1.9.9"));
+        assert!(!is_newer_version("1.0.0", "1.0.0"));
+        assert!(!is_newer_version("0.9.0", "1.0.0"));
+    }
+
+    #[test]
+    fn test_cache_expires_after_a_day() {
+        let cache_file = std::env::temp_dir()
+            .join(format!("ask-sh-test-{}", uuid::Uuid::new_v4()))
+            .join("update-check");
+        let now = Duration::from_secs(1_000_000);
+
+        write_cache(&cache_file, now, "1.2.3");
+        assert_eq!(read_cache(&cache_file, now), Some("1.2.3".to_string()));
+        assert_eq!(read_cache(&cache_file, now + CHECK_INTERVAL * 2), None);
+
+        let _ = fs::remove_dir_all(cache_file.parent().unwrap());
+    }
+}
@@ -0,0 +1,212 @@
+//! What the user wants the model to know about them, like their aliases, preferred
+//! tools or conventions, added to the system prompt. A project can add its own
+//! conventions in a `.ask-sh.md` file.
+
+use once_cell::sync::Lazy;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{get_config_dir, theme::icon, ENV_CONTEXT_FILE};
+
+const CONTEXT_FILE: &str = "context.md";
+const PROJECT_CONTEXT_FILE: &str = ".ask-sh.md";
+
+/// Read once, however many times the system prompt is rendered
+static USER_CONTEXT: Lazy<Option<String>> =
+    Lazy::new(|| combine_contexts(read_user_context(), read_project_context()));
+
+pub fn get_user_context() -> Option<String> {
+    USER_CONTEXT.clone()
+}
+
+/// ASK_SH_CONTEXT_FILE, or ~/.config/ask-sh/context.md when it exists
+fn read_user_context() -> Option<String> {
+    let (path, is_configured) = match env::var(ENV_CONTEXT_FILE) {
+        Ok(path) if !path.trim().is_empty() => (expand_home(&path), true),
+        _ => (get_config_dir()?.join(CONTEXT_FILE), false),
+    };
+
+    match fs::read_to_string(&path) {
+        Ok(context) => Some(context.trim().to_string()).filter(|context| !context.is_empty()),
+        Err(e) => {
+            // Only a file the user pointed to is expected to be there
+            if is_configured {
+                eprintln!(
+                    "{}Could not read the context file {}: {}",
+                    icon("⚠️  "),
+                    path.display(),
+                    e
+                );
+            }
+            None
+        }
+    }
+}
+
+/// The `.ask-sh.md` of the current directory or of one of its parents
+fn read_project_context() -> Option<(PathBuf, String)> {
+    let current_dir = env::current_dir().ok()?;
+    let home = env::var("HOME").ok().map(PathBuf::from);
+
+    let path = find_project_context(&current_dir, home.as_deref())?;
+    let context = fs::read_to_string(&path).ok()?.trim().to_string();
+
+    Some((path, context)).filter(|(_, context)| !context.is_empty())
+}
+
+/// Look up from `start`, without going past the root of the git repository or the home directory.
+/// As the file drives the commands the model runs, directories like `/tmp` that other users
+/// can write to are never searched, nor any file the user doesn't own or others could change.
+fn find_project_context(start: &Path, home: Option<&Path>) -> Option<PathBuf> {
+    for dir in start.ancestors() {
+        // Neither the filesystem root nor its children, like `/tmp` or `/home`
+        if dir.parent().and_then(Path::parent).is_none() || !is_owned_by_user(dir) {
+            break;
+        }
+
+        let path = dir.join(PROJECT_CONTEXT_FILE);
+        if path.is_file() {
+            return Some(path).filter(|path| is_trusted(path));
+        }
+
+        if dir.join(".git").exists() || Some(dir) == home {
+            break;
+        }
+    }
+
+    None
+}
+
+#[cfg(unix)]
+fn is_owned_by_user(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    // SAFETY: geteuid can't fail and has no side effects
+    let uid = unsafe { libc::geteuid() };
+    fs::metadata(path).is_ok_and(|metadata| metadata.uid() == uid)
+}
+
+#[cfg(not(unix))]
+fn is_owned_by_user(_path: &Path) -> bool {
+    true
+}
+
+/// Owned by the user, and not writable by anyone else
+fn is_trusted(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let writable_by_others =
+            fs::metadata(path).map_or(true, |metadata| metadata.permissions().mode() & 0o022 != 0);
+        if writable_by_others {
+            return false;
+        }
+    }
+
+    is_owned_by_user(path)
+}
+
+/// The user's own context is used as is, unless there is a project context to tell it apart from
+fn combine_contexts(user: Option<String>, project: Option<(PathBuf, String)>) -> Option<String> {
+    let Some((path, project)) = project else {
+        return user;
+    };
+
+    let project = format!(
+        "### Project conventions ({})\n\n{}",
+        path.display(),
+        project
+    );
+    match user {
+        Some(user) => Some(format!(
+            "### General preferences\n\n{}\n\n{}",
+            user, project
+        )),
+        None => Some(project),
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var("HOME")) {
+        (Some(relative), Ok(home)) => PathBuf::from(home).join(relative),
+        _ => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_project_context() {
+        let root = env::temp_dir().join(format!("ask-sh-context-{}", uuid::Uuid::new_v4()));
+        let repo = root.join("repo");
+        let nested = repo.join("src/bin");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir(repo.join(".git")).unwrap();
+        fs::write(root.join(PROJECT_CONTEXT_FILE), "outside").unwrap();
+
+        // The search stops at the git root
+        assert_eq!(find_project_context(&nested, None), None);
+
+        fs::write(
+            repo.join(PROJECT_CONTEXT_FILE),
+            "Run tests with `just test`",
+        )
+        .unwrap();
+        assert_eq!(
+            find_project_context(&nested, None),
+            Some(repo.join(PROJECT_CONTEXT_FILE))
+        );
+
+        // and at the home directory
+        fs::remove_dir(repo.join(".git")).unwrap();
+        fs::remove_file(repo.join(PROJECT_CONTEXT_FILE)).unwrap();
+        assert_eq!(find_project_context(&nested, Some(&repo)), None);
+        assert_eq!(
+            find_project_context(&nested, None),
+            Some(root.join(PROJECT_CONTEXT_FILE))
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_project_context_without_git_or_home() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = env::temp_dir().join(format!("ask-sh-context-{}", uuid::Uuid::new_v4()));
+        let nested = root.join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+        let context = root.join(PROJECT_CONTEXT_FILE);
+        fs::write(&context, "Use `just`").unwrap();
+        fs::set_permissions(&context, fs::Permissions::from_mode(0o644)).unwrap();
+
+        assert_eq!(find_project_context(&nested, None), Some(context.clone()));
+
+        // Anyone could have written it
+        fs::set_permissions(&context, fs::Permissions::from_mode(0o666)).unwrap();
+        assert_eq!(find_project_context(&nested, None), None);
+
+        // `/tmp/.ask-sh.md` is never read, whoever wrote it
+        assert_eq!(find_project_context(Path::new("/tmp"), None), None);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_combine_contexts() {
+        let user = Some("Prefer `rg`".to_string());
+        let project = Some((PathBuf::from("/repo/.ask-sh.md"), "Use `just`".to_string()));
+
+        assert_eq!(combine_contexts(user.clone(), None), user);
+        assert_eq!(
+            combine_contexts(user, project).unwrap(),
+            "### General preferences\n\nPrefer `rg`\n\n### Project conventions (/repo/.ask-sh.md)\n\nUse `just`"
+        );
+    }
+}
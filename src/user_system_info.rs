@@ -1,12 +1,46 @@
-use std::env::{
-    self,
-    consts::{ARCH, OS},
+use std::{
+    env::{
+        self,
+        consts::{ARCH, OS},
+    },
+    fs,
 };
 
+use crate::ENV_LANG;
+
+/// Languages by their ISO 639-1 code, as found at the start of a locale like ja_JP.UTF-8
+const LANGUAGES: &[(&str, &str)] = &[
+    ("ar", "Arabic"),
+    ("cs", "Czech"),
+    ("de", "German"),
+    ("es", "Spanish"),
+    ("fr", "French"),
+    ("hi", "Hindi"),
+    ("id", "Indonesian"),
+    ("it", "Italian"),
+    ("ja", "Japanese"),
+    ("ko", "Korean"),
+    ("nl", "Dutch"),
+    ("pl", "Polish"),
+    ("pt", "Portuguese"),
+    ("ru", "Russian"),
+    ("sv", "Swedish"),
+    ("th", "Thai"),
+    ("tr", "Turkish"),
+    ("uk", "Ukrainian"),
+    ("vi", "Vietnamese"),
+    ("zh", "Chinese"),
+];
+
 pub struct UserSystemInfo {
     pub arch: String,
     pub os: String,
     pub shell: String,
+    pub distro: Option<String>,
+    /// The language to answer in, None for English
+    pub language: Option<String>,
+    /// Set with ASK_SH_LANG, rather than only the locale's
+    pub language_forced: bool,
 }
 
 impl UserSystemInfo {
@@ -15,25 +49,125 @@ impl UserSystemInfo {
             arch: ARCH.to_string(),
             os: OS.to_string(),
             shell: get_system_shell(),
+            distro: get_linux_distro(),
+            language: get_user_language(),
+            language_forced: env::var(ENV_LANG).is_ok(),
         }
     }
 }
 
+/// ASK_SH_LANG, e.g. `ja` or `Japanese`, or else the language of the LANG locale
+pub fn get_user_language() -> Option<String> {
+    match env::var(ENV_LANG) {
+        Ok(language) => parse_language(&language, true),
+        Err(_) => parse_language(&env::var("LANG").ok()?, false),
+    }
+}
+
+/// The language's name, None for English and the C locale. A name that isn't a known
+/// language code is kept as is when the user set it for ask.sh.
+fn parse_language(value: &str, keep_unknown: bool) -> Option<String> {
+    let value = value.trim();
+    let code = value
+        .split(['_', '-', '.', '@'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+
+    if matches!(code.as_str(), "" | "c" | "posix" | "en" | "english") {
+        return None;
+    }
+
+    match LANGUAGES.iter().find(|(known, _)| *known == code) {
+        Some((_, name)) => Some(name.to_string()),
+        None if keep_unknown => Some(value.to_string()),
+        None => None,
+    }
+}
+
+fn get_linux_distro() -> Option<String> {
+    if OS != "linux" {
+        return None;
+    }
+
+    let os_release = fs::read_to_string("/etc/os-release").ok()?;
+    parse_os_release(&os_release)
+}
+
+/// Reads the distro name from the contents of /etc/os-release
+fn parse_os_release(content: &str) -> Option<String> {
+    let get_value = |key: &str| {
+        content
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .map(|value| value.trim().trim_matches('"').to_string())
+            .filter(|value| !value.is_empty())
+    };
+
+    get_value("PRETTY_NAME").or_else(|| get_value("NAME"))
+}
+
 fn get_system_shell() -> String {
     // get user's shell name
-    // when env::var("SHELL") is not set, use BASH_VERSION or ZSH_VERSION to guess the shell
-    let shell = match env::var("SHELL") {
+    // when env::var("SHELL") is not set, use BASH_VERSION, ZSH_VERSION or FISH_VERSION to guess the shell
+
+    match env::var("SHELL") {
         Ok(value) => value,
         Err(_e) => {
             if env::var("BASH_VERSION").is_ok() {
                 "Bash".to_string()
             } else if env::var("ZSH_VERSION").is_ok() {
                 "zsh".to_string()
+            } else if env::var("FISH_VERSION").is_ok() {
+                "fish".to_string()
+            } else if cfg!(windows) {
+                // cmd.exe sets PROMPT for its children, PowerShell doesn't
+                if env::var("PROMPT").is_ok() {
+                    "cmd".to_string()
+                } else {
+                    "PowerShell".to_string()
+                }
             } else {
                 "Unknown".to_string()
             }
         }
-    };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_os_release() {
+        let ubuntu = "NAME=\"Ubuntu\"\nVERSION_ID=\"22.04\"\nPRETTY_NAME=\"Ubuntu 22.04.3 LTS\"\n";
+        assert_eq!(
+            parse_os_release(ubuntu),
+            Some("Ubuntu 22.04.3 LTS".to_string())
+        );
+
+        let name_only = "NAME=Alpine\nID=alpine\n";
+        assert_eq!(parse_os_release(name_only), Some("Alpine".to_string()));
 
-    shell
+        assert_eq!(parse_os_release("ID=unknown\n"), None);
+    }
+
+    #[test]
+    fn test_parse_language() {
+        assert_eq!(
+            parse_language("ja_JP.UTF-8", false),
+            Some("Japanese".to_string())
+        );
+        assert_eq!(
+            parse_language("pt-BR", true),
+            Some("Portuguese".to_string())
+        );
+        assert_eq!(parse_language("en_US.UTF-8", false), None);
+        assert_eq!(parse_language("C.UTF-8", false), None);
+        assert_eq!(parse_language("eo", false), None);
+        assert_eq!(
+            parse_language("Esperanto", true),
+            Some("Esperanto".to_string())
+        );
+    }
 }